@@ -0,0 +1,93 @@
+//! Time per packet on the way from the bytes read off a port to the fields of a row
+//!
+//! `cargo bench` runs it, printing the framing and the parsing separately.
+
+use charter::device::{Device, DeviceKind};
+use charter::parse::{self, Encoding, ExtraFields, ParseOptions};
+use charter::queue::{self, WhenFull};
+use charter::radio::RadioArgs;
+use charter::receiver::{Line, Link, Reader, Receive};
+use charter::serial::LineEnding;
+use clap::{Args, FromArgMatches};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+const PACKETS: usize = 100_000;
+/// About what a read off a serial port at 115200 baud returns
+const CHUNK: usize = 64;
+
+struct Discard;
+
+impl Link for Discard {}
+
+fn main() {
+    let device = DeviceKind::Rn2483.profile();
+    let command = RadioArgs::augment_args(clap::Command::new("bench"));
+    let radio = RadioArgs::from_arg_matches(&command.get_matches_from(["bench"])).unwrap();
+    let mut bytes = Vec::new();
+    for packet in 0..PACKETS {
+        let payload = format!("{packet} 21.5 48.2 1013.25 -3 0 1 2 3 4 5");
+        bytes.extend_from_slice(b"radio_rx  ");
+        bytes.extend_from_slice(hex::encode(payload).as_bytes());
+        bytes.extend_from_slice(b"\r\n");
+    }
+
+    let started = Instant::now();
+    let lines = frame(device, &radio, &bytes);
+    report("framing", started.elapsed());
+    assert_eq!(lines.len(), PACKETS);
+
+    let options = ParseOptions {
+        fields: 11,
+        delimiter: None,
+        checksum: None,
+        allow_short: false,
+        extra_fields: ExtraFields::Error,
+        schema: None,
+    };
+    let resyncs = AtomicU64::new(0);
+    let mut decoded = Vec::new();
+    let started = Instant::now();
+    for line in &lines {
+        parse::get_data(device, Encoding::Hex, &line.text, &resyncs, &mut decoded).unwrap();
+        let text = std::str::from_utf8(&decoded).unwrap();
+        black_box(parse::parse_data(text, &options).unwrap());
+    }
+    report("parsing", started.elapsed());
+}
+
+/// Frames the bytes a chunk at a time like the read task hands them over, as lines off the
+/// queue
+fn frame(device: &'static dyn Device, radio: &RadioArgs, bytes: &[u8]) -> Vec<Line> {
+    let receive = Receive {
+        device,
+        radio,
+        line_ending: LineEnding::Crlf,
+        timeout: Duration::from_secs(1),
+        signal: false,
+    };
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let (sender, mut receiver) = queue::bounded(PACKETS, WhenFull::Block);
+        let running = Arc::new(AtomicBool::new(true));
+        let mut reader = Reader::new(receive, "bench".into(), None, running, sender, Discard);
+        for chunk in bytes.chunks(CHUNK) {
+            reader.read(chunk, SystemTime::now()).await.unwrap();
+        }
+        drop(reader);
+        let mut lines = Vec::with_capacity(PACKETS);
+        while let Some(line) = receiver.recv().await {
+            lines.push(line);
+        }
+        lines
+    })
+}
+
+fn report(stage: &str, elapsed: Duration) {
+    let per_packet = elapsed.as_nanos() / PACKETS as u128;
+    println!("{stage}: {per_packet} ns per packet ({PACKETS} in {elapsed:.2?})");
+}
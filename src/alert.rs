@@ -0,0 +1,117 @@
+use clap::ValueEnum;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
+use tracing::{debug, warn};
+
+/// Settings for telling someone at the laptop of what happens to the telemetry
+#[derive(clap::Args)]
+#[command(about = None, long_about = None)]
+pub struct AlertArgs {
+    #[arg(long, value_name = "PROGRAM")]
+    /// Run the program on each event, with the event and what happened as its two arguments and
+    /// both as JSON on stdin, without waiting for it
+    pub notify_cmd: Option<PathBuf>,
+    #[arg(long, value_enum, value_name = "EVENT", value_delimiter = ',', default_values_t = Event::value_variants().to_vec())]
+    /// Events that run --notify-cmd or ring the --bell, given as a list like
+    /// first-packet,telemetry-lost, telemetry-lost and -resumed coming with --idle-warn
+    pub notify_on: Vec<Event>,
+    #[arg(long)]
+    /// Ring the terminal bell on each event
+    pub bell: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Event {
+    /// The first record of the run
+    FirstPacket,
+    /// No packet parsed for the --idle-warn time
+    TelemetryLost,
+    /// A packet parsed after a --idle-warn warning
+    TelemetryResumed,
+    /// A port that went away
+    PortLost,
+}
+
+impl Event {
+    fn name(self) -> String {
+        self.to_possible_value().unwrap().get_name().to_string()
+    }
+}
+
+struct Alerts {
+    program: Option<PathBuf>,
+    events: Vec<Event>,
+    bell: bool,
+}
+
+/// What the events do, once `configure` has been told
+static ALERTS: Mutex<Option<Alerts>> = Mutex::new(None);
+
+pub fn configure(args: &AlertArgs) {
+    if args.notify_cmd.is_none() && !args.bell {
+        return;
+    }
+    *ALERTS.lock().unwrap_or_else(PoisonError::into_inner) = Some(Alerts {
+        program: args.notify_cmd.clone(),
+        events: args.notify_on.clone(),
+        bell: args.bell,
+    });
+}
+
+/// Runs --notify-cmd and rings the --bell for the event, if they're wanted for it
+pub fn fire(event: Event, detail: &str) {
+    let alerts = ALERTS.lock().unwrap_or_else(PoisonError::into_inner);
+    let Some(alerts) = alerts
+        .as_ref()
+        .filter(|alerts| alerts.events.contains(&event))
+    else {
+        return;
+    };
+    if alerts.bell {
+        let mut stderr = std::io::stderr();
+        let _ = stderr.write_all(b"\x07").and_then(|_| stderr.flush());
+    }
+    let Some(program) = alerts.program.clone() else {
+        return;
+    };
+    let (name, detail) = (event.name(), detail.to_string());
+    // The program may take its time, which the read loop and the main thread can't wait for
+    std::thread::spawn(move || {
+        if let Err(error) = run(&program, &name, &detail) {
+            warn!(
+                kind = "notify_cmd",
+                "--notify-cmd {} failed on {name}: {error}",
+                program.display()
+            );
+        }
+    });
+}
+
+fn run(program: &PathBuf, event: &str, detail: &str) -> Result<(), String> {
+    let mut child = Command::new(program)
+        .args([event, detail])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|error| error.to_string())?;
+    let json = serde_json::json!({
+        "event": event,
+        "detail": detail,
+        "time": charter::clock::timestamp(SystemTime::now()),
+    });
+    if let Some(mut stdin) = child.stdin.take() {
+        // A program that only looks at its arguments may well close stdin unread
+        let _ = writeln!(stdin, "{json}");
+    }
+    let status = child.wait().map_err(|error| error.to_string())?;
+    match status.success() {
+        true => {
+            debug!("--notify-cmd {} ran on {event}", program.display());
+            Ok(())
+        }
+        false => Err(status.to_string()),
+    }
+}
@@ -0,0 +1,238 @@
+use crate::output::{Durability, Flush};
+use clap::ValueEnum;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, info};
+
+/// What a timed raw log starts with, so it can't be mistaken for plain bytes
+pub const MAGIC: &[u8] = b"charter-raw 1\n";
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum RawFormat {
+    /// The bytes as they came in, with the ports' interleaved if there's more than one
+    Bytes,
+    /// Each chunk read led by its port's position in --port, the receive time in ms since the
+    /// epoch and its length, all little-endian: u8, u64, u32
+    Timed,
+}
+
+/// The raw log in use, written to by every reader thread
+static RAW_LOG: Mutex<Option<RawLog>> = Mutex::new(None);
+
+/// Appends everything read from the ports to a file, ahead of any framing
+struct RawLog {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    format: RawFormat,
+    policy: Flush,
+    sync: Durability,
+}
+
+fn lock() -> MutexGuard<'static, Option<RawLog>> {
+    RAW_LOG.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Opens the raw log for appending, which has to be in the same format if it isn't empty
+pub fn open(path: &Path, format: RawFormat, policy: Flush, sync: Durability) -> Result<(), String> {
+    let failed = |error| format!("Failed to open {}: {error}", path.display());
+    let mut file = File::options()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(failed)?;
+    let mut start = Vec::with_capacity(MAGIC.len());
+    (&mut file)
+        .take(MAGIC.len() as u64)
+        .read_to_end(&mut start)
+        .map_err(failed)?;
+    let timed = start == MAGIC;
+    match format {
+        _ if start.is_empty() => info!("Capturing the raw bytes to {}", path.display()),
+        RawFormat::Timed if !timed => {
+            return Err(format!(
+                "{} isn't a timed raw log, pass --raw-log-format bytes to append to it",
+                path.display()
+            ))
+        }
+        RawFormat::Bytes if timed => {
+            return Err(format!(
+                "{} is a timed raw log, pass --raw-log-format timed to append to it",
+                path.display()
+            ))
+        }
+        _ => info!("Appending the raw bytes to {}", path.display()),
+    }
+    let mut writer = BufWriter::new(file);
+    if start.is_empty() && format == RawFormat::Timed {
+        writer.write_all(MAGIC).map_err(failed)?;
+    }
+    *lock() = Some(RawLog {
+        path: path.to_path_buf(),
+        writer,
+        format,
+        policy,
+        sync,
+    });
+    Ok(())
+}
+
+/// Appends bytes read at `received` from the port at `source` in --port
+///
+/// A failure is logged and ends the capture, the run goes on without it.
+pub fn write(source: u8, received: SystemTime, bytes: &[u8]) {
+    let mut raw_log = lock();
+    let Some(log) = raw_log.as_mut() else {
+        return;
+    };
+    let mut result = match log.format {
+        RawFormat::Bytes => Ok(()),
+        RawFormat::Timed => {
+            let received = received
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            let mut prefix = [0; 13];
+            prefix[0] = source;
+            prefix[1..9].copy_from_slice(&received.to_le_bytes());
+            prefix[9..].copy_from_slice(&(bytes.len() as u32).to_le_bytes());
+            log.writer.write_all(&prefix)
+        }
+    }
+    .and_then(|_| log.writer.write_all(bytes));
+    // Chunks rarely end on a record, so this is as close as the raw log gets to it
+    if log.policy == Flush::EveryRecord {
+        result = result.and_then(|_| log.flush());
+    }
+    if let Err(error) = result {
+        error!(
+            "Failed to capture to {}, no longer capturing: {error}",
+            log.path.display()
+        );
+        *raw_log = None;
+    }
+}
+
+impl RawLog {
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()?;
+        match self.sync {
+            Durability::Fsync => self.writer.get_ref().sync_data(),
+            Durability::None | Durability::Flush => Ok(()),
+        }
+    }
+}
+
+/// Flushes the raw log along with the outputs
+pub fn flush() {
+    let mut raw_log = lock();
+    if let Some(Err(error)) = raw_log.as_mut().map(RawLog::flush) {
+        error!("Failed to flush the raw log, no longer capturing: {error}");
+        *raw_log = None;
+    }
+}
+
+/// Writes out and closes the raw log on the way out
+pub fn finish() {
+    let Some(mut log) = lock().take() else {
+        return;
+    };
+    let result = log.writer.flush().and_then(|_| match log.sync {
+        Durability::None => Ok(()),
+        Durability::Flush | Durability::Fsync => log.writer.get_ref().sync_data(),
+    });
+    if let Err(error) = result {
+        error!("Failed to finish {}: {error}", log.path.display());
+    }
+}
+
+/// Bytes read from a port in one go, as the raw log has them
+pub struct Chunk {
+    /// The port's position in --port
+    pub source: u8,
+    /// When they were read, if the raw log is timed
+    pub received: Option<SystemTime>,
+    pub bytes: Vec<u8>,
+}
+
+/// Reads the chunks back from a raw log in either format
+pub struct Chunks {
+    reader: BufReader<File>,
+    timed: bool,
+}
+
+/// Opens a raw log for replaying, telling the format by how it starts
+pub fn chunks(path: &Path) -> Result<Chunks, String> {
+    let failed = |error| format!("Failed to open {}: {error}", path.display());
+    let mut reader = BufReader::new(File::open(path).map_err(failed)?);
+    let mut start = Vec::with_capacity(MAGIC.len());
+    (&mut reader)
+        .take(MAGIC.len() as u64)
+        .read_to_end(&mut start)
+        .map_err(failed)?;
+    let timed = start == MAGIC;
+    info!(
+        "Replaying {}{}",
+        path.display(),
+        if timed { " with its receive times" } else { "" }
+    );
+    if !timed {
+        // Plain bytes, the first of which were just read
+        reader.rewind().map_err(failed)?;
+    }
+    Ok(Chunks { reader, timed })
+}
+
+impl Iterator for Chunks {
+    type Item = std::io::Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.timed {
+            // The same size the ports are read in
+            let mut bytes = vec![0; 1024];
+            return match self.reader.read(&mut bytes) {
+                Ok(0) => None,
+                Ok(n) => {
+                    bytes.truncate(n);
+                    Some(Ok(Chunk {
+                        source: 0,
+                        received: None,
+                        bytes,
+                    }))
+                }
+                Err(error) => Some(Err(error)),
+            };
+        }
+        let mut prefix = [0; 13];
+        match self.reader.read(&mut prefix[..1]) {
+            Ok(0) => return None,
+            Ok(_) => (),
+            Err(error) => return Some(Err(error)),
+        }
+        let chunk = self
+            .reader
+            .read_exact(&mut prefix[1..])
+            .and_then(|_| {
+                let received = u64::from_le_bytes(prefix[1..9].try_into().unwrap());
+                let length = u32::from_le_bytes(prefix[9..].try_into().unwrap());
+                let mut bytes = vec![0; length as usize];
+                self.reader.read_exact(&mut bytes)?;
+                Ok(Chunk {
+                    source: prefix[0],
+                    received: Some(UNIX_EPOCH + Duration::from_millis(received)),
+                    bytes,
+                })
+            })
+            .map_err(|error| match error.kind() {
+                ErrorKind::UnexpectedEof => std::io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "the raw log ends partway through a chunk",
+                ),
+                _ => error,
+            });
+        Some(chunk)
+    }
+}
@@ -0,0 +1,56 @@
+use clap::ValueEnum;
+
+/// Checksums carried as the last payload field, in hexadecimal
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Checksum {
+    /// CRC16-CCITT (polynomial 0x1021, initial value 0xFFFF)
+    Crc16,
+    /// Sum of all bytes modulo 256
+    Sum8,
+}
+
+impl Checksum {
+    pub fn name(self) -> &'static str {
+        match self {
+            Checksum::Crc16 => "crc16",
+            Checksum::Sum8 => "sum8",
+        }
+    }
+
+    pub fn compute(self, data: &[u8]) -> u16 {
+        match self {
+            Checksum::Crc16 => data.iter().fold(0xffff, |crc, &byte| {
+                (0..8).fold(crc ^ (u16::from(byte) << 8), |crc, _| {
+                    if crc & 0x8000 != 0 {
+                        (crc << 1) ^ 0x1021
+                    } else {
+                        crc << 1
+                    }
+                })
+            }),
+            Checksum::Sum8 => data
+                .iter()
+                .fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+                .into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc16_matches_the_ccitt_check_value() {
+        assert_eq!(Checksum::Crc16.compute(b"123456789"), 0x29b1);
+        assert_eq!(Checksum::Crc16.compute(b"A"), 0xb915);
+        assert_eq!(Checksum::Crc16.compute(b""), 0xffff);
+    }
+
+    #[test]
+    fn sum8_wraps_around() {
+        assert_eq!(Checksum::Sum8.compute(b"123456789"), 0xdd);
+        assert_eq!(Checksum::Sum8.compute(&[0xff, 0x02]), 0x01);
+        assert_eq!(Checksum::Sum8.compute(b""), 0);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::schema::TimeUnit;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::fmt::Write;
+use std::time::{Duration, SystemTime};
+use tracing::info;
+
+/// Turns the on-board timestamp of each packet into wall-clock time, anchored to the receive
+/// time of the first packet for counters that start at boot
+pub struct Clock {
+    unit: TimeUnit,
+    /// Seconds since boot and receive time of the first packet
+    first: Option<(f64, SystemTime)>,
+    last: Option<(f64, SystemTime)>,
+    /// The last raw value, to notice the counter wrapping around
+    raw: Option<f64>,
+    wraps: u32,
+}
+
+impl Clock {
+    pub fn new(unit: TimeUnit) -> Self {
+        Clock {
+            unit,
+            first: None,
+            last: None,
+            raw: None,
+            wraps: 0,
+        }
+    }
+
+    /// ISO 8601 time of the packet, empty if the field isn't a number
+    pub fn absolute(&mut self, value: &str, received: SystemTime) -> String {
+        let Ok(raw) = value.parse::<f64>() else {
+            return String::new();
+        };
+        if self.unit.is_boot() {
+            // A u32 counter wraps after ~49 days in ms, a late or repeated packet only goes
+            // back a little
+            let modulus = u32::MAX as f64 + 1.0;
+            if self.raw.is_some_and(|last| last - raw > modulus / 2.0) {
+                self.wraps += 1;
+            }
+            self.raw = Some(raw);
+        }
+        let seconds = (raw + self.wraps as f64 * (u32::MAX as f64 + 1.0)) * self.unit.seconds();
+        let &mut (first_seconds, first_received) = self.first.get_or_insert((seconds, received));
+        self.last = Some((seconds, received));
+
+        let time = if self.unit.is_boot() {
+            let since_first = seconds - first_seconds;
+            let offset = Duration::try_from_secs_f64(since_first.abs()).unwrap_or_default();
+            // Packets that were sent before the first one arrived come out earlier
+            if since_first < 0.0 {
+                first_received - offset
+            } else {
+                first_received + offset
+            }
+        } else {
+            SystemTime::UNIX_EPOCH + Duration::try_from_secs_f64(seconds).unwrap_or_default()
+        };
+        timestamp(time)
+    }
+
+    /// Logs how far the on-board clock drifted from the receive times
+    pub fn log_summary(&self) {
+        let (Some((first_seconds, first_received)), Some((last_seconds, last_received))) =
+            (self.first, self.last)
+        else {
+            return;
+        };
+        let received = last_received
+            .duration_since(first_received)
+            .unwrap_or_default()
+            .as_secs_f64();
+        if received == 0.0 {
+            return;
+        }
+        let drift = (last_seconds - first_seconds) - received;
+        info!(
+            "On-board clock drifted {drift:+.3} s over {received:.0} s ({:+.0} ppm)",
+            drift / received * 1_000_000.0
+        );
+    }
+}
+
+/// ISO 8601 UTC time in milliseconds
+pub fn timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Appends the time as `timestamp` writes it, so a buffer can be reused for each record
+pub fn push_timestamp(text: &mut String, time: SystemTime) {
+    let time = DateTime::<Utc>::from(time).format("%Y-%m-%dT%H:%M:%S%.3fZ");
+    let _ = write!(text, "{time}");
+}
@@ -0,0 +1,302 @@
+use crate::output::{self, Compression, Output, OutputArgs, Record};
+use crate::schema::{self, Schema};
+use charter::sink;
+use chrono::DateTime;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::SystemTime;
+use tracing::{error, info, warn};
+
+/// Options of the convert subcommand
+#[derive(clap::Args)]
+pub struct ConvertArgs {
+    #[arg(value_name = "[FORMAT:]FILE", value_parser = parse_input)]
+    /// CSV or JSON Lines file to read, by its extension unless FORMAT is csv or jsonl, `-` for
+    /// CSV on stdin, decompressed if the name ends in .gz or .zst
+    input: Input,
+    #[arg(long, value_name = "CHAR", default_value = ",", value_parser = sink::parse_delimiter)]
+    /// What separates the values of a CSV input
+    input_delimiter: u8,
+    #[arg(long, value_name = "FILE", value_parser = schema::load)]
+    /// TOML file typing the columns for SQLite and Parquet output
+    schema: Option<Schema>,
+    #[command(flatten)]
+    outputs: OutputArgs,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum InputFormat {
+    Csv,
+    Jsonl,
+}
+
+#[derive(Clone)]
+struct Input {
+    format: InputFormat,
+    /// None for stdin
+    path: Option<PathBuf>,
+    compression: Option<Compression>,
+}
+
+/// Parses `-` or `[FORMAT:]FILE`
+fn parse_input(value: &str) -> Result<Input, String> {
+    if value == "-" {
+        return Ok(Input {
+            format: InputFormat::Csv,
+            path: None,
+            compression: None,
+        });
+    }
+    let (format, path) = match value.split_once(':') {
+        Some(("csv", path)) => (Some(InputFormat::Csv), path),
+        Some(("jsonl", path)) => (Some(InputFormat::Jsonl), path),
+        _ => (None, value),
+    };
+    if path.is_empty() {
+        return Err(format!("`{value}` doesn't name a file"));
+    }
+    let (name, compression) = match path {
+        _ if path.ends_with(".gz") => (&path[..path.len() - 3], Some(Compression::Gzip)),
+        _ if path.ends_with(".zst") => (&path[..path.len() - 4], Some(Compression::Zstd)),
+        _ => (path, None),
+    };
+    let format = format.unwrap_or(match Path::new(name).extension() {
+        Some(extension) if extension == "jsonl" || extension == "json" => InputFormat::Jsonl,
+        _ => InputFormat::Csv,
+    });
+    Ok(Input {
+        format,
+        path: Some(PathBuf::from(path)),
+        compression,
+    })
+}
+
+impl Input {
+    fn name(&self) -> String {
+        match self.path {
+            Some(ref path) => path.display().to_string(),
+            None => String::from("stdin"),
+        }
+    }
+}
+
+/// A record read back, with its values by column name
+struct Row {
+    index: Option<usize>,
+    received: Option<SystemTime>,
+    values: Vec<(String, String)>,
+}
+
+type Rows = Box<dyn Iterator<Item = Result<Row, String>>>;
+
+fn parse_time(value: &str) -> Result<SystemTime, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(SystemTime::from)
+        .map_err(|error| format!("`{value}` isn't an ISO 8601 time: {error}"))
+}
+
+fn csv_rows(reader: Box<dyn Read>, delimiter: u8) -> Result<Rows, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(reader);
+    let header: Vec<String> = reader
+        .headers()
+        .map_err(|error| format!("Failed to read the header: {error}"))?
+        .iter()
+        .map(String::from)
+        .collect();
+    Ok(Box::new(reader.into_records().map(move |record| {
+        let record = record.map_err(|error| error.to_string())?;
+        let line = record.position().map_or(0, |position| position.line());
+        let mut row = Row {
+            index: None,
+            received: None,
+            values: Vec::with_capacity(record.len()),
+        };
+        for (position, value) in record.iter().enumerate() {
+            match header.get(position).map(String::as_str) {
+                Some("index") => {
+                    let index = value
+                        .parse()
+                        .map_err(|_| format!("line {line}: `{value}` isn't an index"))?;
+                    row.index = Some(index);
+                }
+                Some("received_at") => {
+                    row.received =
+                        Some(parse_time(value).map_err(|error| format!("line {line}: {error}"))?)
+                }
+                Some(name) => row.values.push((name.to_string(), value.to_string())),
+                None => row
+                    .values
+                    .push((format!("field_{position}"), value.to_string())),
+            }
+        }
+        Ok(row)
+    })))
+}
+
+fn json_rows(reader: Box<dyn Read>) -> Rows {
+    let lines = BufReader::new(reader).lines().enumerate();
+    Box::new(
+        lines
+            .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+            .map(|(number, line)| {
+                let line = line.map_err(|error| error.to_string())?;
+                let failed = |error: String| format!("line {}: {error}", number + 1);
+                let object: serde_json::Map<String, serde_json::Value> =
+                    serde_json::from_str(&line).map_err(|error| failed(error.to_string()))?;
+                let mut row = Row {
+                    index: None,
+                    received: None,
+                    values: Vec::with_capacity(object.len()),
+                };
+                for (name, value) in object {
+                    match (name.as_str(), value) {
+                        ("index", value) => {
+                            let index = value
+                                .as_u64()
+                                .ok_or_else(|| failed(format!("`{value}` isn't an index")))?;
+                            row.index = Some(index as usize);
+                        }
+                        ("received_at", serde_json::Value::String(value)) => {
+                            row.received = Some(parse_time(&value).map_err(failed)?)
+                        }
+                        (_, serde_json::Value::Null) => row.values.push((name, String::new())),
+                        (_, serde_json::Value::String(value)) => row.values.push((name, value)),
+                        (_, value) => row.values.push((name, value.to_string())),
+                    }
+                }
+                Ok(row)
+            }),
+    )
+}
+
+fn invalid(message: String) -> ! {
+    use clap::CommandFactory;
+    crate::Cli::command()
+        .error(clap::error::ErrorKind::ArgumentConflict, message)
+        .exit()
+}
+
+/// Writes the records of the input to the outputs and exits
+pub fn run(args: &ConvertArgs) -> ! {
+    if args.outputs.output.is_empty() {
+        invalid(String::from("convert needs an --output to write to"));
+    }
+    if let Some(ref input) = args.input.path {
+        if args.outputs.files().any(|(_, path)| path == input) {
+            invalid(format!(
+                "{} can't be both the input and an --output",
+                input.display()
+            ));
+        }
+    }
+    if let Some(message) = args.outputs.unknown_full_rate() {
+        invalid(message);
+    }
+    let input = &args.input;
+    let reader: Box<dyn Read> = match input.path {
+        Some(ref path) => charter::sink::open_read(path, input.compression)
+            .unwrap_or_else(|error| panic!("Failed to open {}: {error}", path.display())),
+        None => Box::new(std::io::stdin()),
+    };
+    let mut rows = match input.format {
+        InputFormat::Csv => csv_rows(reader, args.input_delimiter)
+            .unwrap_or_else(|error| panic!("{}: {error}", input.name())),
+        InputFormat::Jsonl => json_rows(reader),
+    }
+    .peekable();
+
+    // The columns of the first record, which JSON keys seen later are added to
+    let mut columns: Vec<String> = match rows.peek() {
+        Some(Ok(row)) => row.values.iter().map(|(name, _)| name.clone()).collect(),
+        _ => Vec::new(),
+    };
+    if let Some(Ok(Row { received: None, .. })) = rows.peek() {
+        warn!(
+            "{} has no received_at, the records are given the time they're converted",
+            input.name()
+        );
+    }
+    let header = (!columns.is_empty()).then(|| columns.clone());
+    let width_varies = input.format == InputFormat::Jsonl;
+    let mut outputs: Vec<Box<dyn Output>> = Vec::new();
+    for (format, path) in args.outputs.files() {
+        let file = output::open(
+            &args.outputs,
+            format,
+            path,
+            header.clone(),
+            args.schema.as_ref(),
+            width_varies,
+        );
+        outputs.push(args.outputs.sampled(file, Some(path)));
+    }
+    if let Some(format) = args.outputs.stdout() {
+        let stdout = output::stdout(&args.outputs, format, header);
+        outputs.push(args.outputs.sampled(stdout, Some(Path::new("-"))));
+    }
+    for output in &mut outputs {
+        output.prepare().unwrap_or_else(|error| {
+            crate::exit_with(crate::EXIT_OUTPUT_FAILED);
+            panic!("{error}")
+        });
+    }
+    output::install(outputs);
+
+    let policy = args.outputs.flush_policy();
+    let (mut converted, mut skipped) = (0, 0);
+    for (number, row) in rows.enumerate() {
+        let row = match row {
+            Ok(row) => row,
+            Err(error) => {
+                warn!("Skipping a record of {}: {error}", input.name());
+                skipped += 1;
+                continue;
+            }
+        };
+        let mut values = vec![""; columns.len()];
+        for (name, value) in &row.values {
+            match columns.iter().position(|column| column == name) {
+                Some(position) => values[position] = value,
+                None => {
+                    columns.push(name.clone());
+                    values.push(value);
+                }
+            }
+        }
+        let record = Record {
+            index: row.index.unwrap_or(number),
+            received: row.received.unwrap_or_else(SystemTime::now),
+            payload: &[],
+            columns: &columns,
+            values: &values,
+        };
+        let mut fatal = None;
+        let mut outputs = output::lock();
+        for output in outputs.iter_mut() {
+            if let Err(error) = output.write(&record) {
+                match output::is_fatal(&*error) {
+                    true => fatal = Some(error),
+                    false => error!("{error}"),
+                }
+            }
+        }
+        fatal = fatal.or_else(|| output::written(&mut outputs, policy));
+        drop(outputs);
+        if let Some(error) = fatal {
+            crate::exit_with(crate::EXIT_OUTPUT_FAILED);
+            panic!("{error}");
+        }
+        converted += 1;
+    }
+    output::finish_all();
+    info!(
+        "Converted {converted} records from {}, skipped {skipped}",
+        input.name()
+    );
+    crate::log_file::finish();
+    exit(0)
+}
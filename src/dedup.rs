@@ -0,0 +1,78 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Remembers the most recent packets to recognize retransmissions of the same one
+pub struct Dedup {
+    size: usize,
+    age: Duration,
+    recent: VecDeque<(Instant, String)>,
+}
+
+impl Dedup {
+    pub fn new(size: usize, age: Duration) -> Self {
+        Dedup {
+            size,
+            age,
+            recent: VecDeque::with_capacity(size),
+        }
+    }
+
+    /// Whether the key was seen within the window, remembering it either way
+    pub fn is_duplicate(&mut self, key: String) -> bool {
+        let now = Instant::now();
+        while self
+            .recent
+            .front()
+            .is_some_and(|(seen, _)| now.duration_since(*seen) > self.age)
+        {
+            self.recent.pop_front();
+        }
+        if self.recent.iter().any(|(_, recent)| *recent == key) {
+            return true;
+        }
+        if self.recent.len() == self.size {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((now, key));
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeats_within_the_window_are_duplicates() {
+        let mut dedup = Dedup::new(3, Duration::from_secs(60));
+        assert!(!dedup.is_duplicate("a".into()));
+        assert!(!dedup.is_duplicate("b".into()));
+        assert!(dedup.is_duplicate("a".into()));
+        assert!(dedup.is_duplicate("b".into()));
+        // A duplicate isn't remembered a second time
+        assert_eq!(dedup.recent.len(), 2);
+    }
+
+    #[test]
+    fn the_oldest_packet_leaves_a_full_window() {
+        let mut dedup = Dedup::new(2, Duration::from_secs(60));
+        assert!(!dedup.is_duplicate("a".into()));
+        assert!(!dedup.is_duplicate("b".into()));
+        assert!(!dedup.is_duplicate("c".into()));
+        assert!(dedup.is_duplicate("c".into()));
+        assert!(dedup.is_duplicate("b".into()));
+        assert!(!dedup.is_duplicate("a".into()));
+        assert!(!dedup.is_duplicate("b".into()));
+    }
+
+    #[test]
+    fn packets_are_forgotten_after_the_age() {
+        let mut dedup = Dedup::new(10, Duration::from_millis(10));
+        assert!(!dedup.is_duplicate("a".into()));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!dedup.is_duplicate("b".into()));
+        assert_eq!(dedup.recent.len(), 1);
+        assert!(!dedup.is_duplicate("a".into()));
+        assert!(dedup.is_duplicate("b".into()));
+    }
+}
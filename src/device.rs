@@ -0,0 +1,242 @@
+use crate::radio::{self, RadioArgs, RadioError};
+use crate::receiver::{Framing, Query, Rn2483, Rylr};
+use clap::ValueEnum;
+use serialport::SerialPort;
+use std::io::Write;
+use std::time::Duration;
+use tracing::info;
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum DeviceKind {
+    /// Microchip RN2483/RN2903 (`radio rx 0`, `radio_rx <hex>`)
+    Rn2483,
+    /// REYAX RYLR896/RYLR998 (`AT+...`, `+RCV=addr,len,data,rssi,snr`)
+    Rylr,
+}
+
+impl DeviceKind {
+    pub fn profile(self) -> &'static dyn Device {
+        match self {
+            DeviceKind::Rn2483 => &Rn2483,
+            DeviceKind::Rylr => &Rylr,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DeviceKind::Rn2483 => "rn2483",
+            DeviceKind::Rylr => "rylr",
+        }
+    }
+}
+
+/// The command set of a LoRa module, everything else about receiving is shared
+pub trait Device: Framing {
+    /// The first option given that the module has no equivalent for
+    fn unsupported(&self, _radio: &RadioArgs) -> Option<&'static str> {
+        None
+    }
+
+    /// Brings a freshly opened module into receive, returning its firmware version if known
+    fn begin(
+        &self,
+        serial: &mut Box<dyn SerialPort>,
+        radio: &RadioArgs,
+    ) -> Result<Option<String>, RadioError>;
+
+    /// Stops receiving, then puts the module to sleep if asked to
+    fn end(
+        &self,
+        serial: &mut Box<dyn SerialPort>,
+        sleep: Option<u32>,
+    ) -> Result<(), serialport::Error>;
+
+    /// Commands the read loop sends after each packet
+    fn follow_ups<'a>(&self, radio: &'a RadioArgs, signal: bool) -> Vec<Query<'a>>;
+
+    /// A command the module answers whatever it's doing, to tell it's still there
+    fn probe(&self) -> &'static str;
+
+    /// The commands that set the module up again and put it into receive, each answered by
+    /// one line, for when it answers a probe and may have reset in the silence
+    fn arm(&self, radio: &RadioArgs) -> Vec<String>;
+}
+
+impl Device for Rn2483 {
+    fn begin(
+        &self,
+        serial: &mut Box<dyn SerialPort>,
+        radio: &RadioArgs,
+    ) -> Result<Option<String>, RadioError> {
+        radio::wake_module(serial)?;
+        let version = radio::log_version(serial)?;
+        radio::serial_begin(serial, radio)?;
+        Ok(version)
+    }
+
+    fn end(
+        &self,
+        serial: &mut Box<dyn SerialPort>,
+        sleep: Option<u32>,
+    ) -> Result<(), serialport::Error> {
+        radio::serial_end(serial, sleep)
+    }
+
+    fn follow_ups<'a>(&self, radio: &'a RadioArgs, signal: bool) -> Vec<Query<'a>> {
+        let mut queries = Vec::new();
+        if signal {
+            queries.extend([Query::Snr, Query::Rssi]);
+        }
+        if let Some(ref ack) = radio.ack {
+            queries.extend([Query::RxStop, Query::Tx(ack), Query::TxDone(ack)]);
+        }
+        // Transmitting ends reception whatever the firmware does by itself
+        if !radio.no_rearm || radio.ack.is_some() {
+            queries.push(Query::Rearm);
+        }
+        queries
+    }
+
+    fn probe(&self) -> &'static str {
+        "sys get ver"
+    }
+
+    fn arm(&self, radio: &RadioArgs) -> Vec<String> {
+        // A module that reset came back with the MAC running and the radio settings lost
+        let mut commands = vec![String::from("mac pause")];
+        commands.extend(radio::settings(radio));
+        commands.push(String::from("radio rx 0"));
+        commands
+    }
+}
+
+/// Sends an AT command that the module acknowledges with `+OK`
+fn expect_at_ok(serial: &mut Box<dyn SerialPort>, command: &str) -> Result<(), RadioError> {
+    match radio::command(serial, command)?.as_str() {
+        "+OK" => Ok(()),
+        reply => Err(RadioError::UnexpectedReply {
+            command: command.to_string(),
+            reply: reply.to_string(),
+        }),
+    }
+}
+
+impl Device for Rylr {
+    fn unsupported(&self, radio: &RadioArgs) -> Option<&'static str> {
+        [
+            (radio.ack.is_some(), "--ack"),
+            (radio.crc.is_some(), "--crc"),
+            (radio.sync.is_some(), "--sync"),
+            (radio.wdt.is_some(), "--wdt"),
+        ]
+        .into_iter()
+        .find_map(|(given, option)| given.then_some(option))
+    }
+
+    fn begin(
+        &self,
+        serial: &mut Box<dyn SerialPort>,
+        radio: &RadioArgs,
+    ) -> Result<Option<String>, RadioError> {
+        // The first command after AT+MODE=1 only wakes the module and goes unanswered
+        serial.write_all(b"AT\r\n")?;
+        std::thread::sleep(Duration::from_millis(100));
+        serial.clear(serialport::ClearBuffer::Input)?;
+        expect_at_ok(serial, "AT+MODE=0")?;
+
+        let version = match radio::command(serial, "AT+VER?") {
+            Ok(reply) => match reply.strip_prefix("+VER=") {
+                Some(version) => {
+                    info!("Module firmware: {version}");
+                    Some(version.to_string())
+                }
+                None => {
+                    tracing::warn!("Unexpected reply to `AT+VER?` ({reply})");
+                    None
+                }
+            },
+            Err(RadioError::NoReply(_)) => {
+                tracing::warn!("No reply to `AT+VER?`, this may not be a REYAX module");
+                None
+            }
+            Err(error) => return Err(error),
+        };
+
+        if let Some(freq) = radio.freq {
+            info!("Setting frequency to {:.3} MHz", freq as f64 / 1_000_000.0);
+            expect_at_ok(serial, &format!("AT+BAND={freq}"))?;
+        }
+        if radio.sf.is_some() || radio.bw.is_some() || radio.cr.is_some() {
+            // AT+PARAMETER sets all four at once, so keep the current values of the rest
+            let command = "AT+PARAMETER?";
+            let reply = radio::command(serial, command)?;
+            let mut current: Vec<u8> = reply
+                .strip_prefix("+PARAMETER=")
+                .map(|values| values.split(',').filter_map(|v| v.parse().ok()).collect())
+                .unwrap_or_default();
+            if current.len() != 4 {
+                return Err(RadioError::UnexpectedReply {
+                    command: command.to_string(),
+                    reply,
+                });
+            }
+            if let Some(sf) = radio.sf {
+                info!("Setting spreading factor to SF{sf}");
+                current[0] = sf;
+            }
+            if let Some(ref bw) = radio.bw {
+                info!("Setting bandwidth to {bw} kHz");
+                current[1] = match bw.as_str() {
+                    "125" => 7,
+                    "250" => 8,
+                    _ => 9,
+                };
+            }
+            if let Some(cr) = radio.cr {
+                info!("Setting coding rate to 4/{cr}");
+                current[2] = cr - 4;
+            }
+            let values: Vec<String> = current.iter().map(u8::to_string).collect();
+            expect_at_ok(serial, &format!("AT+PARAMETER={}", values.join(",")))?;
+        }
+        Ok(version)
+    }
+
+    fn end(
+        &self,
+        serial: &mut Box<dyn SerialPort>,
+        sleep: Option<u32>,
+    ) -> Result<(), serialport::Error> {
+        if sleep.is_some() {
+            serial.write_all(b"AT+MODE=1\r\n")?;
+            info!("Module sleeping until the next start");
+        }
+        Ok(())
+    }
+
+    fn follow_ups<'a>(&self, _radio: &'a RadioArgs, _signal: bool) -> Vec<Query<'a>> {
+        Vec::new()
+    }
+
+    fn probe(&self) -> &'static str {
+        "AT"
+    }
+
+    fn arm(&self, _radio: &RadioArgs) -> Vec<String> {
+        // AT+BAND and AT+PARAMETER are kept in the module's flash
+        vec![String::from("AT+MODE=0")]
+    }
+}
+
+/// Prepares a freshly opened port and arms the radio
+/// Returns the module's firmware version, if it reported one
+pub fn start_receiver(
+    serial: &mut Box<dyn SerialPort>,
+    device: &dyn Device,
+    radio: &RadioArgs,
+) -> Result<Option<String>, RadioError> {
+    if let Some(duration) = radio.reset_on_open {
+        radio::reset_module(serial, duration, radio.reset_rts)?;
+    }
+    device.begin(serial, radio)
+}
@@ -0,0 +1,83 @@
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Warns when no packet has been parsed for a while, again every so often while the silence
+/// lasts, and once more when packets are back
+pub struct Idle {
+    after: Duration,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// When the last packet was parsed, or the radios started listening
+    last: Instant,
+    /// When the last warning went out, while there's nothing new
+    warned: Option<Instant>,
+    /// The longest silence that has ended
+    longest: Duration,
+}
+
+impl Idle {
+    pub fn new(after: Duration) -> Self {
+        Idle {
+            after,
+            state: Mutex::new(State {
+                last: Instant::now(),
+                warned: None,
+                longest: Duration::ZERO,
+            }),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Starts counting the silence, once the radios are listening
+    pub fn start(&self) {
+        self.state().last = Instant::now();
+    }
+
+    /// Warns if it's been quiet for too long, called by the read loops as time passes
+    pub fn tick(&self) {
+        let mut state = self.state();
+        let silent = state.last.elapsed();
+        if silent < self.after {
+            return;
+        }
+        match state.warned {
+            Some(warned) if warned.elapsed() < self.after => return,
+            Some(_) => warn!(
+                kind = "idle",
+                "Still no telemetry, {} s since the last packet",
+                silent.as_secs()
+            ),
+            None => {
+                warn!(kind = "idle", "No telemetry for {} s", silent.as_secs());
+                let detail = format!("no telemetry for {} s", silent.as_secs());
+                crate::alert::fire(crate::alert::Event::TelemetryLost, &detail);
+            }
+        }
+        state.warned = Some(Instant::now());
+    }
+
+    /// Notes a parsed packet, saying so if it ends a silence that was warned of
+    pub fn parsed(&self) {
+        let mut state = self.state();
+        let gap = state.last.elapsed();
+        state.longest = state.longest.max(gap);
+        state.last = Instant::now();
+        if state.warned.take().is_some() {
+            info!("Telemetry resumed after {} s", gap.as_secs());
+            let detail = format!("telemetry resumed after {} s", gap.as_secs());
+            crate::alert::fire(crate::alert::Event::TelemetryResumed, &detail);
+        }
+    }
+
+    /// The longest time without a parsed packet, including the silence up to now
+    pub fn longest(&self) -> Duration {
+        let state = self.state();
+        state.longest.max(state.last.elapsed())
+    }
+}
@@ -0,0 +1,267 @@
+use crate::output::{Output, Record};
+use crate::schema::Schema;
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+use ureq::Agent;
+
+/// Longest wait between attempts to post to an unreachable server
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Settings for posting records to InfluxDB
+#[derive(clap::Args)]
+#[command(about = None, long_about = None)]
+pub struct InfluxArgs {
+    #[arg(long = "influx-url", id = "influx_url", value_name = "URL", requires_all = ["influx_org", "influx_bucket"])]
+    /// InfluxDB server to post each record to as line protocol, e.g. http://localhost:8086
+    pub url: Option<String>,
+    #[arg(
+        long = "influx-org",
+        id = "influx_org",
+        value_name = "ORG",
+        requires = "influx_url"
+    )]
+    /// Organization the bucket belongs to
+    pub org: Option<String>,
+    #[arg(
+        long = "influx-bucket",
+        id = "influx_bucket",
+        value_name = "BUCKET",
+        requires = "influx_url"
+    )]
+    /// Bucket to write to
+    pub bucket: Option<String>,
+    #[arg(
+        long = "influx-token",
+        value_name = "TOKEN",
+        env = "CHARTER_INFLUX_TOKEN",
+        hide_env_values = true
+    )]
+    /// API token allowed to write to the bucket
+    pub token: Option<String>,
+    #[arg(long = "influx-queue", id = "influx_queue", value_name = "RECORDS", default_value_t = 10000, value_parser = clap::value_parser!(u64).range(1..))]
+    /// Records kept for retrying while the server can't be reached, the oldest are dropped first
+    pub queue: u64,
+}
+
+/// Posts records from a background thread, so a slow or unreachable server never holds up
+/// reading the ports
+pub struct Influx {
+    /// The server, without the credentials, for the logs
+    url: String,
+    measurement: String,
+    /// Columns written as tags rather than fields
+    tags: HashSet<String>,
+    sender: Option<SyncSender<String>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Influx {
+    /// Starts posting records, named and tagged as the schema says
+    pub fn new(args: &InfluxArgs, schema: Option<&Schema>) -> Option<Self> {
+        let url = args.url.as_ref()?;
+        let mut tags = HashSet::from([String::from("port")]);
+        tags.extend(
+            schema
+                .iter()
+                .flat_map(|schema| &schema.fields)
+                .filter(|field| field.tag)
+                .map(|field| field.name.clone()),
+        );
+        let endpoint = format!("{}/api/v2/write", url.trim_end_matches('/'));
+        let request = Request {
+            endpoint,
+            org: args.org.clone().unwrap_or_default(),
+            bucket: args.bucket.clone().unwrap_or_default(),
+            token: args.token.clone(),
+        };
+        let queue = args.queue as usize;
+        let (sender, receiver) = mpsc::sync_channel(queue);
+        let thread = std::thread::spawn(move || post_lines(request, receiver, queue));
+        Some(Influx {
+            url: crate::config::redact(url),
+            measurement: schema
+                .and_then(|schema| schema.measurement.clone())
+                .unwrap_or_else(|| String::from("telemetry")),
+            tags,
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    /// Formats the record as line protocol, unless it has no field values at all
+    fn line(&self, record: &Record) -> Option<String> {
+        let mut tags = String::new();
+        let mut fields = Vec::new();
+        for (name, value) in record.named().filter(|(_, value)| !value.is_empty()) {
+            if self.tags.contains(name.as_ref()) {
+                tags.push_str(&format!(
+                    ",{}={}",
+                    escape(&name, ",= "),
+                    escape(value, ",= ")
+                ));
+            } else {
+                fields.push(format!("{}={}", escape(&name, ",= "), field_value(value)));
+            }
+        }
+        if fields.is_empty() {
+            return None;
+        }
+        let time = record
+            .received
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        Some(format!(
+            "{}{tags} {} {time}",
+            escape(&self.measurement, ", "),
+            fields.join(",")
+        ))
+    }
+}
+
+impl Output for Influx {
+    fn name(&self) -> String {
+        self.url.clone()
+    }
+
+    fn prepare(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn write(&mut self, record: &Record) -> Result<(), Box<dyn Error>> {
+        let Some(line) = self.line(record) else {
+            debug!("Nothing but tags to post to InfluxDB");
+            return Ok(());
+        };
+        let sender = self
+            .sender
+            .as_ref()
+            .ok_or("InfluxDB output already finished")?;
+        match sender.try_send(line) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err("InfluxDB queue is full, dropped the record".into()),
+            Err(TrySendError::Disconnected(_)) => Err("InfluxDB output stopped".into()),
+        }
+    }
+
+    /// Gives the queued records one last chance to go out
+    fn finish(&mut self) {
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Request {
+    endpoint: String,
+    org: String,
+    bucket: String,
+    token: Option<String>,
+}
+
+impl Request {
+    fn post(&self, agent: &Agent, body: &str) -> Result<(), ureq::Error> {
+        let mut request = agent
+            .post(&self.endpoint)
+            .query("org", &self.org)
+            .query("bucket", &self.bucket)
+            .query("precision", "ms");
+        if let Some(ref token) = self.token {
+            request = request.header("Authorization", format!("Token {token}"));
+        }
+        request.send(body)?;
+        Ok(())
+    }
+}
+
+/// Posts lines as they come in, holding on to up to `capacity` of them while the server can't
+/// be reached
+fn post_lines(request: Request, receiver: Receiver<String>, capacity: usize) {
+    let agent: Agent = Agent::config_builder()
+        .timeout_global(Some(Duration::from_secs(10)))
+        .build()
+        .into();
+    let mut queue = VecDeque::new();
+    let mut backoff = Duration::from_secs(1);
+    let mut retry = Instant::now();
+    let mut open = true;
+    while open || !queue.is_empty() {
+        let next = if queue.is_empty() {
+            receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else if open {
+            receiver.recv_timeout(retry.saturating_duration_since(Instant::now()))
+        } else {
+            Err(RecvTimeoutError::Timeout)
+        };
+        match next {
+            Ok(line) => {
+                queue.push_back(line);
+                // Whatever else is waiting goes out in the same post
+                queue.extend(receiver.try_iter());
+                let excess = queue.len().saturating_sub(capacity);
+                if excess > 0 {
+                    queue.drain(..excess);
+                    warn!("InfluxDB queue is full, dropped the {excess} oldest records");
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => open = false,
+            Err(RecvTimeoutError::Timeout) => (),
+        }
+        if queue.is_empty() || (open && Instant::now() < retry) {
+            continue;
+        }
+
+        let body = queue.make_contiguous().join("\n");
+        match request.post(&agent, &body) {
+            Ok(()) => {
+                if backoff > Duration::from_secs(1) {
+                    info!("Posted {} queued records to InfluxDB", queue.len());
+                }
+                queue.clear();
+                backoff = Duration::from_secs(1);
+            }
+            Err(error) if open => {
+                warn!(
+                    "Failed to post to InfluxDB, retrying {} records in {} s: {error}",
+                    queue.len(),
+                    backoff.as_secs()
+                );
+                retry = Instant::now() + backoff;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            Err(error) => {
+                warn!(
+                    "Failed to post to InfluxDB, dropped {} records: {error}",
+                    queue.len()
+                );
+                return;
+            }
+        }
+    }
+}
+
+/// Escapes the characters line protocol gives a meaning in that position
+fn escape(text: &str, special: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if special.contains(c) || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Numbers and booleans are written as such, anything else as a string
+fn field_value(value: &str) -> String {
+    match value {
+        "true" | "false" => value.to_string(),
+        value if value.parse::<f64>().is_ok_and(f64::is_finite) => value.to_string(),
+        value => format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+    }
+}
@@ -0,0 +1,60 @@
+use serde_json::{Map, Value};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug)]
+pub struct InvalidJson {
+    pub reason: String,
+    pub text: String,
+}
+
+impl Display for InvalidJson {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Error while parsing data: invalid JSON ({}) in {:?}",
+            self.reason, self.text
+        )
+    }
+}
+
+impl Error for InvalidJson {}
+
+/// Parses a JSON object into its members in order, nested objects flattened to dotted keys
+pub fn parse(text: &str) -> Result<Vec<(String, String)>, InvalidJson> {
+    let invalid = |reason: String| InvalidJson {
+        reason,
+        text: text.to_string(),
+    };
+    match serde_json::from_str(text).map_err(|error| invalid(error.to_string()))? {
+        Value::Object(object) => {
+            let mut members = Vec::with_capacity(object.len());
+            flatten("", object, &mut members);
+            Ok(members)
+        }
+        _ => Err(invalid(String::from("not an object"))),
+    }
+}
+
+fn flatten(prefix: &str, object: Map<String, Value>, members: &mut Vec<(String, String)>) {
+    for (key, value) in object {
+        let key = if prefix.is_empty() {
+            key
+        } else {
+            format!("{prefix}.{key}")
+        };
+        match value {
+            Value::Object(object) => flatten(&key, object, members),
+            value => members.push((key, cell(value))),
+        }
+    }
+}
+
+/// CSV cell for a JSON value, strings unquoted and null left empty
+fn cell(value: Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(string) => string,
+        value => value.to_string(),
+    }
+}
@@ -0,0 +1,18 @@
+//! Receiving telemetry from LoRa modules over a serial port, as done by the `charter` binary
+//!
+//! [`receiver`] talks to the module and frames what it sends into lines, with
+//! [`receiver::Receiver`] doing so without the async read loop, [`parse`] turns packet lines into
+//! rows of fields and [`sink`] writes the rows to CSV files.
+
+pub mod checksum;
+pub mod clock;
+pub mod device;
+pub mod json;
+pub mod parse;
+pub mod queue;
+pub mod radio;
+pub mod receiver;
+pub mod rotation;
+pub mod schema;
+pub mod serial;
+pub mod sink;
@@ -0,0 +1,247 @@
+use crate::output::{Output, Record};
+use clap::ValueEnum;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::Write;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+use tungstenite::Message;
+
+/// Lines a client can fall behind by before it's dropped
+const CLIENT_QUEUE: usize = 256;
+
+/// How long a write to a client may block before it counts as gone
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Settings for streaming records to TCP and WebSocket clients
+#[derive(clap::Args)]
+#[command(about = None, long_about = None)]
+pub struct ListenArgs {
+    #[arg(long = "listen", id = "listen", value_name = "ADDRESS")]
+    /// Stream each record to the TCP clients connecting to this address, e.g. 0.0.0.0:7878
+    pub address: Option<SocketAddr>,
+    #[arg(
+        long = "listen-format",
+        id = "listen_format",
+        value_enum,
+        default_value_t = LineFormat::Jsonl,
+        requires = "listen"
+    )]
+    /// How the records are sent
+    pub format: LineFormat,
+    #[arg(long = "ws-listen", id = "ws_listen", value_name = "ADDRESS")]
+    /// Push each record as JSON to the WebSocket clients connecting to this address, e.g.
+    /// 127.0.0.1:9001
+    pub websocket: Option<SocketAddr>,
+    #[arg(
+        long = "listen-replay",
+        id = "listen_replay",
+        value_name = "RECORDS",
+        default_value_t = 0
+    )]
+    /// Recent records sent to each TCP or WebSocket client as it connects
+    pub replay: u64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Protocol {
+    /// A line per record
+    Tcp,
+    /// A text message per record
+    WebSocket,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum LineFormat {
+    /// One JSON object per line
+    Jsonl,
+    /// CSV rows led by the index and receive time, without a header
+    Csv,
+}
+
+struct Client {
+    address: SocketAddr,
+    sender: SyncSender<Arc<[u8]>>,
+}
+
+/// Clients connected so far along with the records replayed to new ones, kept together so a
+/// client connecting mid-write neither misses nor repeats a record
+#[derive(Default)]
+struct Clients {
+    clients: Vec<Client>,
+    recent: VecDeque<Arc<[u8]>>,
+}
+
+/// Accepts TCP or WebSocket clients in the background, each getting its own thread to write to
+/// it so a slow one is dropped rather than holding up the others
+pub struct Listen {
+    address: SocketAddr,
+    protocol: Protocol,
+    format: LineFormat,
+    replay: usize,
+    clients: Arc<Mutex<Clients>>,
+}
+
+impl Listen {
+    pub fn tcp(args: &ListenArgs) -> Option<Self> {
+        Some(Listen {
+            address: args.address?,
+            protocol: Protocol::Tcp,
+            format: args.format,
+            replay: args.replay as usize,
+            clients: Arc::default(),
+        })
+    }
+
+    pub fn websocket(args: &ListenArgs) -> Option<Self> {
+        Some(Listen {
+            address: args.websocket?,
+            protocol: Protocol::WebSocket,
+            format: LineFormat::Jsonl,
+            replay: args.replay as usize,
+            clients: Arc::default(),
+        })
+    }
+
+    fn line(&self, record: &Record) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self.format {
+            // A message needs no line break to end it
+            LineFormat::Jsonl if self.protocol == Protocol::WebSocket => {
+                Ok(serde_json::to_vec(&record.to_json())?)
+            }
+            LineFormat::Jsonl => {
+                let mut line = serde_json::to_vec(&record.to_json())?;
+                line.push(b'\n');
+                Ok(line)
+            }
+            LineFormat::Csv => {
+                let index = record.index.to_string();
+                let received = crate::clock::timestamp(record.received);
+                let mut writer = csv::WriterBuilder::new()
+                    .terminator(csv::Terminator::Any(b'\n'))
+                    .from_writer(Vec::new());
+                let row = [index.as_str(), received.as_str()]
+                    .into_iter()
+                    .chain(record.values.iter().copied());
+                writer.write_record(row)?;
+                Ok(writer.into_inner()?)
+            }
+        }
+    }
+}
+
+impl Output for Listen {
+    fn name(&self) -> String {
+        match self.protocol {
+            Protocol::Tcp => format!("tcp://{}", self.address),
+            Protocol::WebSocket => format!("ws://{}", self.address),
+        }
+    }
+
+    fn prepare(&mut self) -> Result<(), String> {
+        let listener = TcpListener::bind(self.address)
+            .map_err(|error| format!("Failed to listen on {}: {error}", self.address))?;
+        info!("Listening for clients on {}", self.name());
+        let (clients, protocol, replay) = (self.clients.clone(), self.protocol, self.replay);
+        std::thread::spawn(move || accept(listener, &clients, protocol, replay));
+        Ok(())
+    }
+
+    fn write(&mut self, record: &Record) -> Result<(), Box<dyn Error>> {
+        let line: Arc<[u8]> = self.line(record)?.into();
+        let mut clients = self.clients.lock().unwrap();
+        if self.replay > 0 {
+            if clients.recent.len() == self.replay {
+                clients.recent.pop_front();
+            }
+            clients.recent.push_back(line.clone());
+        }
+        clients
+            .clients
+            .retain(|client| match client.sender.try_send(line.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!("Dropped client {} for falling behind", client.address);
+                    false
+                }
+                // Its thread already logged the disconnect
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+        Ok(())
+    }
+
+    /// Closes each connection once the client got what's queued for it, without waiting for
+    /// one that's stuck
+    fn finish(&mut self) {
+        self.clients.lock().unwrap().clients.clear();
+    }
+}
+
+fn accept(listener: TcpListener, clients: &Mutex<Clients>, protocol: Protocol, replay: usize) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                warn!("Failed to accept a client: {error}");
+                continue;
+            }
+        };
+        let Ok(address) = stream.peer_addr() else {
+            continue;
+        };
+        // The timeout also keeps a client from stalling its WebSocket handshake forever
+        let timeouts = stream
+            .set_write_timeout(Some(WRITE_TIMEOUT))
+            .and_then(|_| stream.set_read_timeout(Some(WRITE_TIMEOUT)));
+        if let Err(error) = timeouts {
+            warn!("Failed to set up client {address}: {error}");
+            continue;
+        }
+        info!("Client {address} connected");
+        let (sender, receiver) = mpsc::sync_channel(CLIENT_QUEUE.max(replay));
+        let mut clients = clients.lock().unwrap();
+        for line in &clients.recent {
+            let _ = sender.try_send(line.clone());
+        }
+        std::thread::spawn(move || match protocol {
+            Protocol::Tcp => serve(stream, address, receiver),
+            Protocol::WebSocket => serve_websocket(stream, address, receiver),
+        });
+        clients.clients.push(Client { address, sender });
+    }
+}
+
+/// Writes the lines to the client until it goes away or there are no more
+fn serve(mut stream: TcpStream, address: SocketAddr, receiver: Receiver<Arc<[u8]>>) {
+    for line in receiver {
+        if let Err(error) = stream.write_all(&line) {
+            info!("Client {address} disconnected: {error}");
+            return;
+        }
+    }
+    info!("Client {address} disconnected");
+}
+
+/// Upgrades the connection and sends the records as text messages, closing it properly once
+/// there are no more
+fn serve_websocket(stream: TcpStream, address: SocketAddr, receiver: Receiver<Arc<[u8]>>) {
+    let mut socket = match tungstenite::accept(stream) {
+        Ok(socket) => socket,
+        Err(error) => {
+            info!("Client {address} didn't open a WebSocket: {error}");
+            return;
+        }
+    };
+    for line in receiver {
+        let text = String::from_utf8_lossy(&line).into_owned();
+        if let Err(error) = socket.send(Message::text(text)) {
+            info!("Client {address} disconnected: {error}");
+            return;
+        }
+    }
+    let _ = socket.close(None).and_then(|_| socket.flush());
+    info!("Client {address} disconnected");
+}
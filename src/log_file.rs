@@ -0,0 +1,233 @@
+use clap::ValueEnum;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use tracing::level_filters::LevelFilter;
+use tracing::{Metadata, Subscriber};
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::format::{debug_fn, Writer};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Settings for writing the log to a file as well as stderr
+#[derive(clap::Args)]
+#[command(about = None, long_about = None)]
+pub struct LogFileArgs {
+    #[arg(long, value_name = "PATH", global = true)]
+    /// Also write the log to this file, without colours, along with the options in effect
+    pub log_file: Option<PathBuf>,
+    #[arg(long, value_enum, value_name = "LEVEL", default_value_t = LogLevel::Info, global = true)]
+    /// The least severe messages written to the log file, whatever --debug says for stderr
+    pub log_file_level: LogLevel,
+    #[arg(long, value_name = "WHEN", default_value = "daily", value_parser = parse_rotation, global = true)]
+    /// When the log file is started afresh: daily, hourly or never, which name the files by
+    /// the date like charter.2026-10-14.log, or a size like 10MB, which renames the full file to
+    /// charter.log.1
+    pub log_rotate: LogRotation,
+    #[arg(long, value_name = "FILES", default_value_t = 7, value_parser = clap::value_parser!(u64).range(1..), global = true)]
+    /// Old log files kept once the log has been rotated, the oldest are deleted
+    pub log_keep: u64,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+/// How the lines of the log look, on stderr and in the log file
+#[derive(Clone, Copy, ValueEnum)]
+pub enum LogFormat {
+    /// Time, level, module and message
+    Full,
+    /// The message followed by its fields, like port=/dev/ttyUSB0 kind=decode
+    Compact,
+    /// Spread over several lines, for reading rather than collecting
+    Pretty,
+    /// One JSON object per line, with the message and the port, packet or index and kind of
+    /// error as keys
+    Json,
+}
+
+/// A tracing layer writing the log in the format to the writer, without colours unless `ansi`
+pub fn layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    let layer = match ansi {
+        true => layer,
+        false => layer.with_ansi(false),
+    };
+    match format {
+        // The fields are for the other formats, this one stays as it always was
+        LogFormat::Full => layer
+            .fmt_fields(debug_fn(|writer: &mut Writer, field, value| {
+                match field.name() {
+                    "message" => write!(writer, "{value:?}"),
+                    _ => Ok(()),
+                }
+            }))
+            .boxed(),
+        LogFormat::Compact => layer.compact().boxed(),
+        LogFormat::Pretty => layer.pretty().boxed(),
+        LogFormat::Json => layer
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(false)
+            .boxed(),
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum LogRotation {
+    Daily,
+    Hourly,
+    Never,
+    /// Bytes a file grows to before it's renamed
+    Size(u64),
+}
+
+/// Parses `daily`, `hourly`, `never` or a size
+fn parse_rotation(value: &str) -> Result<LogRotation, String> {
+    match value {
+        "daily" => Ok(LogRotation::Daily),
+        "hourly" => Ok(LogRotation::Hourly),
+        "never" => Ok(LogRotation::Never),
+        _ => crate::rotation::parse_size(value)
+            .map(LogRotation::Size)
+            .map_err(|error| format!("`{value}` isn't daily, hourly, never or a size: {error}")),
+    }
+}
+
+/// Writes out what's still queued for the log file when it's dropped
+static GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
+
+/// Opens the log file for a tracing layer, written to from a background thread
+pub fn open(args: &LogFileArgs) -> Result<Option<NonBlocking>, String> {
+    let Some(ref path) = args.log_file else {
+        return Ok(None);
+    };
+    let failed = |error: &dyn std::fmt::Display| {
+        format!("Failed to open the log file {}: {error}", path.display())
+    };
+    let (writer, guard) = match args.log_rotate {
+        LogRotation::Size(limit) => {
+            let writer =
+                BySize::open(path, limit, args.log_keep).map_err(|error| failed(&error))?;
+            tracing_appender::non_blocking(writer)
+        }
+        rotation => {
+            let rotation = match rotation {
+                LogRotation::Daily => Rotation::DAILY,
+                LogRotation::Hourly => Rotation::HOURLY,
+                _ => Rotation::NEVER,
+            };
+            let directory = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            let mut builder = RollingFileAppender::builder()
+                .rotation(rotation)
+                .max_log_files(args.log_keep as usize + 1);
+            if let Some(stem) = path.file_stem() {
+                builder = builder.filename_prefix(stem.to_string_lossy());
+            }
+            if let Some(extension) = path.extension() {
+                builder = builder.filename_suffix(extension.to_string_lossy());
+            }
+            let writer = builder.build(directory).map_err(|error| failed(&error))?;
+            tracing_appender::non_blocking(writer)
+        }
+    };
+    *GUARD.lock().unwrap_or_else(PoisonError::into_inner) = Some(guard);
+    Ok(Some(writer))
+}
+
+/// Whether a message goes to the log file, which always gets the options in effect
+pub fn wanted(level: LevelFilter, meta: &Metadata) -> bool {
+    *meta.level() <= level || meta.target() == "charter::config"
+}
+
+/// Writes out the rest of the log file, on the way out or from the panic hook
+pub fn finish() {
+    if let Ok(mut guard) = GUARD.try_lock() {
+        guard.take();
+    }
+}
+
+/// A log file that's renamed to FILE.1 once it reaches its size, FILE.1 to FILE.2 and so on
+struct BySize {
+    path: PathBuf,
+    file: File,
+    bytes: u64,
+    limit: u64,
+    keep: u64,
+}
+
+impl BySize {
+    fn open(path: &Path, limit: u64, keep: u64) -> std::io::Result<Self> {
+        let file = File::options().append(true).create(true).open(path)?;
+        Ok(BySize {
+            path: path.to_path_buf(),
+            bytes: file.metadata()?.len(),
+            file,
+            limit,
+            keep,
+        })
+    }
+
+    fn numbered(&self, number: u64) -> PathBuf {
+        let mut path = self.path.as_os_str().to_owned();
+        path.push(format!(".{number}"));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        let _ = std::fs::remove_file(self.numbered(self.keep));
+        for number in (1..self.keep).rev() {
+            let _ = std::fs::rename(self.numbered(number), self.numbered(number + 1));
+        }
+        std::fs::rename(&self.path, self.numbered(1))?;
+        self.file = File::options().append(true).create(true).open(&self.path)?;
+        self.bytes = 0;
+        Ok(())
+    }
+}
+
+impl Write for BySize {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // Each write is a whole line, which stays in one file
+        if self.bytes > 0 && self.bytes + buf.len() as u64 > self.limit {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -1,199 +1,2055 @@
-use clap::Parser;
-use csv::Writer;
-use serialport::{DataBits, Parity, SerialPort, StopBits};
+mod alert;
+mod capture;
+mod config;
+mod convert;
+mod dedup;
+mod idle;
+mod influx;
+mod listen;
+mod log_file;
+mod metadata;
+mod metrics;
+mod mqtt;
+mod notify;
+mod output;
+mod parquet;
+mod plot;
+mod ports;
+mod reassembly;
+mod sequence;
+mod shell;
+mod summary;
+mod table;
+mod tui;
+mod udp;
+mod webhook;
+
+use alert::{AlertArgs, Event};
+use capture::RawFormat;
+use charter::device::{self, Device, DeviceKind};
+use charter::parse::{
+    get_data, parse_binary, parse_data, parse_json, Encoding, ExtraFields, Format, GetDataError,
+    ParseError, ParseOptions,
+};
+use charter::queue::{self, WhenFull};
+use charter::receiver::{self, Ended, Line, Link, Reader, Receive, Source, Stopped};
+use charter::{checksum, clock, radio, rotation, schema, serial};
+use checksum::Checksum;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use clock::Clock;
+use convert::ConvertArgs;
+use dedup::Dedup;
+use idle::Idle;
+use influx::{Influx, InfluxArgs};
+use listen::{Listen, ListenArgs};
+use log_file::{LogFileArgs, LogFormat};
+use mqtt::{Mqtt, MqttArgs};
+use output::{Flush, Output, OutputArgs, OutputFormat, ReceivedAt, Record, Target};
+use plot::{Plot, PlotField};
+use radio::{RadioArgs, Switch};
+use reassembly::Reassembly;
+use schema::Schema;
+use sequence::Sequences;
+use serial::{FlowControlArg, NativePort, SerialArgs, Stream};
+use serialport::SerialPort;
 use std::backtrace;
 use std::backtrace::Backtrace;
-use std::error::Error;
-use std::fmt::{Display, Formatter, Write};
-use std::io::{BufWriter, ErrorKind, Read, Write as IoWrite};
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::future::Future;
+use std::io::IsTerminal;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
 use std::process::exit;
-use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
-use std::time::Duration;
-use tracing::{debug, error, info, Level};
-use tracing_subscriber::FmtSubscriber;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicI64, AtomicU64};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, TryLockError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use summary::{Intervals, Summary};
+use table::{Display as DisplayMode, Table};
+use tokio::sync::mpsc;
+use tracing::level_filters::LevelFilter;
+use tracing::{debug, error, info};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::layer::{Layer, SubscriberExt};
+use tui::Dashboard;
+use udp::{Udp, UdpArgs};
+use webhook::{Webhook, WebhookArgs};
 
 #[derive(Parser)]
-struct Args {
-    /// Serial port assigned to LoRa receiver
-    port: String,
-    #[arg(short, long)]
+#[command(
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    after_help = EXIT_CODES
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[arg(short, long, global = true)]
     /// Log debug information
     debug: bool,
-    #[arg(short, long)]
-    /// CSV file name to print data to
-    output: Option<PathBuf>,
-    #[arg(short, long)]
-    /// Allow the creation of a new CSV file
-    create: bool,
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = LogFormat::Full, global = true)]
+    /// How the log is written, on stderr and to --log-file
+    log_format: LogFormat,
+    #[arg(long, value_name = "FILE", global = true)]
+    /// TOML file of options named as on the command line, like `baud = 57600` or `output =
+    /// ["data.csv"]`, which those given on the command line override [default: charter.toml,
+    /// then ~/.config/charter/charter.toml, then /etc/charter/charter.toml]
+    config: Option<PathBuf>,
+    #[command(flatten)]
+    log_file: LogFileArgs,
+    #[command(flatten)]
+    args: Args,
 }
 
-fn main() {
-    let args = Args::parse();
+/// Options of the listen subcommand, which are also those of charter without a subcommand
+#[derive(clap::Args)]
+#[command(about = None, long_about = None)]
+struct Args {
+    #[arg(
+        required_unless_present_any = ["auto", "replay", "stdin"],
+        value_delimiter = ',',
+        env = "CHARTER_PORT"
+    )]
+    /// Serial ports assigned to LoRa receivers
+    port: Vec<String>,
+    #[arg(long)]
+    /// Detect the receiver port by its USB VID:PID
+    auto: bool,
+    #[arg(long = "usb-id", value_name = "VID:PID", value_delimiter = ',', value_parser = ports::parse_usb_id)]
+    /// USB IDs considered by --auto [default: common RN2483 bridges]
+    usb_ids: Vec<(u16, u16)>,
+    #[arg(long, value_name = "RAW_LOG", hide = true, conflicts_with_all = ["auto", "raw_log"])]
+    /// The replay subcommand, as it was given before there was one
+    replay: Option<PathBuf>,
+    #[arg(long, conflicts_with_all = ["port", "auto", "replay", "tui"])]
+    /// Read the lines from stdin rather than a port, e.g. to try out simulated packets, and
+    /// exit at its end
+    stdin: bool,
+    #[arg(long)]
+    /// Tell systemd once the radios are armed, ping its watchdog as the ports are read and tell
+    /// it of the shutdown, for units with Type=notify (does nothing outside of one)
+    notify: bool,
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    /// Warn when no packet has been parsed for this long, again as long as the silence lasts, and
+    /// report the longest silence in the summary
+    idle_warn: Option<u64>,
+    #[arg(long, value_name = "SECS", default_value_t = 60)]
+    /// Log the packets, errors and signal of every period this long, unless nothing came in,
+    /// 0 for never
+    stats_every: u64,
+    #[command(flatten)]
+    alerts: AlertArgs,
+    #[arg(long, value_name = "PATH")]
+    /// Append every byte read from the ports to this file, before any framing, to replay later
+    raw_log: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t = RawFormat::Bytes, requires = "raw_log")]
+    /// How the raw log is written
+    raw_log_format: RawFormat,
+    #[command(flatten)]
+    serial: SerialArgs,
+    #[command(flatten)]
+    radio: RadioArgs,
+    #[command(flatten)]
+    common: Common,
+}
 
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(if args.debug {
-            Level::TRACE
-        } else {
-            Level::INFO
+/// Options shared by listen and replay, for making records of the lines and where they go
+#[derive(clap::Args)]
+#[command(about = None, long_about = None)]
+struct Common {
+    #[arg(long, value_enum, default_value_t = DisplayMode::Log)]
+    /// How records are shown on the console, a table falls back to log lines when stdout isn't
+    /// a terminal
+    display: DisplayMode,
+    #[arg(long, conflicts_with = "display")]
+    /// Show a dashboard of the latest values, the packet rate and recent warnings instead of
+    /// log lines, which q or Ctrl-C quits, while the outputs keep going
+    tui: bool,
+    #[arg(long, value_name = "FIELD", conflicts_with_all = ["display", "tui"], value_parser = plot::parse_field)]
+    /// Plot a numeric field, by name or position among the payload's fields, as a sparkline
+    /// redrawn on one line of the terminal instead of log lines
+    plot: Option<PlotField>,
+    #[arg(long, value_name = "VALUES", default_value_t = 60, requires = "plot", value_parser = clap::value_parser!(u64).range(2..))]
+    /// Latest values the plot shows, as far as the terminal is wide enough
+    plot_window: u64,
+    #[arg(long, value_name = "ROWS", default_value_t = 20, value_parser = clap::value_parser!(u64).range(1..))]
+    /// Rows between repeats of the table header
+    table_header: u64,
+    #[arg(long, requires = "output")]
+    /// Write the end-of-run summary as JSON next to each output file too, as FILE.summary.json
+    summary_json: bool,
+    #[arg(long, requires = "output")]
+    /// Number the records from 0 again, in the logs and the files' index column alike, rather
+    /// than carrying on after the rows of the longest --output appended to
+    restart_index: bool,
+    #[command(flatten)]
+    influx: InfluxArgs,
+    #[command(flatten)]
+    mqtt: MqttArgs,
+    #[command(flatten)]
+    listen: ListenArgs,
+    #[command(flatten)]
+    udp: UdpArgs,
+    #[command(flatten)]
+    webhook: WebhookArgs,
+    #[arg(long, value_name = "ADDRESS")]
+    /// Serve Prometheus metrics at http://ADDRESS/metrics, e.g. 127.0.0.1:9400
+    metrics_listen: Option<std::net::SocketAddr>,
+    #[arg(long, value_name = "N", default_value_t = 11, value_parser = clap::value_parser!(u64).range(1..))]
+    /// Number of whitespace-separated fields in each payload
+    fields: u64,
+    #[arg(long, value_name = "FILE", value_parser = schema::load, conflicts_with = "fields")]
+    /// TOML file naming and typing the payload fields, used for validation and the CSV header
+    schema: Option<Schema>,
+    #[arg(long, requires = "schema")]
+    /// Write values as received, ignoring the transforms in the schema
+    raw: bool,
+    #[arg(
+        long,
+        value_name = "NAMES",
+        value_delimiter = ',',
+        conflicts_with_all = ["schema", "no_header"]
+    )]
+    /// Column names for the CSV header [default: from --schema, or field_0, field_1, ...]
+    header: Vec<String>,
+    #[arg(long)]
+    /// Pad payloads with missing trailing fields with empty values instead of skipping them
+    allow_short: bool,
+    #[arg(long, value_enum, default_value_t = ExtraFields::Drop)]
+    /// What to do with fields beyond the expected number
+    extra_fields: ExtraFields,
+    #[arg(long, value_enum)]
+    /// How the module encodes payloads [default: hex for rn2483, none for rylr]
+    encoding: Option<Encoding>,
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    /// How payloads are laid out
+    format: Format,
+    #[arg(long, value_name = "INDEX")]
+    /// Field holding a packet counter, used to report lost packets [default: from --schema]
+    seq_field: Option<usize>,
+    #[arg(long, value_name = "N", default_value_t = 1 << 32, value_parser = clap::value_parser!(u64).range(2..=1 << 32))]
+    /// The packet counter wraps around to 0 on reaching N, e.g. 65536 for a u16
+    seq_modulus: u64,
+    #[arg(long)]
+    /// Skip repeats of a recent packet, compared by sequence number if there is one
+    dedup: bool,
+    #[arg(long, value_name = "PACKETS", default_value_t = 16, requires = "dedup", value_parser = clap::value_parser!(u64).range(1..))]
+    /// Number of recent packets --dedup compares against
+    dedup_window: u64,
+    #[arg(long, value_name = "MS", default_value_t = 10000, requires = "dedup")]
+    /// How long --dedup remembers a packet
+    dedup_age: u64,
+    #[arg(long)]
+    /// Join payloads split over several packets tagged INDEX/COUNT or ID:INDEX/COUNT in the first field
+    reassemble: bool,
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 5000,
+        requires = "reassemble"
+    )]
+    /// How long --reassemble waits for the rest of a split payload
+    reassembly_timeout: u64,
+    #[arg(long, value_name = "CHAR")]
+    /// Character separating payload fields, consecutive ones delimit empty fields [default: whitespace]
+    delimiter: Option<char>,
+    #[arg(long, value_enum)]
+    /// Verify the checksum in the last field over the fields before it, joined by the delimiter
+    checksum: Option<Checksum>,
+    #[arg(long, value_enum, default_value_t = DeviceKind::Rn2483)]
+    /// Command set spoken by the LoRa module
+    device: DeviceKind,
+    #[arg(long, value_name = "LINES", default_value_t = 1024, value_parser = clap::value_parser!(u64).range(1..))]
+    /// Number of received lines buffered while the output catches up
+    channel_depth: u64,
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = WhenFull::DropNewest)]
+    /// What to do with a received line when the buffer is full
+    when_full: WhenFull,
+    #[arg(long)]
+    /// Query each packet's SNR and RSSI, appended as columns after the data fields (modules that
+    /// report them with every packet get the columns anyway)
+    signal: bool,
+    #[command(flatten)]
+    outputs: OutputArgs,
+}
+
+impl Args {
+    /// The options for replaying a raw log, leaving those for the ports as they are when none
+    /// are given
+    fn replaying(replay: ReplayArgs) -> Self {
+        Args {
+            port: replay.port,
+            auto: false,
+            usb_ids: Vec::new(),
+            replay: Some(replay.raw_log),
+            stdin: false,
+            notify: false,
+            idle_warn: None,
+            stats_every: 0,
+            alerts: unset(),
+            raw_log: None,
+            raw_log_format: RawFormat::Bytes,
+            serial: unset(),
+            radio: unset(),
+            common: replay.common,
+        }
+    }
+
+    /// How the ports are received from
+    fn receive(&self) -> Receive<'_> {
+        Receive {
+            device: self.device.profile(),
+            radio: &self.radio,
+            line_ending: self.serial.line_ending,
+            timeout: Duration::from_millis(self.serial.timeout_ms),
+            signal: self.signal,
+        }
+    }
+}
+
+/// The options of a group as they are when none of them are given
+fn unset<T: clap::Args + FromArgMatches>() -> T {
+    let command = T::augment_args(clap::Command::new("unset"));
+    T::from_arg_matches(&command.get_matches_from(["unset"])).unwrap()
+}
+
+// Args and Common are groups of options, used as the options they hold
+impl std::ops::Deref for Args {
+    type Target = Common;
+
+    fn deref(&self) -> &Common {
+        &self.common
+    }
+}
+
+impl std::ops::Deref for Common {
+    type Target = OutputArgs;
+
+    fn deref(&self) -> &OutputArgs {
+        &self.outputs
+    }
+}
+
+impl Common {
+    /// Names of the payload fields, if the schema or --header gave any
+    fn column_names(&self) -> Vec<String> {
+        match self.schema {
+            Some(ref schema) => schema
+                .fields
+                .iter()
+                .map(|field| field.name.clone())
+                .collect(),
+            None => self.header.clone(),
+        }
+    }
+
+    /// Index of the packet counter field, if there is one
+    fn seq_field(&self) -> Option<usize> {
+        self.seq_field.or_else(|| {
+            self.schema
+                .as_ref()?
+                .fields
+                .iter()
+                .position(|field| field.sequence)
         })
-        .finish();
+    }
+
+    /// Number of fields in each payload, as listed by the schema if there is one
+    fn field_count(&self) -> usize {
+        self.schema
+            .as_ref()
+            .map_or(self.fields as usize, |schema| schema.fields.len())
+    }
+
+    fn parse_options(&self) -> ParseOptions<'_> {
+        ParseOptions {
+            fields: self.field_count(),
+            delimiter: self.delimiter,
+            checksum: self.checksum,
+            allow_short: self.allow_short,
+            extra_fields: self.extra_fields,
+            schema: self.schema.as_ref(),
+        }
+    }
+
+    /// Whether records end in `snr` and `rssi` columns, empty for packets without them
+    fn signal_columns(&self) -> bool {
+        self.signal || self.device.profile().reports_signal()
+    }
+
+    /// Whether the records are printed as info lines, as they are without any --output
+    fn console(&self) -> bool {
+        self.output.is_empty() && !self.tui && self.plot.is_none()
+    }
+}
+
+/// Options of the replay subcommand
+#[derive(clap::Args)]
+struct ReplayArgs {
+    #[arg(value_name = "RAW_LOG")]
+    /// File written by --raw-log
+    raw_log: PathBuf,
+    #[arg(value_delimiter = ',')]
+    /// Names of the ports the raw log was captured from, in the order they were given
+    port: Vec<String>,
+    #[command(flatten)]
+    common: Common,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Capture from the ports, as charter does when given ports without a subcommand
+    Listen(Box<Args>),
+    /// Feed a --raw-log through the same parsing and outputs as fast as it can be read, without
+    /// opening a port or sending a command, and exit at its end
+    Replay(Box<ReplayArgs>),
+    /// Rewrite a CSV or JSON Lines file written by charter in other output formats
+    Convert(Box<ConvertArgs>),
+    /// List available serial ports and exit
+    Ports,
+    /// Send raw commands to the module and print its replies
+    Shell {
+        /// Serial port the module is connected to
+        port: String,
+        #[arg(long, value_enum, default_value_t = DeviceKind::Rn2483)]
+        /// Command set spoken by the LoRa module, to stop it receiving on exit
+        device: DeviceKind,
+        #[command(flatten)]
+        serial: SerialArgs,
+    },
+}
+
+const EXIT_CODES: &str = "Exit codes:
+  0  Capture stopped normally
+  1  Unexpected error
+  2  Invalid command line
+  3  --auto found no receiver
+  4  --auto found more than one receiver
+  5  A serial device disappeared or stopped answering during the capture
+  6  A serial port couldn't be opened
+  7  An output couldn't be set up or written to
+
+Ctrl-C, SIGTERM and SIGHUP all stop the radio, finish the outputs and log the summary before
+exiting with 0.";
+
+/// No port matched the --auto USB IDs
+const EXIT_NO_RECEIVER: i32 = 3;
+/// More than one port matched the --auto USB IDs
+const EXIT_AMBIGUOUS_RECEIVER: i32 = 4;
+/// A serial device disappeared and couldn't be reconnected, or its module stopped answering
+const EXIT_DEVICE_LOST: i32 = 5;
+/// A serial port couldn't be opened
+const EXIT_PORT_FAILED: i32 = 6;
+/// An output couldn't be prepared, or failed in a way it can't go on from
+const EXIT_OUTPUT_FAILED: i32 = 7;
+
+/// What the panic hook exits with
+static EXIT_CODE: AtomicI32 = AtomicI32::new(1);
+
+/// Makes the next panic exit with `code` rather than 1, for failures that have one of their own
+fn exit_with(code: i32) {
+    EXIT_CODE.store(code, std::sync::atomic::Ordering::SeqCst);
+}
+
+fn main() {
+    let config = config::load();
+    let cli = Cli::from_arg_matches(&config.matches).unwrap_or_else(|error| error.exit());
+
+    // Stdout is kept for the records
+    let console = log_file::layer(cli.log_format, tui::Logs, true).with_filter(if cli.debug {
+        LevelFilter::TRACE
+    } else {
+        LevelFilter::INFO
+    });
+    let file = log_file::open(&cli.log_file).unwrap_or_else(|error| {
+        Cli::command()
+            .error(clap::error::ErrorKind::Io, error)
+            .exit()
+    });
+    let level = LevelFilter::from(cli.log_file.log_file_level);
+    let file = file.map(|writer| {
+        log_file::layer(cli.log_format, writer, false)
+            .with_filter(filter_fn(move |meta| log_file::wanted(level, meta)))
+    });
+    let subscriber = tracing_subscriber::registry().with(console).with(file);
     tracing::subscriber::set_global_default(subscriber).unwrap();
+    config.log();
 
     std::panic::set_hook(Box::new(|panic| {
+        // Another thread is already cleaning up, and exits for both
+        if PANICKING.swap(true, std::sync::atomic::Ordering::SeqCst) {
+            loop {
+                std::thread::park();
+            }
+        }
+        tui::restore();
         let trace = Backtrace::capture();
         if trace.status() == backtrace::BacktraceStatus::Disabled {
             error!("{panic}");
         } else {
             error!("{panic} {trace}");
         }
-        exit(1);
+        output::finish_after_panic();
+        stop_after_panic();
+        summarize_after_panic();
+        log_file::finish();
+        exit(EXIT_CODE.load(std::sync::atomic::Ordering::SeqCst));
     }));
 
-    let mut serial = serialport::new(&args.port, 115200)
-        .data_bits(DataBits::Eight)
-        .parity(Parity::None)
-        .stop_bits(StopBits::One)
-        .timeout(Duration::from_millis(1000))
-        .open()
-        .unwrap_or_else(|error| panic!("Failed to open {}: {:?}", &args.port, error.kind()));
-
-    let running = serial_begin(&mut serial).expect("Failed to start communication");
-    let r = running.clone();
-    let mut serial_clone = serial.try_clone().unwrap();
-    ctrlc::set_handler(move || {
-        serial_end(&mut serial_clone).unwrap();
-        r.store(false, std::sync::atomic::Ordering::SeqCst);
+    let mut args = match cli.command {
+        None => cli.args,
+        Some(Command::Listen(args)) => *args,
+        Some(Command::Replay(replay)) => Args::replaying(*replay),
+        Some(Command::Convert(convert)) => convert::run(&convert),
+        Some(Command::Ports) => list_ports(),
+        Some(Command::Shell {
+            port,
+            device,
+            serial,
+        }) => shell::run(&port, device.profile(), &serial),
+    };
+    if args.raw {
+        if let Some(ref mut schema) = args.common.schema {
+            schema.strip_transforms();
+        }
+    }
+    // Where the options of the capture are, for telling where the port came from
+    let matches = config
+        .matches
+        .subcommand_matches("listen")
+        .unwrap_or(&config.matches);
+
+    let device = args.device.profile();
+    if let Some(option) = device.unsupported(&args.radio) {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                format!("{option} isn't supported by {} modules", args.device.name()),
+            )
+            .exit();
+    }
+
+    let invalid = match (args.format, &args.schema) {
+        (Format::Binary, None) => Some(String::from("--format binary requires a --schema")),
+        (Format::Binary, Some(schema)) => schema
+            .binary_width()
+            .err()
+            .map(|field| format!("schema field {} has no fixed binary width", field.name)),
+        (Format::Text, _) if !args.header.is_empty() && args.header.len() != args.field_count() => {
+            Some(format!(
+                "--header names {} columns for {} fields",
+                args.header.len(),
+                args.field_count()
+            ))
+        }
+        _ => None,
+    }
+    .or_else(|| {
+        (args.checksum.is_some() && args.format != Format::Text)
+            .then(|| String::from("--checksum only applies to text payloads"))
+    })
+    .or_else(|| {
+        (args.reassemble && args.format != Format::Text)
+            .then(|| String::from("--reassemble only applies to text payloads"))
+    })
+    .or_else(|| {
+        let mut paths: Vec<&Path> = args.files().map(|(_, path)| path).collect();
+        paths.sort();
+        paths
+            .windows(2)
+            .find(|pair| pair[0] == pair[1])
+            .map(|pair| format!("{} is given as --output more than once", pair[0].display()))
+    })
+    .or_else(|| args.unknown_full_rate())
+    .or_else(|| {
+        let sqlite = args
+            .files()
+            .any(|(format, _)| format == OutputFormat::Sqlite);
+        (args.overwrite && sqlite)
+            .then(|| String::from("--overwrite doesn't apply to sqlite output"))
+    })
+    .or_else(|| {
+        let stdout = args
+            .output
+            .iter()
+            .filter(|target| matches!(target, Target::Stdout { .. }));
+        (stdout.count() > 1).then(|| String::from("stdout is given as --output more than once"))
+    })
+    .or_else(|| match args.tui {
+        true if args.stdout().is_some() => Some(String::from(
+            "--tui needs stdout for itself, it can't be an --output too",
+        )),
+        true if !std::io::stdout().is_terminal() => {
+            Some(String::from("--tui needs stdout to be a terminal"))
+        }
+        _ => None,
     })
-    .expect("Failed to set Ctrl-C handler");
-
-    let mut serial_buf: Vec<u8> = vec![0; 1024];
-    let mut line_buf = String::new();
-    let mut index: usize = 0;
-    while running.load(std::sync::atomic::Ordering::SeqCst) {
-        match serial.read(serial_buf.as_mut_slice()) {
-            Ok(n) => {
-                let str = match String::from_utf8(Vec::from(&serial_buf[..n])) {
-                    Ok(str) => str,
-                    Err(error) => {
-                        error!("{error}");
+    .or_else(|| match args.plot {
+        Some(_) if args.stdout().is_some() => Some(String::from(
+            "--plot needs stdout for itself, it can't be an --output too",
+        )),
+        Some(_) if !std::io::stdout().is_terminal() => {
+            Some(String::from("--plot needs stdout to be a terminal"))
+        }
+        _ => None,
+    })
+    .or_else(|| {
+        // Rotation and compression leave the SQLite and Parquet outputs alone
+        let mut text = args
+            .files()
+            .filter(|(format, _)| matches!(format, OutputFormat::Csv | OutputFormat::Jsonl))
+            .peekable();
+        let rotating = args.rotate_every.is_some() || args.rotate_size.is_some();
+        match text.peek() {
+            None if rotating => Some(String::from(
+                "--rotate-every and --rotate-size only apply to csv and jsonl output",
+            )),
+            None if args.compress.is_some() => Some(String::from(
+                "--compress only applies to csv and jsonl output",
+            )),
+            _ if rotating => text.find_map(|(_, path)| rotation::check_pattern(path).err()),
+            _ => None,
+        }
+    })
+    .or_else(|| {
+        let field = args.seq_field?;
+        (field >= args.field_count() && args.format != Format::Json).then(|| {
+            format!(
+                "--seq-field {field} is out of range for {} fields",
+                args.field_count()
+            )
+        })
+    });
+    if let Some(message) = invalid {
+        Cli::command()
+            .error(clap::error::ErrorKind::ArgumentConflict, message)
+            .exit();
+    }
+    // Kept for the rest of the process, so the panic hook can still summarize the run
+    let args: &'static Args = Box::leak(Box::new(args));
+
+    let ports = if args.stdin {
+        vec![String::from("stdin")]
+    } else if args.replay.is_some() {
+        args.port.clone()
+    } else if args.auto {
+        // A port from CHARTER_PORT or the config file shouldn't stop --auto, one given
+        // explicitly should
+        if matches.value_source("port") == Some(ValueSource::CommandLine) && !config.in_file("port")
+        {
+            Cli::command()
+                .error(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    "--auto can't be combined with an explicit port",
+                )
+                .exit();
+        }
+        debug!("Port detected by USB ID");
+        vec![detect_port(usb_ids(args))]
+    } else {
+        match matches.value_source("port") {
+            Some(ValueSource::EnvVariable) => debug!("Port taken from CHARTER_PORT"),
+            _ if config.in_file("port") => debug!("Port taken from the config file"),
+            _ => debug!("Port taken from the command line"),
+        }
+        args.port.clone()
+    };
+
+    match args.extra_fields {
+        ExtraFields::Drop => info!(
+            "Fields beyond the first {} will be dropped",
+            args.field_count()
+        ),
+        ExtraFields::Append => info!(
+            "Fields beyond the first {} will be appended as extra columns",
+            args.field_count()
+        ),
+        ExtraFields::Error => info!(
+            "Payloads with more than {} fields will be skipped",
+            args.field_count()
+        ),
+    }
+
+    // Rows only name their port when there's more than one to tell apart
+    let multiple = ports.len() > 1;
+    // JSON keys without a name given up front get a column the first time they're seen
+    let mut columns = args.column_names();
+    let mut outputs: Vec<Box<dyn Output>> = Vec::new();
+    for (format, path) in args.files() {
+        let header = known_columns(args, &columns, multiple);
+        // Appended extra fields leave the rows of varying width
+        let width_varies = args.extra_fields == ExtraFields::Append;
+        let file = output::open(
+            args,
+            format,
+            path,
+            header,
+            args.schema.as_ref(),
+            width_varies,
+        );
+        outputs.push(args.sampled(file, Some(path)));
+    }
+    if let Some(format) = args.stdout() {
+        let stdout = output::stdout(args, format, known_columns(args, &columns, multiple));
+        outputs.push(args.sampled(stdout, Some(Path::new("-"))));
+    }
+    if let Some(influx) = Influx::new(&args.influx, args.schema.as_ref()) {
+        outputs.push(args.sampled(Box::new(influx), None));
+    }
+    if let Some(mqtt) = Mqtt::new(&args.mqtt) {
+        outputs.push(args.sampled(Box::new(mqtt), None));
+    }
+    if let Some(listen) = Listen::tcp(&args.listen) {
+        outputs.push(args.sampled(Box::new(listen), None));
+    }
+    if let Some(websocket) = Listen::websocket(&args.listen) {
+        outputs.push(args.sampled(Box::new(websocket), None));
+    }
+    if let Some(udp) = Udp::new(&args.udp) {
+        outputs.push(args.sampled(Box::new(udp), None));
+    }
+    if let Some(webhook) = Webhook::new(&args.webhook) {
+        outputs.push(args.sampled(Box::new(webhook), None));
+    }
+    for output in &mut outputs {
+        output.prepare().unwrap_or_else(|error| {
+            exit_with(EXIT_OUTPUT_FAILED);
+            panic!("{error}")
+        });
+    }
+    // The records carry on from those already written, so the indexes stay unique
+    let resume = match args.restart_index {
+        true => 0,
+        false => outputs
+            .iter()
+            .filter_map(|output| output.rows())
+            .max()
+            .unwrap_or(0),
+    };
+    if resume > 0 {
+        info!("Resuming at row {resume}");
+    }
+    output::install(outputs);
+    let policy = args.flush_policy();
+    if let Some(path) = &args.raw_log {
+        capture::open(path, args.raw_log_format, policy, args.durability).unwrap_or_else(|error| {
+            exit_with(EXIT_OUTPUT_FAILED);
+            panic!("{error}")
+        });
+    }
+    if !args.output.is_empty() {
+        info!("Flushing the output {policy}");
+    }
+    if let Some(sample) = args.sample {
+        info!("Writing the records {sample}");
+    }
+    if policy != Flush::EveryRecord {
+        let tick = match policy {
+            Flush::EveryMs(interval) => Duration::from_millis(interval.min(100)),
+            _ => Duration::from_millis(100),
+        };
+        std::thread::spawn(move || loop {
+            std::thread::sleep(tick);
+            output::flush_due(policy);
+        });
+    }
+
+    let shared = Arc::new(Shared {
+        idle: args
+            .idle_warn
+            .map(|secs| Idle::new(Duration::from_secs(secs))),
+        ..Shared::default()
+    });
+    *cleanup() = Some(Cleanup {
+        running: shared.running.clone(),
+        serials: Vec::new(),
+        device,
+        sleep: args.radio.sleep_on_exit,
+        // A reader holds its port for up to a read timeout
+        wait: Duration::from_millis(args.serial.timeout_ms + 100),
+        report: None,
+    });
+    // The ports are read from tokio tasks, which they're registered with as they're opened
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to start the async runtime");
+    let _runtime = runtime.enter();
+    let mut serials = Vec::with_capacity(ports.len());
+    let mut streams = Vec::with_capacity(ports.len());
+    let mut firmware = Vec::with_capacity(ports.len());
+    if args.notify {
+        notify::connect();
+    }
+    alert::configure(&args.alerts);
+    let opens = args.replay.is_none() && !args.stdin;
+    for port in ports.iter().filter(|_| opens) {
+        info!(
+            "Opening {} at {} baud ({})",
+            port,
+            args.serial.baud,
+            args.serial.frame_format()
+        );
+        let (mut serial, native) = wait_for_port(port, args)
+            .unwrap_or_else(|error| open_failed(port, &args.serial, error));
+        let version = device::start_receiver(&mut serial, args.device.profile(), &args.radio)
+            .unwrap_or_else(|error| panic!("Failed to start communication: {error}"));
+        let stream = serial::stream(native)
+            .unwrap_or_else(|error| panic!("Failed to read {port} asynchronously: {error}"));
+        let serial = Arc::new(Mutex::new(serial));
+        if let Some(cleanup) = cleanup().as_mut() {
+            cleanup.serials.push(serial.clone());
+        }
+        serials.push(serial);
+        streams.push(stream);
+        firmware.push(version.unwrap_or_else(|| String::from("unknown")));
+    }
+
+    // A replay adds no run of its own
+    for (_, output) in args.files().filter(|_| args.replay.is_none()) {
+        match run_metadata(args, &ports, &firmware).append_to(output) {
+            Ok(path) => debug!("Appended run metadata to {}", path.display()),
+            Err(error) => tracing::warn!("Failed to write run metadata: {error}"),
+        }
+    }
+
+    if let Some(address) = args.metrics_listen {
+        metrics::serve(address, shared.clone()).unwrap_or_else(|error| panic!("{error}"));
+    }
+    if args.stats_every > 0 {
+        summary::heartbeat(shared.clone(), Duration::from_secs(args.stats_every));
+    }
+    // The radios are stopped once the readers are done with the ports, which they are within a
+    // read timeout of this
+    let stop = {
+        let r = shared.running.clone();
+        move || {
+            notify::stopping();
+            r.store(false, std::sync::atomic::Ordering::SeqCst);
+        }
+    };
+    notify::ready(&format!("Receiving on {}", ports.join(", ")));
+    if let Some(ref idle) = shared.idle {
+        idle.start();
+    }
+
+    let seq_field = args.seq_field();
+    let mut sequences = seq_field.map(|_| Sequences::new(args.seq_modulus));
+    let mut clock = args
+        .schema
+        .as_ref()
+        .and_then(Schema::timestamp)
+        .map(|(_, unit)| Clock::new(unit));
+    let mut dedup = args.dedup.then(|| {
+        Dedup::new(
+            args.dedup_window as usize,
+            Duration::from_millis(args.dedup_age),
+        )
+    });
+    let mut table = match args.display {
+        DisplayMode::Table if args.console() && std::io::stdout().is_terminal() => {
+            Some(Table::new(args.table_header as usize))
+        }
+        DisplayMode::Table if args.console() => {
+            info!("Printing log lines as stdout isn't a terminal");
+            None
+        }
+        _ => None,
+    };
+    let mut reassembly = args
+        .reassemble
+        .then(|| Reassembly::new(Duration::from_millis(args.reassembly_timeout)));
+    let (sender, mut receiver) = queue::bounded(args.channel_depth as usize, args.when_full);
+    let started = Instant::now();
+    let dashboard = args.tui.then(|| Dashboard::start(stop.clone()));
+    let mut plot = args
+        .plot
+        .clone()
+        .map(|field| Plot::new(field, args.plot_window as usize));
+    if let Some(cleanup) = cleanup().as_mut() {
+        cleanup.report = Some(Report {
+            args,
+            shared: shared.clone(),
+            started,
+        });
+    }
+    let finished = runtime.block_on(async {
+        let mut tasks = Vec::new();
+        if let Some(path) = &args.replay {
+            let (ports, shared) = (ports.clone(), shared.clone());
+            tasks.push(tokio::spawn(replay_raw_log(
+                args,
+                path,
+                ports,
+                shared,
+                sender.clone(),
+            )));
+        }
+        if args.stdin {
+            tasks.push(tokio::spawn(read_stdin(
+                args,
+                shared.clone(),
+                sender.clone(),
+            )));
+        }
+        let opened = serials.iter().cloned().zip(streams);
+        for (source, (port, (serial, stream))) in ports.iter().cloned().zip(opened).enumerate() {
+            let source = source as u8;
+            tasks.extend(read_port(
+                args, source, port, serial, stream, &shared, &sender,
+            ));
+        }
+        drop(sender);
+
+        // The sinks take one record at a time, so it's the queue that says how far behind they are
+        let (rows, mut parsed) = mpsc::channel::<Row>(1);
+        // The buffers of the rows written, handed back to be filled again
+        let (spare, mut spares) = mpsc::channel::<(Vec<String>, Vec<u8>)>(2);
+        let parse = tokio::spawn({
+            let shared = shared.clone();
+            async move {
+                let encoding = args.encoding.unwrap_or_else(|| device.encoding());
+                let learn = columns.is_empty();
+                let options = args.parse_options();
+                let mut index: usize = resume;
+                let mut names = Arc::new(record_columns(args, &columns, multiple));
+                let mut named = Arc::new(columns.clone());
+                // Each payload is decoded into the same buffer, which the fields borrow from
+                let mut decoded = Vec::new();
+                let mut derived = Vec::new();
+                while let Some(line) = receiver.recv().await {
+                    // Stray replies like `ok` or `busy` aren't worth a warning
+                    if !device.is_packet(&line.text) && !line.text.contains(char::is_whitespace) {
+                        debug!("Ignoring `{}` from {}", line.text, line.port);
+                        continue;
+                    }
+                    let packet = shared
+                        .packets
+                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    let received = line.received.duration_since(UNIX_EPOCH).unwrap_or_default();
+                    shared.last_packet.store(
+                        received.as_millis() as u64,
+                        std::sync::atomic::Ordering::Relaxed,
+                    );
+                    if let Some(rssi) = line.rssi {
+                        shared
+                            .last_rssi
+                            .store(rssi.into(), std::sync::atomic::Ordering::Relaxed);
+                    }
+                    shared
+                        .intervals
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .observe(line.received);
+                    if let Err(error) =
+                        get_data(device, encoding, &line.text, &shared.resyncs, &mut decoded)
+                    {
+                        let counter = match error {
+                            GetDataError::IrregularMessage(_) => Some(&shared.irregular),
+                            GetDataError::Decode { .. } => Some(&shared.decode_failures),
+                            _ => None,
+                        };
+                        if let Some(counter) = counter {
+                            counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        }
+                        shared
+                            .parse_errors
+                            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        tracing::warn!(port = line.port, packet, kind = error.kind(), "{error}");
                         continue;
                     }
+                    let payload = match reassembly.as_mut() {
+                        Some(reassembly) => match std::str::from_utf8(&decoded) {
+                            Ok(text) => {
+                                match reassembly.add(&line.port, text.to_string(), args.delimiter) {
+                                    Some(text) => Cow::Owned(text.into_bytes()),
+                                    None => continue,
+                                }
+                            }
+                            Err(error) => {
+                                tracing::warn!(port = line.port, packet, kind = "utf8", "{error}");
+                                continue;
+                            }
+                        },
+                        None => Cow::Borrowed(decoded.as_slice()),
+                    };
+                    let parsed = match (args.format, &args.schema) {
+                        (Format::Binary, Some(schema)) => parse_binary(&payload, schema),
+                        (Format::Json, schema) => std::str::from_utf8(&payload)
+                            .map_err(Into::into)
+                            .and_then(|text| {
+                                parse_json(text, schema.as_ref(), &mut columns, learn)
+                            }),
+                        _ => std::str::from_utf8(&payload)
+                            .map_err(Into::into)
+                            .and_then(|text| parse_data(text, &options)),
+                    };
+                    let mut data = match parsed {
+                        Ok(data) => data,
+                        Err(error) => {
+                            match error {
+                                ParseError::Data(GetDataError::FieldCount {
+                                    found,
+                                    expected,
+                                    ..
+                                }) if found < expected => {
+                                    shared
+                                        .truncated
+                                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                                }
+                                ParseError::Data(GetDataError::ChecksumMismatch { .. }) => {
+                                    shared
+                                        .checksum_failures
+                                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                                }
+                                _ => (),
+                            }
+                            shared
+                                .parse_errors
+                                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                            tracing::warn!(
+                                port = line.port,
+                                packet,
+                                kind = error.kind(),
+                                "{error}"
+                            );
+                            continue;
+                        }
+                    };
+                    if let Some(ref idle) = shared.idle {
+                        idle.parsed();
+                    }
+                    if let Some(ref schema) = args.schema {
+                        let valid = schema.check_gps(&data);
+                        if schema.gps_valid {
+                            derived.push(valid.unwrap_or(true).to_string());
+                        }
+                        if let (Some(clock), Some((field, _))) = (&mut clock, schema.timestamp()) {
+                            let value = data.get(field).map(AsRef::as_ref).unwrap_or_default();
+                            derived.push(clock.absolute(value, line.received));
+                        }
+                        let at = schema.fields.len().min(data.len());
+                        data.splice(at..at, derived.drain(..).map(Cow::Owned));
+                    }
+                    if let Some(ref mut dedup) = dedup {
+                        let key = match seq_field.and_then(|field| data.get(field)) {
+                            Some(sequence) => sequence.to_string(),
+                            None => data.join(" "),
+                        };
+                        if dedup.is_duplicate(key) {
+                            debug!("Skipping repeated packet {data:?} from {}", line.port);
+                            shared
+                                .duplicates
+                                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                            continue;
+                        }
+                    }
+                    if let (Some(sequences), Some(field)) = (&mut sequences, seq_field) {
+                        match data.get(field).map(|value| value.parse()) {
+                            Some(Ok(value)) => sequences.observe(&line.port, value),
+                            _ => debug!("No sequence number in field {field}"),
+                        }
+                    }
+                    let snr = line.snr.map(|snr| snr.to_string()).unwrap_or_default();
+                    let rssi = line.rssi.map(|rssi| rssi.to_string()).unwrap_or_default();
+                    let (mut values, mut buffer) = spares.try_recv().unwrap_or_default();
+                    values.clear();
+                    if multiple {
+                        values.push(line.port.clone());
+                    }
+                    let fields = values.len()..values.len() + data.len();
+                    values.extend(data.into_iter().map(Cow::into_owned));
+                    if args.signal_columns() {
+                        values.extend([snr, rssi]);
+                    }
+                    // Only JSON keys seen for the first time change the columns
+                    if columns.len() != named.len() {
+                        names = Arc::new(record_columns(args, &columns, multiple));
+                        named = Arc::new(columns.clone());
+                    }
+                    buffer.clear();
+                    buffer.extend_from_slice(&payload);
+                    let row = Row {
+                        index,
+                        payload: buffer,
+                        values,
+                        fields,
+                        names: names.clone(),
+                        columns: named.clone(),
+                        line,
+                    };
+                    if rows.send(row).await.is_err() {
+                        break;
+                    }
+                    index += 1;
+                }
+                (reassembly, sequences, clock)
+            }
+        });
+
+        // Writing the records blocks on the files, so it has a thread of its own
+        let sink = tokio::task::spawn_blocking({
+            let shared = shared.clone();
+            move || {
+                let unchecked = match args.radio.crc {
+                    Some(Switch::Off) => " without CRC",
+                    _ => "",
                 };
-                line_buf.write_str(&str).unwrap();
-                while let Some(pos) = line_buf.find("\r\n") {
-                    let line = line_buf[..pos].trim_end().to_string();
-                    line_buf.clear();
-                    match get_data(line) {
-                        Ok(data) => {
-                            if let Ok(data) = parse_data(data) {
-                                match args.output {
-                                    Some(ref output) => {
-                                        match write_csv(&data, output, args.create) {
-                                            Ok(_) => debug!(
-                                                "Written {:?} to {} ({})",
-                                                &data,
-                                                &output.display(),
-                                                index
-                                            ),
-                                            Err(error) => {
-                                                if let Some(io_error) =
-                                                    error.downcast_ref::<std::io::Error>()
-                                                {
-                                                    match io_error.kind() {
-                                                        ErrorKind::NotFound => panic!("{error}"),
-                                                        _ => error!("{error}"),
-                                                    }
-                                                } else {
-                                                    error!("{error}");
-                                                }
-                                            }
-                                        };
+                let derived = args
+                    .schema
+                    .as_ref()
+                    .map(Schema::derived_columns)
+                    .unwrap_or_default();
+                while let Some(row) = parsed.blocking_recv() {
+                    let Row {
+                        index,
+                        ref line,
+                        ref payload,
+                        ref names,
+                        ref columns,
+                        ..
+                    } = row;
+                    let record: Vec<&str> = row.values.iter().map(String::as_str).collect();
+                    if index == resume {
+                        alert::fire(
+                            Event::FirstPacket,
+                            &format!("first packet from {}", line.port),
+                        );
+                    }
+                    shared
+                        .records
+                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    let mut fatal = None;
+                    let mut failed = false;
+                    let mut outputs = output::lock();
+                    for output in outputs.iter_mut() {
+                        match output.write(&Record {
+                            index,
+                            received: line.received,
+                            payload,
+                            columns: names,
+                            values: &record,
+                        }) {
+                            Ok(_) => debug!(
+                                port = line.port,
+                                index,
+                                "Written {:?}{unchecked} to {} ({})",
+                                &record,
+                                output.name(),
+                                index
+                            ),
+                            Err(error) => {
+                                failed = true;
+                                shared
+                                    .write_errors
+                                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                                match output::is_fatal(&*error) {
+                                    true => fatal = Some(error),
+                                    false => {
+                                        error!(port = line.port, index, kind = "write", "{error}")
                                     }
-                                    None => info!("{index}: {data:?}"),
                                 }
-
-                                index += 1;
-                            };
+                            }
                         }
-                        Err(error) => tracing::warn!("{error}"),
+                    }
+                    fatal = fatal.or_else(|| output::written(&mut outputs, policy));
+                    drop(outputs);
+                    if !failed {
+                        shared
+                            .written
+                            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    }
+                    if let Some(error) = fatal {
+                        exit_with(EXIT_OUTPUT_FAILED);
+                        panic!("{error}");
+                    }
+                    let received = match args.received_at {
+                        ReceivedAt::Off => String::new(),
+                        _ => format!(" {}", clock::timestamp(line.received)),
                     };
+                    if let Some(ref dashboard) = dashboard {
+                        let fields = &row.values[row.fields.clone()];
+                        dashboard.update(columns, &derived, fields, line.snr, line.rssi);
+                    }
+                    if let Some(ref mut plot) = plot {
+                        plot.add(names, &record, multiple as usize);
+                    }
+                    let data = &row.values[row.fields.clone()];
+                    match (args.console(), table.as_mut()) {
+                        (false, _) => (),
+                        (true, Some(table)) => {
+                            let time = chrono::DateTime::<chrono::Utc>::from(line.received)
+                                .format("%H:%M:%S%.3f")
+                                .to_string();
+                            let index = index.to_string();
+                            let names: Vec<String> = ["index", "received"]
+                                .into_iter()
+                                .map(String::from)
+                                .chain(names.iter().cloned())
+                                .collect();
+                            let values: Vec<&str> = [index.as_str(), time.as_str()]
+                                .into_iter()
+                                .chain(record.iter().copied())
+                                .collect();
+                            table.print(&names, &values);
+                        }
+                        (true, None) if multiple => info!(
+                            "{index}{received} ({}): {}{}{unchecked}",
+                            line.port,
+                            describe(data, columns, &derived),
+                            signal(line.snr, line.rssi)
+                        ),
+                        (true, None) => info!(
+                            "{index}{received}: {}{}{unchecked}",
+                            describe(data, columns, &derived),
+                            signal(line.snr, line.rssi)
+                        ),
+                    }
+                    // Dropped instead once there are spares enough
+                    let _ = spare.try_send((row.values, row.payload));
                 }
+                plot
+            }
+        });
+
+        // Stopping leaves the readers to finish within a read timeout, and the rest to drain what
+        // they've framed by then
+        let mut finished = pin!(async {
+            for task in tasks {
+                task.await.unwrap();
             }
-            Err(ref error) if error.kind() == ErrorKind::TimedOut => (),
-            Err(ref error) if error.kind() == ErrorKind::Interrupted => {
-                exit(0);
+            (parse.await.unwrap(), sink.await.unwrap())
+        });
+        tokio::select! {
+            finished = &mut finished => finished,
+            () = shutdown() => {
+                stop();
+                finished.await
             }
-            Err(error) => panic!("{}", error),
         }
+    });
+    let ((reassembly, sequences, clock), plot) = finished;
+
+    notify::stopping();
+    // The readers have stopped, so nothing else writes to the ports
+    stop_radios(&serials, device, args.radio.sleep_on_exit);
+    output::finish_all();
+    let lost = shared.lost.load(std::sync::atomic::Ordering::SeqCst);
+    if lost {
+        error!(
+            "Serial device lost after {} records",
+            shared.records.load(std::sync::atomic::Ordering::Relaxed)
+        );
+    }
+    tui::restore();
+    if let Some(ref plot) = plot {
+        plot.finish();
+    }
+    let report = cleanup().as_mut().and_then(|cleanup| cleanup.report.take());
+    if let Some(report) = report {
+        report.log();
+    }
+    if let Some(ref reassembly) = reassembly {
+        reassembly.log_summary();
+    }
+    if let Some(ref sequences) = sequences {
+        sequences.log_summary();
+    }
+    if let Some(ref clock) = clock {
+        clock.log_summary();
+    }
+    log_file::finish();
+    if lost {
+        exit(EXIT_DEVICE_LOST);
+    }
+}
+
+/// State shared between the reader threads, the Ctrl-C handler and the main thread
+struct Shared {
+    running: Arc<AtomicBool>,
+    /// Set when a device disappeared for good
+    lost: AtomicBool,
+    /// `radio_err` replies, i.e. receive watchdog timeouts and CRC failures
+    radio_errors: AtomicU64,
+    /// `busy` replies to arming receive
+    busy: AtomicU64,
+    /// Payloads skipped for missing fields
+    truncated: AtomicU64,
+    /// Payloads skipped for failing --checksum
+    checksum_failures: AtomicU64,
+    /// Packets skipped by --dedup
+    duplicates: AtomicU64,
+    /// Corrupted lines in which a later packet was found
+    resyncs: AtomicU64,
+    /// Packet lines received, whether or not they made it into a record
+    packets: AtomicU64,
+    /// Packets whose payload couldn't be decoded or parsed
+    parse_errors: AtomicU64,
+    /// Records made from the packets and passed on to the outputs
+    records: AtomicU64,
+    /// Records that every output took without failing
+    written: AtomicU64,
+    /// Lines that looked like packets but weren't
+    irregular: AtomicU64,
+    /// Payloads that weren't valid in the encoding
+    decode_failures: AtomicU64,
+    bytes_read: AtomicU64,
+    /// Failed writes to any output
+    write_errors: AtomicU64,
+    /// Received lines dropped for the output falling behind
+    dropped: AtomicU64,
+    /// Receive time of the last packet in ms since the epoch, 0 before the first
+    last_packet: AtomicU64,
+    /// RSSI of the last packet that had one, `i64::MIN` before that
+    last_rssi: AtomicI64,
+    /// Watches for silence with --idle-warn
+    idle: Option<Idle>,
+    /// Time between consecutive packets, for the summary
+    intervals: Mutex<Intervals>,
+}
+
+impl Default for Shared {
+    fn default() -> Self {
+        Shared {
+            running: Arc::new(AtomicBool::new(true)),
+            lost: AtomicBool::new(false),
+            radio_errors: AtomicU64::new(0),
+            busy: AtomicU64::new(0),
+            truncated: AtomicU64::new(0),
+            checksum_failures: AtomicU64::new(0),
+            duplicates: AtomicU64::new(0),
+            resyncs: AtomicU64::new(0),
+            packets: AtomicU64::new(0),
+            parse_errors: AtomicU64::new(0),
+            records: AtomicU64::new(0),
+            written: AtomicU64::new(0),
+            irregular: AtomicU64::new(0),
+            decode_failures: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            write_errors: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            last_packet: AtomicU64::new(0),
+            last_rssi: AtomicI64::new(i64::MIN),
+            idle: None,
+            intervals: Mutex::new(Intervals::default()),
+        }
+    }
+}
+
+/// Formats the fields for the console, by name when the columns are known, which the `derived`
+/// ones the schema splices in after the fields follow
+fn describe(data: &[String], columns: &[String], derived: &[String]) -> String {
+    if columns.is_empty() {
+        return format!("{data:?}");
     }
+    let fields: Vec<String> = columns
+        .iter()
+        .chain(derived)
+        .zip(data)
+        .map(|(column, value)| format!("{column}={value}"))
+        .collect();
+    format!("[{}]", fields.join(", "))
 }
 
-fn serial_begin(serial: &mut Box<dyn SerialPort>) -> Result<Arc<AtomicBool>, serialport::Error> {
-    info!("Starting serial communication...");
-    serial.write_all("radio rx 0\r\n".as_bytes())?;
-    Ok(Arc::new(AtomicBool::new(true)))
+/// Describes the packet's signal quality for the console, if it was queried
+fn signal(snr: Option<i8>, rssi: Option<i16>) -> String {
+    let format = |value: Option<String>| value.unwrap_or_else(|| String::from("?"));
+    match (snr, rssi) {
+        (None, None) => String::new(),
+        (snr, rssi) => format!(
+            " (SNR {} dB, RSSI {} dBm)",
+            format(snr.map(|snr| snr.to_string())),
+            format(rssi.map(|rssi| rssi.to_string()))
+        ),
+    }
+}
+
+/// Stops receiving on every port, carrying on past ports that are already gone
+fn stop_radios(
+    serials: &[Arc<Mutex<Box<dyn SerialPort>>>],
+    device: &dyn Device,
+    sleep: Option<u32>,
+) {
+    for serial in serials {
+        if let Err(error) = device.end(&mut serial.lock().unwrap(), sleep) {
+            error!("Failed to stop the radio: {error}");
+        }
+    }
 }
 
-fn serial_end(serial: &mut Box<dyn SerialPort>) -> Result<(), serialport::Error> {
-    Ok(serial.write_all("radio rxstop\r\n".as_bytes())?)
+/// What the panic hook needs to stop the radios
+struct Cleanup {
+    running: Arc<AtomicBool>,
+    /// The ports whose radio is receiving
+    serials: Vec<Arc<Mutex<Box<dyn SerialPort>>>>,
+    device: &'static dyn Device,
+    sleep: Option<u32>,
+    /// How long a port may be held by its reader
+    wait: Duration,
+    /// What the summary is made of, once the capture has started and until it's logged
+    report: Option<Report>,
 }
 
-#[derive(Debug)]
-enum GetDataError {
-    IrregularMessage(&'static str),
-    ParseError(&'static str),
+/// The run so far, to summarize however it ends
+struct Report {
+    args: &'static Args,
+    shared: Arc<Shared>,
+    started: Instant,
 }
 
-impl Display for GetDataError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            GetDataError::IrregularMessage(msg) => write!(f, "Irregular message: {}", msg),
-            GetDataError::ParseError(msg) => write!(f, "Error while parsing data: {}", msg),
+impl Report {
+    /// Logs the summary, then writes it next to the outputs with --summary-json
+    fn log(self) {
+        let summary = Summary::new(self.args, &self.shared, self.started.elapsed());
+        summary.log();
+        for (_, output) in self.args.files().filter(|_| self.args.summary_json) {
+            match summary.write_next_to(output) {
+                Ok(path) => debug!("Wrote the summary to {}", path.display()),
+                Err(error) => tracing::warn!("Failed to write the summary: {error}"),
+            }
         }
     }
 }
 
-impl Error for GetDataError {}
+static CLEANUP: Mutex<Option<Cleanup>> = Mutex::new(None);
+
+/// Set once a thread panics, so the hook only runs once
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+fn cleanup() -> MutexGuard<'static, Option<Cleanup>> {
+    CLEANUP.lock().unwrap_or_else(PoisonError::into_inner)
+}
 
-fn get_data(line: String) -> Result<String, Box<dyn Error>> {
-    let mut message = line.split_whitespace();
-    if message.clone().count() != 2 {
-        debug!("{line}");
-        return Err(Box::new(GetDataError::IrregularMessage(
-            "this line doesn't contain any data",
-        )));
+/// Stops the radios from the panic hook or a failed open, unless the capture is already stopping
+///
+/// Nothing here may panic or wait on a lock for longer than a reader holds it, as the panicking
+/// thread might hold it.
+fn stop_after_panic() {
+    let cleanup = match CLEANUP.try_lock() {
+        Ok(cleanup) => cleanup,
+        Err(TryLockError::Poisoned(cleanup)) => cleanup.into_inner(),
+        Err(TryLockError::WouldBlock) => return,
     };
-    let data = match message.nth(1) {
-        Some(data) => data,
-        None => {
-            debug!("{line}");
-            return Err(Box::new(GetDataError::ParseError(
-                "failed to retrieve data",
-            )));
+    let Some(cleanup) = cleanup.as_ref() else {
+        return;
+    };
+    if !cleanup
+        .running
+        .swap(false, std::sync::atomic::Ordering::SeqCst)
+    {
+        return;
+    }
+    for serial in &cleanup.serials {
+        // A reader holds the port for a read at most, and won't take it again once `running` is
+        // cleared
+        let deadline = Instant::now() + cleanup.wait;
+        let locked = loop {
+            match serial.try_lock() {
+                Ok(serial) => break Some(serial),
+                Err(TryLockError::Poisoned(serial)) => break Some(serial.into_inner()),
+                Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(10))
+                }
+                Err(TryLockError::WouldBlock) => break None,
+            }
+        };
+        let Some(mut serial) = locked else {
+            error!("Failed to stop a radio, its port is in use");
+            continue;
+        };
+        if let Err(error) = cleanup.device.end(&mut serial, cleanup.sleep) {
+            error!("Failed to stop the radio: {error}");
         }
+    }
+}
+
+/// Logs the summary from the panic hook, unless the run has already been summarized
+fn summarize_after_panic() {
+    let report = match CLEANUP.try_lock() {
+        Ok(mut cleanup) => cleanup.as_mut().and_then(|cleanup| cleanup.report.take()),
+        Err(TryLockError::Poisoned(cleanup)) => cleanup
+            .into_inner()
+            .as_mut()
+            .and_then(|cleanup| cleanup.report.take()),
+        Err(TryLockError::WouldBlock) => None,
     };
-    Ok(String::from_utf8(hex::decode(data)?)?)
+    if let Some(report) = report {
+        report.log();
+    }
+}
+
+/// A record on its way from the parse task to the sinks
+struct Row {
+    index: usize,
+    line: Line,
+    /// The payload as decoded from the packet, before it was parsed
+    payload: Vec<u8>,
+    /// The port if there are several, the fields, then the signal if there are columns for it
+    values: Vec<String>,
+    /// Where the fields are among the values
+    fields: std::ops::Range<usize>,
+    names: Arc<Vec<String>>,
+    /// The names of the fields alone, for the console
+    columns: Arc<Vec<String>>,
+}
+
+/// Keeps count of what happens to the lines from one source
+struct Forward {
+    shared: Arc<Shared>,
+    /// What the bytes are captured as in the raw log, none when replaying it
+    source: Option<u8>,
+}
+
+impl Link for Forward {
+    fn read(&self, bytes: &[u8], received: SystemTime) {
+        self.shared
+            .bytes_read
+            .fetch_add(bytes.len() as u64, std::sync::atomic::Ordering::Relaxed);
+        if let Some(source) = self.source {
+            capture::write(source, received, bytes);
+        }
+    }
+
+    fn stopped(&self, reply: Stopped) {
+        let counter = match reply {
+            Stopped::RadioErr => &self.shared.radio_errors,
+            Stopped::Busy => &self.shared.busy,
+        };
+        counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn dropped(&self, line: &Line) {
+        let dropped = self
+            .shared
+            .dropped
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+            + 1;
+        tracing::warn!(
+            port = line.port,
+            kind = "dropped",
+            "Output is falling behind, dropped {dropped} lines so far, the last from {}",
+            line.port
+        );
+    }
+
+    fn lost(&self, port: &str, reason: &str) {
+        alert::fire(Event::PortLost, &format!("{port}: {reason}"));
+    }
+}
+
+/// A port as its read task sees it
+struct Port {
+    args: &'static Args,
+    shared: Arc<Shared>,
+    /// Where the commands go, which a reconnect puts the new port in
+    serial: Arc<Mutex<Box<dyn SerialPort>>>,
 }
 
-fn parse_data(line: String) -> Result<[String; 11], Box<dyn Error>> {
-    let mut data: [String; 11] = [const { String::new() }; 11];
-    for (index, item) in line.split_whitespace().take(11).enumerate() {
-        data[index] = item.to_string();
+impl Source for Port {
+    type Stream = Stream;
+
+    fn alive(&self) {
+        notify::watchdog();
+        if let Some(ref idle) = self.shared.idle {
+            idle.tick();
+        }
+    }
+
+    fn lost(&self, port: &str, reason: &str) {
+        alert::fire(Event::PortLost, &format!("{port}: {reason}"));
+    }
+
+    fn reconnect(&self, port: String) -> impl Future<Output = Option<(String, Stream)>> + Send {
+        let (args, shared, serial) = (self.args, self.shared.clone(), self.serial.clone());
+        async move {
+            let reconnected = tokio::task::spawn_blocking(move || {
+                let mut port = port;
+                let (new_serial, stream) = reconnect(args, &mut port, &shared.running)?;
+                *serial.lock().unwrap() = new_serial;
+                Some((port, stream))
+            });
+            reconnected.await.unwrap()
+        }
     }
-    Ok(data)
 }
 
-fn write_csv(data: &[String; 11], path: &PathBuf, create: bool) -> Result<(), Box<dyn Error>> {
-    let file = std::fs::OpenOptions::new()
-        .append(true)
-        .create(create)
-        .open(path)?;
+/// Reads one port and frames what's read, each in a task of its own, stopping the other
+/// readers if it's lost so what's left can be drained before exiting
+fn read_port(
+    args: &'static Args,
+    source: u8,
+    port: String,
+    serial: Arc<Mutex<Box<dyn SerialPort>>>,
+    stream: Stream,
+    shared: &Arc<Shared>,
+    sender: &queue::Sender<Line>,
+) -> [tokio::task::JoinHandle<()>; 2] {
+    let forward = Forward {
+        shared: shared.clone(),
+        source: Some(source),
+    };
+    let running = shared.running.clone();
+    let (receive, serial_for_reads) = (args.receive(), serial.clone());
+    let reader = Reader::new(
+        receive,
+        port.clone(),
+        Some(serial),
+        running,
+        sender.clone(),
+        forward,
+    );
+    let (reads, received) = mpsc::channel(16);
+    let framing = tokio::spawn({
+        let shared = shared.clone();
+        async move {
+            if receiver::frame(reader, received).await == Ended::Lost {
+                lose(&shared);
+            }
+        }
+    });
+    let reading = tokio::spawn({
+        let shared = shared.clone();
+        async move {
+            let source = Port {
+                args,
+                shared: shared.clone(),
+                serial: serial_for_reads,
+            };
+            let running = &*shared.running;
+            let read = receiver::read_port(&source, port, stream, receive, running, &reads);
+            match read.await {
+                Ok(Ended::Lost) => lose(&shared),
+                Ok(_) => (),
+                Err(error) => panic!("{error}"),
+            }
+        }
+    });
+    [reading, framing]
+}
+
+/// Takes note of a port that's gone for good and stops the capture
+fn lose(shared: &Shared) {
+    shared.lost.store(true, std::sync::atomic::Ordering::SeqCst);
+    shared
+        .running
+        .store(false, std::sync::atomic::Ordering::SeqCst);
+}
+
+/// Waits for Ctrl-C, or a signal or console event that ends the process otherwise
+async fn shutdown() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to set SIGTERM handler");
+        let mut hangup = signal(SignalKind::hangup()).expect("Failed to set SIGHUP handler");
+        tokio::select! {
+            interrupted = tokio::signal::ctrl_c() => {
+                interrupted.expect("Failed to set Ctrl-C handler")
+            }
+            _ = terminate.recv() => (),
+            _ = hangup.recv() => (),
+        }
+    }
+    #[cfg(windows)]
+    {
+        use tokio::signal::windows::{ctrl_close, ctrl_shutdown};
+        let mut close = ctrl_close().expect("Failed to set the console close handler");
+        let mut shutdown = ctrl_shutdown().expect("Failed to set the shutdown handler");
+        tokio::select! {
+            interrupted = tokio::signal::ctrl_c() => {
+                interrupted.expect("Failed to set Ctrl-C handler")
+            }
+            _ = close.recv() => (),
+            _ = shutdown.recv() => (),
+        }
+    }
+}
 
-    let buf_writer = BufWriter::new(file);
-    let mut writer = Writer::from_writer(buf_writer);
-    writer.write_record(data)?;
-    writer.flush()?;
-    Ok(())
+/// Frames the lines captured in a raw log as if they came from the ports, as fast as it can be
+/// read, with chunks from the nth port named by the nth of `ports`
+async fn replay_raw_log(
+    args: &'static Args,
+    path: &'static Path,
+    ports: Vec<String>,
+    shared: Arc<Shared>,
+    sender: queue::Sender<Line>,
+) {
+    let chunks = capture::chunks(path).unwrap_or_else(|error| panic!("{error}"));
+    // The log is read where blocking on it holds up no task
+    let (read, mut received) = mpsc::channel(16);
+    tokio::task::spawn_blocking(move || {
+        for chunk in chunks {
+            if read.blocking_send(chunk).is_err() {
+                return;
+            }
+        }
+    });
+    let mut readers: Vec<Option<Reader<Forward>>> = Vec::new();
+    while let Some(chunk) = received.recv().await {
+        if !shared.running.load(std::sync::atomic::Ordering::SeqCst) {
+            break;
+        }
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(error) => {
+                tracing::warn!("Stopped replaying {}: {error}", path.display());
+                break;
+            }
+        };
+        let source = chunk.source as usize;
+        if readers.len() <= source {
+            readers.resize_with(source + 1, || None);
+        }
+        let reader = readers[source].get_or_insert_with(|| {
+            let port = match ports.get(source) {
+                Some(port) => port.clone(),
+                None if source == 0 => path.display().to_string(),
+                None => format!("{}#{source}", path.display()),
+            };
+            let forward = Forward {
+                shared: shared.clone(),
+                source: None,
+            };
+            let running = shared.running.clone();
+            Reader::new(args.receive(), port, None, running, sender.clone(), forward)
+        });
+        // Lines from a log without times are received now, as the live run can't be known
+        let received = chunk.received.unwrap_or_else(SystemTime::now);
+        if reader.read(&chunk.bytes, received).await.is_err() {
+            return;
+        }
+    }
+    for reader in readers.iter_mut().flatten() {
+        if reader.finish().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Frames lines from stdin until it ends, as if they came from a port that's never written to
+async fn read_stdin(args: &'static Args, shared: Arc<Shared>, sender: queue::Sender<Line>) {
+    // A read that never returns mustn't hold up the shutdown, so it's left to a thread that
+    // isn't waited for
+    let (chunks, mut received_chunks) = mpsc::channel(16);
+    std::thread::spawn(move || {
+        let mut stdin = std::io::stdin().lock();
+        let mut buffer: Vec<u8> = vec![0; 1024];
+        loop {
+            let chunk = match stdin.read(&mut buffer) {
+                Ok(0) => return,
+                Ok(n) => Ok(buffer[..n].to_vec()),
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => Err(error),
+            };
+            if chunks.blocking_send(chunk).is_err() {
+                return;
+            }
+        }
+    });
+    let forward = Forward {
+        shared: shared.clone(),
+        source: Some(0),
+    };
+    let (port, running) = (String::from("stdin"), shared.running.clone());
+    let mut reader = Reader::new(args.receive(), port, None, running, sender, forward);
+    while shared.running.load(std::sync::atomic::Ordering::SeqCst) {
+        if let Some(ref idle) = shared.idle {
+            idle.tick();
+        }
+        let next = tokio::time::timeout(Duration::from_millis(100), received_chunks.recv());
+        let bytes = match next.await {
+            Ok(Some(Ok(bytes))) => bytes,
+            Ok(Some(Err(error))) => panic!("Failed to read stdin: {error}"),
+            Err(_) => continue,
+            Ok(None) => break,
+        };
+        if reader.read(&bytes, SystemTime::now()).await.is_err() {
+            return;
+        }
+    }
+    let _ = reader.finish().await;
+}
+
+/// Says why the port couldn't be opened and exits, which is no bug and gets no backtrace
+fn open_failed(port: &str, serial: &SerialArgs, error: serialport::Error) -> ! {
+    // Whether the port is likely the wrong one, so that the ones there are are worth listing
+    let (problem, list) = match error.kind() {
+        // Ports are opened exclusively, so a second capture or shell fails with EBUSY
+        serialport::ErrorKind::Unknown if error.description.contains("busy") => (
+            String::from("the port is in use (is another charter instance running?)"),
+            false,
+        ),
+        serialport::ErrorKind::InvalidInput | serialport::ErrorKind::Unknown
+            if serial.flow_control == FlowControlArg::Hardware =>
+        {
+            (
+                format!("the adapter doesn't support hardware flow control ({error})"),
+                false,
+            )
+        }
+        serialport::ErrorKind::InvalidInput => (
+            format!("the port rejected {} baud ({error})", serial.baud),
+            false,
+        ),
+        serialport::ErrorKind::Io(ErrorKind::PermissionDenied) => (
+            String::from(
+                "permission denied (on Linux, add your user to the dialout group and log in again)",
+            ),
+            false,
+        ),
+        serialport::ErrorKind::NoDevice | serialport::ErrorKind::Io(ErrorKind::NotFound) => {
+            (String::from("there's no such port"), true)
+        }
+        _ => (error.to_string(), true),
+    };
+    error!("Failed to open {port}: {problem}");
+    if list {
+        match serialport::available_ports() {
+            Ok(ports) if ports.is_empty() => error!("No serial ports found"),
+            Ok(ports) => {
+                info!("The serial ports there are:");
+                ports::print_ports(&ports);
+            }
+            Err(error) => debug!("Failed to enumerate serial ports: {error}"),
+        }
+    }
+    // Ports opened before this one have their radio armed
+    output::finish_all();
+    stop_after_panic();
+    log_file::finish();
+    exit(EXIT_PORT_FAILED);
+}
+
+/// Opens the port, giving a handle to set the module up and send it commands along with the
+/// port to read it from
+fn open_receiver(port: &str, args: &Args) -> Result<Opened, serialport::Error> {
+    let native = args.serial.open_native(port)?;
+    Ok((Box::new(native.try_clone_native()?), native))
+}
+
+/// A handle to the port for the commands and the port as its platform type, for reading it
+type Opened = (Box<dyn SerialPort>, NativePort);
+
+fn wait_for_port(port: &str, args: &Args) -> Result<Opened, serialport::Error> {
+    let Some(max_wait) = args.radio.wait_for_port else {
+        return open_receiver(port, args);
+    };
+    let deadline = max_wait.map(|secs| Instant::now() + Duration::from_secs(secs));
+    let mut delay = Duration::from_millis(100);
+    let mut attempt = 1;
+    loop {
+        match open_receiver(port, args) {
+            Ok(opened) => return Ok(opened),
+            // Waiting won't fix permissions
+            Err(error)
+                if error.kind() == serialport::ErrorKind::Io(ErrorKind::PermissionDenied) =>
+            {
+                return Err(error)
+            }
+            Err(error) => {
+                if deadline.is_some_and(|deadline| Instant::now() + delay > deadline) {
+                    return Err(error);
+                }
+                info!("Waiting for {port} (attempt {attempt}): {error}");
+                std::thread::sleep(delay);
+                delay = (delay * 2).min(Duration::from_secs(5));
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Opens the port again and sets the module up, for as many attempts as --reconnect allows
+///
+/// Blocks for the delays between the attempts, so it's run where it holds up no task.
+fn reconnect(
+    args: &Args,
+    port: &mut String,
+    running: &AtomicBool,
+) -> Option<(Box<dyn SerialPort>, Stream)> {
+    let lost = Instant::now();
+    for attempt in 1..=args.radio.reconnect {
+        std::thread::sleep(Duration::from_millis(args.radio.reconnect_delay));
+        if !running.load(std::sync::atomic::Ordering::SeqCst) {
+            return None;
+        }
+        if args.auto {
+            match ports::candidates(usb_ids(args)) {
+                Ok(candidates) if candidates.len() == 1 => {
+                    candidates[0].port_name.clone_into(port);
+                }
+                _ => {
+                    debug!(
+                        "Reconnect attempt {attempt}/{}: no unique receiver",
+                        args.radio.reconnect
+                    );
+                    continue;
+                }
+            }
+        }
+        match open_receiver(port, args) {
+            Ok((mut serial, native)) => {
+                let started =
+                    device::start_receiver(&mut serial, args.device.profile(), &args.radio)
+                        .and_then(|_| Ok(serial::stream(native)?));
+                match started {
+                    Ok(stream) => {
+                        info!(
+                            "Reconnected to {port} after {:.1?} ({attempt} attempts)",
+                            lost.elapsed()
+                        );
+                        return Some((serial, stream));
+                    }
+                    Err(error) => debug!(
+                        "Reconnect attempt {attempt}/{}: {error}",
+                        args.radio.reconnect
+                    ),
+                }
+            }
+            Err(error) => debug!(
+                "Reconnect attempt {attempt}/{}: {error}",
+                args.radio.reconnect
+            ),
+        }
+    }
+    None
+}
+
+fn usb_ids(args: &Args) -> &[(u16, u16)] {
+    if args.usb_ids.is_empty() {
+        &ports::RN2483_BRIDGES
+    } else {
+        &args.usb_ids
+    }
+}
+
+fn list_ports() -> ! {
+    let ports = serialport::available_ports()
+        .unwrap_or_else(|error| panic!("Failed to enumerate serial ports: {error}"));
+    if ports.is_empty() {
+        error!("No serial ports found");
+        exit(1);
+    }
+    ports::print_ports(&ports);
+    exit(0);
+}
+
+fn detect_port(ids: &[(u16, u16)]) -> String {
+    let mut candidates = ports::candidates(ids)
+        .unwrap_or_else(|error| panic!("Failed to enumerate serial ports: {error}"));
+    match candidates.len() {
+        1 => {
+            let port = candidates.remove(0).port_name;
+            info!("Detected receiver on {port}");
+            port
+        }
+        0 => {
+            let ids = ids
+                .iter()
+                .map(|(vid, pid)| format!("{vid:04x}:{pid:04x}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            error!("No serial port matches any of {ids}");
+            if let Ok(ports) = serialport::available_ports() {
+                ports::print_ports(&ports);
+            }
+            log_file::finish();
+            exit(EXIT_NO_RECEIVER);
+        }
+        n => {
+            error!("{n} serial ports look like receivers, pass the port explicitly");
+            ports::print_ports(&candidates);
+            log_file::finish();
+            exit(EXIT_AMBIGUOUS_RECEIVER);
+        }
+    }
+}
+
+fn run_metadata(args: &Args, ports: &[String], firmware: &[String]) -> metadata::Metadata {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut metadata = metadata::Metadata::default();
+    metadata
+        .number("started", started)
+        .strings("ports", ports.iter().map(String::as_str))
+        .strings("firmware", firmware.iter().map(String::as_str))
+        .string("device", args.device.name())
+        .string(
+            "encoding",
+            args.encoding
+                .unwrap_or_else(|| args.device.profile().encoding())
+                .name(),
+        )
+        .number("baud", args.serial.baud)
+        .string("framing", &args.serial.frame_format())
+        .number("fields", args.field_count() as u64);
+    match args.format {
+        Format::Text => (),
+        Format::Binary => {
+            metadata.string("format", "binary");
+        }
+        Format::Json => {
+            metadata.string("format", "json");
+        }
+    }
+    if let Some(delimiter) = args.delimiter {
+        metadata.string("delimiter", &delimiter.to_string());
+    }
+    if let Some(checksum) = args.checksum {
+        metadata.string("checksum", checksum.name());
+    }
+    if let Some(ref schema) = args.schema {
+        metadata.strings(
+            "schema",
+            schema.fields.iter().map(|field| field.name.as_str()),
+        );
+    }
+    if let Some(freq) = args.radio.freq {
+        metadata.number("freq", freq);
+    }
+    if let Some(sf) = args.radio.sf {
+        metadata.number("sf", sf);
+    }
+    if let Some(ref bw) = args.radio.bw {
+        metadata.string("bw", bw);
+    }
+    if let Some(cr) = args.radio.cr {
+        metadata.string("cr", &format!("4/{cr}"));
+    }
+    if let Some(crc) = args.radio.crc {
+        metadata.string("crc", if crc == Switch::On { "on" } else { "off" });
+    }
+    if let Some(sync) = args.radio.sync {
+        metadata.string("sync", &format!("{sync:02X}"));
+    }
+    if let Some(wdt) = args.radio.wdt {
+        metadata.number("wdt", wdt);
+    }
+    metadata
+}
+
+/// Names of the values in each record, unless they're learned from the JSON keys
+fn known_columns(args: &Args, columns: &[String], multiple: bool) -> Option<Vec<String>> {
+    (!columns.is_empty() || args.format != Format::Json)
+        .then(|| record_columns(args, columns, multiple))
+}
+
+/// Names of the values in each record
+fn record_columns(args: &Args, columns: &[String], multiple: bool) -> Vec<String> {
+    let mut names = Vec::with_capacity(columns.len() + 3);
+    if multiple {
+        names.push(String::from("port"));
+    }
+    if columns.is_empty() {
+        names.extend((0..args.field_count()).map(|index| format!("field_{index}")));
+    } else {
+        names.extend_from_slice(columns);
+    }
+    if let Some(ref schema) = args.schema {
+        names.extend(schema.derived_columns());
+    }
+    if args.signal_columns() {
+        names.extend([String::from("snr"), String::from("rssi")]);
+    }
+    names
 }
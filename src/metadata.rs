@@ -0,0 +1,68 @@
+use std::fmt::Write as FmtWrite;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Settings of one capture session, appended as a `[[run]]` table next to the output file so
+/// post-flight analysis knows how the data was received
+#[derive(Default)]
+pub struct Metadata {
+    entries: Vec<(&'static str, String)>,
+}
+
+impl Metadata {
+    pub fn number(&mut self, key: &'static str, value: impl Into<u64>) -> &mut Self {
+        self.entries.push((key, value.into().to_string()));
+        self
+    }
+
+    pub fn string(&mut self, key: &'static str, value: &str) -> &mut Self {
+        self.entries.push((key, quote(value)));
+        self
+    }
+
+    pub fn strings<'a>(
+        &mut self,
+        key: &'static str,
+        values: impl IntoIterator<Item = &'a str>,
+    ) -> &mut Self {
+        let values: Vec<String> = values.into_iter().map(quote).collect();
+        self.entries.push((key, format!("[{}]", values.join(", "))));
+        self
+    }
+
+    /// Appends the session to the metadata file belonging to `output`
+    pub fn append_to(&self, output: &Path) -> std::io::Result<PathBuf> {
+        let mut path = output.as_os_str().to_owned();
+        path.push(".meta.toml");
+        let path = PathBuf::from(path);
+
+        let mut table = String::from("[[run]]\n");
+        for (key, value) in &self.entries {
+            writeln!(table, "{key} = {value}").unwrap();
+        }
+        table.push('\n');
+
+        std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)?
+            .write_all(table.as_bytes())?;
+        Ok(path)
+    }
+}
+
+/// Formats a TOML basic string
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => write!(quoted, "\\u{:04X}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,117 @@
+use crate::Shared;
+use std::fmt::Write as FmtWrite;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info};
+
+/// Serves the counters in Prometheus' text format at `/metrics` from a background thread
+pub fn serve(address: SocketAddr, shared: Arc<Shared>) -> Result<(), String> {
+    let listener = TcpListener::bind(address)
+        .map_err(|error| format!("Failed to listen for metrics on {address}: {error}"))?;
+    info!("Serving metrics on http://{address}/metrics");
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(error) = respond(stream, &shared) {
+                debug!("Failed to serve metrics: {error}");
+            }
+        }
+    });
+    Ok(())
+}
+
+fn respond(stream: TcpStream, shared: &Shared) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // The headers aren't of interest
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let (status, body) = match request.split_whitespace().nth(1) {
+        Some("/metrics") => ("200 OK", render(shared)),
+        _ => ("404 Not Found", String::from("Metrics are at /metrics\n")),
+    };
+    write!(
+        &stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+fn render(shared: &Shared) -> String {
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+        write!(
+            text,
+            "# HELP charter_{name} {help}\n# TYPE charter_{name} {kind}\ncharter_{name} {value}\n"
+        )
+        .unwrap();
+    };
+    let counter =
+        |counter: &std::sync::atomic::AtomicU64| counter.load(Ordering::Relaxed).to_string();
+    metric(
+        "packets_total",
+        "counter",
+        "Packets received",
+        counter(&shared.packets),
+    );
+    metric(
+        "dropped_lines_total",
+        "counter",
+        "Received lines dropped for the output falling behind",
+        counter(&shared.dropped),
+    );
+    metric(
+        "parse_errors_total",
+        "counter",
+        "Packets whose payload couldn't be decoded or parsed",
+        counter(&shared.parse_errors),
+    );
+    metric(
+        "radio_errors_total",
+        "counter",
+        "radio_err replies, i.e. receive timeouts and CRC failures",
+        counter(&shared.radio_errors),
+    );
+    metric(
+        "bytes_read_total",
+        "counter",
+        "Bytes read from the ports",
+        counter(&shared.bytes_read),
+    );
+    metric(
+        "write_errors_total",
+        "counter",
+        "Records an output failed to take",
+        counter(&shared.write_errors),
+    );
+    let last = shared.last_packet.load(Ordering::Relaxed);
+    if last > 0 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let seconds = now.saturating_sub(last) as f64 / 1000.0;
+        metric(
+            "seconds_since_last_packet",
+            "gauge",
+            "Time since the last packet came in",
+            format!("{seconds:.3}"),
+        );
+    }
+    let rssi = shared.last_rssi.load(Ordering::Relaxed);
+    if rssi != i64::MIN {
+        metric(
+            "last_rssi_dbm",
+            "gauge",
+            "Signal strength of the last packet",
+            rssi.to_string(),
+        );
+    }
+    text
+}
@@ -0,0 +1,141 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// The service manager's socket, while there is one to tell
+static NOTIFIER: Mutex<Option<Notifier>> = Mutex::new(None);
+
+/// Set once STOPPING=1 has gone out, which only has to happen once
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+struct Notifier {
+    socket: socket::Socket,
+    /// How often systemd wants to hear from the service, if it watches it
+    watchdog: Option<Duration>,
+    last_ping: Instant,
+}
+
+fn lock() -> MutexGuard<'static, Option<Notifier>> {
+    NOTIFIER.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Connects to the socket in NOTIFY_SOCKET, doing nothing when not started by systemd
+pub fn connect() {
+    let Some(address) = std::env::var_os("NOTIFY_SOCKET") else {
+        debug!("Not started by systemd with Type=notify, --notify has nothing to tell");
+        return;
+    };
+    let socket = match socket::Socket::connect(&address) {
+        Ok(socket) => socket,
+        Err(error) => {
+            warn!(
+                "Failed to connect to the systemd socket {}: {error}",
+                address.to_string_lossy()
+            );
+            return;
+        }
+    };
+    // The watchdog is for this process only if WATCHDOG_PID says so or is left out
+    let ours =
+        std::env::var("WATCHDOG_PID").map_or(true, |pid| pid.parse() == Ok(std::process::id()));
+    let watchdog = std::env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse().ok())
+        .filter(|_| ours)
+        .map(Duration::from_micros);
+    if let Some(watchdog) = watchdog {
+        debug!("Pinging the systemd watchdog, which expects one every {watchdog:?}");
+    }
+    *lock() = Some(Notifier {
+        socket,
+        watchdog,
+        last_ping: Instant::now(),
+    });
+}
+
+fn send(notifier: &mut Notifier, state: &str) {
+    if let Err(error) = notifier.socket.send(state.as_bytes()) {
+        warn!("Failed to notify systemd of {state:?}: {error}");
+    }
+}
+
+/// Tells systemd the radios are armed, along with a status line for `systemctl status`
+pub fn ready(status: &str) {
+    if let Some(notifier) = lock().as_mut() {
+        send(notifier, &format!("READY=1\nSTATUS={status}"));
+    }
+}
+
+/// Pings the watchdog after a read from a port, at most twice per watchdog interval
+pub fn watchdog() {
+    let mut notifier = lock();
+    let Some(notifier) = notifier.as_mut() else {
+        return;
+    };
+    match notifier.watchdog {
+        Some(interval) if notifier.last_ping.elapsed() >= interval / 2 => {
+            notifier.last_ping = Instant::now();
+            send(notifier, "WATCHDOG=1");
+        }
+        _ => (),
+    }
+}
+
+/// Tells systemd the capture is on its way out
+pub fn stopping() {
+    if STOPPING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    if let Some(notifier) = lock().as_mut() {
+        send(notifier, "STOPPING=1");
+    }
+}
+
+/// The datagram socket systemd listens on, at a path or, with a leading `@`, an abstract name
+#[cfg(unix)]
+mod socket {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    pub struct Socket(UnixDatagram);
+
+    impl Socket {
+        pub fn connect(address: &OsStr) -> std::io::Result<Self> {
+            let socket = UnixDatagram::unbound()?;
+            match address.as_bytes() {
+                #[cfg(target_os = "linux")]
+                [b'@', name @ ..] => {
+                    use std::os::linux::net::SocketAddrExt;
+                    let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                    socket.connect_addr(&address)?
+                }
+                _ => socket.connect(address)?,
+            }
+            Ok(Socket(socket))
+        }
+
+        pub fn send(&self, state: &[u8]) -> std::io::Result<()> {
+            self.0.send(state).map(drop)
+        }
+    }
+}
+
+/// There's no systemd to notify
+#[cfg(not(unix))]
+mod socket {
+    use std::ffi::OsStr;
+
+    pub struct Socket;
+
+    impl Socket {
+        pub fn connect(_address: &OsStr) -> std::io::Result<Self> {
+            Err(std::io::ErrorKind::Unsupported.into())
+        }
+
+        pub fn send(&self, _state: &[u8]) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,1100 @@
+use crate::parquet::Parquet;
+use crate::schema::{FieldType, Schema};
+use charter::rotation::{self, RotateIndex, Rotation};
+use charter::sink::{self, Csv, CsvOptions, Dialect, Leading, QuoteStyle, Sink};
+pub use charter::sink::{Compression, Durability, ReceivedAt, Record};
+use clap::ValueEnum;
+use csv::Writer;
+use rusqlite::types::Value;
+use rusqlite::{Connection, OpenFlags};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError, TryLockError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error, info};
+
+/// How long an insert waits for someone else's lock on the database before giving up
+const SQLITE_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+    /// Rows of a `packets` table in an SQLite database
+    Sqlite,
+    /// Typed columns in a new Parquet file, written a row group at a time
+    Parquet,
+}
+
+/// How long records may wait in the buffers once they stop coming in
+const IDLE_FLUSH: Duration = Duration::from_secs(1);
+
+/// When the records buffered for the files are written out
+#[derive(Clone, Copy, PartialEq)]
+pub enum Flush {
+    EveryRecord,
+    EveryN(u64),
+    /// Milliseconds between flushes
+    EveryMs(u64),
+}
+
+impl Display for Flush {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Flush::EveryRecord => write!(f, "every-record"),
+            Flush::EveryN(records) => write!(f, "every-n={records}"),
+            Flush::EveryMs(interval) => write!(f, "every-ms={interval}"),
+        }
+    }
+}
+
+/// Parses `every-record`, `every-n=N` or `every-ms=T`
+pub fn parse_flush(value: &str) -> Result<Flush, String> {
+    if value == "every-record" {
+        return Ok(Flush::EveryRecord);
+    }
+    let (policy, number) = value
+        .split_once('=')
+        .ok_or_else(|| format!("`{value}` isn't every-record, every-n=N or every-ms=T"))?;
+    let number = match number.parse::<u64>() {
+        Ok(0) => return Err(String::from("use every-record to flush after each one")),
+        Ok(number) => number,
+        Err(_) => return Err(format!("`{number}` isn't a whole number")),
+    };
+    match policy {
+        "every-n" => Ok(Flush::EveryN(number)),
+        "every-ms" => Ok(Flush::EveryMs(number)),
+        _ => Err(format!("`{policy}` isn't every-n or every-ms")),
+    }
+}
+
+/// Which records the outputs get, decided by the record alone so a replay samples the same ones
+#[derive(Clone, Copy, PartialEq)]
+pub enum Sample {
+    /// Every Nth record by its index, starting with index 0
+    EveryN(u64),
+    /// The first record received in each window of T milliseconds since the epoch
+    EveryMs(u64),
+}
+
+impl Display for Sample {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Sample::EveryN(records) => write!(f, "every-n={records}"),
+            Sample::EveryMs(interval) => write!(f, "every-ms={interval}"),
+        }
+    }
+}
+
+/// Parses `every-n=N` or `every-ms=T`
+pub fn parse_sample(value: &str) -> Result<Sample, String> {
+    let (policy, number) = value
+        .split_once('=')
+        .ok_or_else(|| format!("`{value}` isn't every-n=N or every-ms=T"))?;
+    let number = match number.parse::<u64>() {
+        Ok(0) => return Err(String::from("the number has to be at least 1")),
+        Ok(number) => number,
+        Err(_) => return Err(format!("`{number}` isn't a whole number")),
+    };
+    match policy {
+        "every-n" => Ok(Sample::EveryN(number)),
+        "every-ms" => Ok(Sample::EveryMs(number)),
+        _ => Err(format!("`{policy}` isn't every-n or every-ms")),
+    }
+}
+
+/// Where an `--output` sends the records
+#[derive(Clone)]
+pub enum Target {
+    /// `-`, CSV, or JSON lines if the value names them, on stdout
+    Stdout { format: Option<OutputFormat> },
+    /// In `--output-format` unless the value names one
+    File {
+        format: Option<OutputFormat>,
+        path: PathBuf,
+    },
+}
+
+/// Parses `-` or `[FORMAT:]FILE`
+pub fn parse_target(value: &str) -> Result<Target, String> {
+    if value == "-" {
+        return Ok(Target::Stdout { format: None });
+    }
+    // A Windows drive letter isn't a format, so `C:\data.csv` still goes by --output-format
+    match value
+        .split_once(':')
+        .and_then(|(format, path)| Some((OutputFormat::from_str(format, true).ok()?, path)))
+    {
+        Some((format @ (OutputFormat::Csv | OutputFormat::Jsonl), "-")) => Ok(Target::Stdout {
+            format: Some(format),
+        }),
+        Some((_, "-")) => Err(String::from("only csv and jsonl can go to stdout")),
+        Some((_, "")) => Err(format!("`{value}` doesn't name a file")),
+        Some((format, path)) => Ok(Target::File {
+            format: Some(format),
+            path: PathBuf::from(path),
+        }),
+        None => Ok(Target::File {
+            format: None,
+            path: PathBuf::from(value),
+        }),
+    }
+}
+
+/// Options for the output files and stdout, shared by every subcommand that writes records
+#[derive(clap::Args)]
+#[command(about = None, long_about = None)]
+pub struct OutputArgs {
+    #[arg(short, long, value_name = "[FORMAT:]FILE", value_parser = parse_target)]
+    /// File name to print data to, `-` for CSV on stdout, can be given more than once with a
+    /// format each like `-o data.csv -o jsonl:data.jsonl -o jsonl:-` [default: info lines
+    /// on the console]
+    pub output: Vec<Target>,
+    #[arg(short, long)]
+    /// Allow the creation of a new output file
+    pub create: bool,
+    #[arg(long, requires = "output")]
+    /// Empty the output files at startup rather than appending to them, so the index starts
+    /// from 0, and create them too with --create
+    pub overwrite: bool,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    /// How records are written to output files that don't name a format
+    pub output_format: OutputFormat,
+    #[arg(long)]
+    /// Leave the leading `index` column out of new CSV files, existing files keep theirs
+    pub no_index: bool,
+    #[arg(long, value_enum, default_value_t = ReceivedAt::First)]
+    /// Where CSV rows have a `received_at` column with the ISO 8601 time each packet came in,
+    /// which the info lines show too unless it's off
+    pub received_at: ReceivedAt,
+    #[arg(long, value_name = "RECORDS", default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..))]
+    /// Records per Parquet row group, which is also the most a crash can lose
+    pub row_group: u64,
+    #[arg(long, value_name = "POLICY", value_parser = parse_flush)]
+    /// When buffered CSV and JSON records are written to the file: every-record, every-n=N or
+    /// every-ms=T, and always after a second without records [default: every-record, or
+    /// every-ms=10000 with --compress]
+    pub flush: Option<Flush>,
+    #[arg(long, value_enum, default_value_t = Durability::None)]
+    /// Make sure the CSV and JSON records are on the disk and not just in the page cache, in
+    /// case of a power cut
+    pub durability: Durability,
+    #[arg(long, value_enum, requires = "output")]
+    /// Compress the CSV or JSON file, which stays readable up to the last flush after a crash
+    pub compress: Option<Compression>,
+    #[arg(long, value_name = "PERIOD", requires_all = ["output", "create"], value_parser = rotation::parse_period)]
+    /// Start a new CSV or JSON file every period, like 1h, named by the --output path as a
+    /// strftime pattern such as data-%Y%m%d-%H.csv (in UTC)
+    pub rotate_every: Option<Duration>,
+    #[arg(long, value_name = "SIZE", requires_all = ["output", "create"], value_parser = rotation::parse_size)]
+    /// Start a new CSV or JSON file once the current one reaches the size, like 50MB
+    pub rotate_size: Option<u64>,
+    #[arg(long, value_enum, default_value_t = RotateIndex::Continue)]
+    /// Whether the index written to the files counts on across rotated files
+    pub rotate_index: RotateIndex,
+    #[arg(long)]
+    /// Neither write a header row into new files nor check the one of existing files
+    pub no_header: bool,
+    #[arg(long, value_name = "CHAR", default_value = ",", value_parser = sink::parse_delimiter)]
+    /// What separates the CSV values, like `;` for Excel in some locales or `tab`
+    pub csv_delimiter: u8,
+    #[arg(long, value_enum, default_value_t = QuoteStyle::Necessary)]
+    /// Which CSV values are quoted
+    pub csv_quote_style: QuoteStyle,
+    #[arg(long)]
+    /// Start new CSV files with a UTF-8 byte order mark, which Excel needs to see UTF-8
+    pub csv_bom: bool,
+    #[arg(long, value_name = "POLICY", value_parser = parse_sample)]
+    /// Only write some of the records: every-n=N for every Nth by index or every-ms=T for the
+    /// first in each T milliseconds, while the statistics still count them all
+    pub sample: Option<Sample>,
+    #[arg(long, value_name = "FILE", requires = "sample")]
+    /// An --output that gets every record despite --sample, `-` for stdout, can be given more
+    /// than once
+    pub full_rate: Vec<PathBuf>,
+}
+
+impl OutputArgs {
+    pub fn dialect(&self) -> Dialect {
+        Dialect {
+            delimiter: self.csv_delimiter,
+            quote_style: self.csv_quote_style,
+            bom: self.csv_bom,
+        }
+    }
+
+    /// The output files and their formats
+    pub fn files(&self) -> impl Iterator<Item = (OutputFormat, &Path)> {
+        self.output.iter().filter_map(|target| match target {
+            Target::Stdout { .. } => None,
+            Target::File { format, path } => {
+                Some((format.unwrap_or(self.output_format), path.as_path()))
+            }
+        })
+    }
+
+    /// The format of the records on stdout, if they go there
+    pub fn stdout(&self) -> Option<OutputFormat> {
+        self.output.iter().find_map(|target| match target {
+            Target::Stdout { format } => Some(format.unwrap_or(OutputFormat::Csv)),
+            Target::File { .. } => None,
+        })
+    }
+
+    /// Why a --full-rate isn't one of the outputs, if it isn't
+    pub fn unknown_full_rate(&self) -> Option<String> {
+        let outputs: Vec<&Path> = self
+            .output
+            .iter()
+            .map(|target| match target {
+                Target::Stdout { .. } => Path::new("-"),
+                Target::File { path, .. } => path.as_path(),
+            })
+            .collect();
+        self.full_rate
+            .iter()
+            .find(|path| !outputs.contains(&path.as_path()))
+            .map(|path| format!("--full-rate {} isn't an --output", path.display()))
+    }
+
+    /// The output with only the records --sample picks, unless it's at `path` and that's one
+    /// of the --full-rate outputs, `-` for stdout
+    pub fn sampled(&self, output: Box<dyn Output>, path: Option<&Path>) -> Box<dyn Output> {
+        match self.sample {
+            Some(_) if path.is_some_and(|path| self.full_rate.iter().any(|full| full == path)) => {
+                output
+            }
+            Some(sample) => Box::new(Sampled {
+                output,
+                sample,
+                window: None,
+            }),
+            None => output,
+        }
+    }
+
+    /// When the output files are flushed
+    ///
+    /// Each flush ends a gzip member or zstd frame, which defeats compressing one record at a time.
+    pub fn flush_policy(&self) -> Flush {
+        match (self.flush, self.compress) {
+            (Some(policy), _) => policy,
+            (None, Some(_)) => Flush::EveryMs(10_000),
+            (None, None) => Flush::EveryRecord,
+        }
+    }
+}
+
+/// The outputs in use, kept here so the panic hook can still finish them
+static OUTPUTS: Mutex<Vec<Box<dyn Output>>> = Mutex::new(Vec::new());
+
+/// Records written since the outputs were last flushed
+static PENDING: AtomicU64 = AtomicU64::new(0);
+/// When the last record was written and the outputs were last flushed, in ms since the epoch
+static LAST_WRITE: AtomicU64 = AtomicU64::new(0);
+static LAST_FLUSH: AtomicU64 = AtomicU64::new(0);
+static FLUSHES: AtomicU64 = AtomicU64::new(0);
+
+/// Somewhere the records go
+pub trait Output: Send {
+    /// Where the records go, for the log
+    fn name(&self) -> String;
+
+    /// Gets the output ready for the records before any port is opened
+    fn prepare(&mut self) -> Result<(), String>;
+
+    fn write(&mut self, record: &Record) -> Result<(), Box<dyn Error>>;
+
+    /// Writes out the records buffered since the last flush
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Writes out whatever is still buffered before exiting
+    fn finish(&mut self) {}
+
+    /// Records the file already had when `prepare` found it to append to
+    fn rows(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Passes the records picked by --sample on to the output
+struct Sampled {
+    output: Box<dyn Output>,
+    sample: Sample,
+    /// The window of the last record passed on, for every-ms
+    window: Option<u64>,
+}
+
+impl Output for Sampled {
+    fn name(&self) -> String {
+        self.output.name()
+    }
+
+    fn prepare(&mut self) -> Result<(), String> {
+        self.output.prepare()
+    }
+
+    fn write(&mut self, record: &Record) -> Result<(), Box<dyn Error>> {
+        let picked = match self.sample {
+            Sample::EveryN(records) => (record.index as u64).is_multiple_of(records),
+            Sample::EveryMs(interval) => {
+                let window = millis(record.received) / interval;
+                let picked = self.window != Some(window);
+                self.window = Some(window);
+                picked
+            }
+        };
+        match picked {
+            true => self.output.write(record),
+            false => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.output.flush()
+    }
+
+    fn rows(&self) -> Option<usize> {
+        self.output.rows()
+    }
+
+    fn finish(&mut self) {
+        self.output.finish()
+    }
+}
+
+/// Hands the prepared outputs over to `lock`, `flush_due` and `finish_all`
+pub fn install(outputs: Vec<Box<dyn Output>>) {
+    *lock() = outputs;
+}
+
+/// The outputs, for writing a record to each
+///
+/// Don't panic while holding them, the panic hook would have to leave them unfinished.
+pub fn lock() -> MutexGuard<'static, Vec<Box<dyn Output>>> {
+    OUTPUTS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Records a file output holds back while it can't be written to, before giving up on it
+const HELD_RECORDS: usize = 10_000;
+
+/// How often a file output that can't be written to tries to open the file again
+const REOPEN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A record kept until its file can be written to again
+struct Held {
+    index: usize,
+    received: SystemTime,
+    payload: Vec<u8>,
+    columns: Vec<String>,
+    values: Vec<String>,
+}
+
+/// Holds the records back while the output's file is gone, e.g. with the directory it was in,
+/// opening it again every so often, which with --create starts the file afresh
+struct Recovering {
+    output: Box<dyn Output>,
+    held: Vec<Held>,
+    /// When the file was last tried, while it can't be written to
+    tried: Option<Instant>,
+}
+
+impl Recovering {
+    fn wrap(output: Box<dyn Output>) -> Box<dyn Output> {
+        Box::new(Recovering {
+            output,
+            held: Vec::new(),
+            tried: None,
+        })
+    }
+
+    fn lost(&mut self, error: &dyn Error) {
+        error!(
+            kind = "write",
+            "{error}, holding back up to {HELD_RECORDS} records until {} can be written again",
+            self.output.name()
+        );
+        self.tried = Some(Instant::now());
+    }
+
+    fn hold(&mut self, record: &Record) -> Result<(), Box<dyn Error>> {
+        if self.held.len() >= HELD_RECORDS {
+            let message = format!(
+                "Gave up on {} after holding back {HELD_RECORDS} records",
+                self.output.name()
+            );
+            return Err(std::io::Error::new(ErrorKind::NotFound, message).into());
+        }
+        self.held.push(Held {
+            index: record.index,
+            received: record.received,
+            payload: record.payload.to_vec(),
+            columns: record.columns.to_vec(),
+            values: record
+                .values
+                .iter()
+                .map(|value| value.to_string())
+                .collect(),
+        });
+        Ok(())
+    }
+
+    /// Opens the file again and writes the records held back, unless it was just tried
+    fn retry(&mut self, now: bool) {
+        match self.tried {
+            Some(tried) if now || tried.elapsed() >= REOPEN_INTERVAL => (),
+            _ => return,
+        }
+        self.tried = Some(Instant::now());
+        if let Err(error) = self.output.prepare() {
+            debug!("{} still can't be written to: {error}", self.output.name());
+            return;
+        }
+        let held = std::mem::take(&mut self.held);
+        for (written, record) in held.iter().enumerate() {
+            let values: Vec<&str> = record.values.iter().map(String::as_str).collect();
+            let record = Record {
+                index: record.index,
+                received: record.received,
+                payload: &record.payload,
+                columns: &record.columns,
+                values: &values,
+            };
+            if let Err(error) = self.output.write(&record) {
+                error!("Failed to write the records held back: {error}");
+                self.held = held.into_iter().skip(written).collect();
+                return;
+            }
+        }
+        if let Err(error) = self.output.flush() {
+            error!("Failed to flush {}: {error}", self.output.name());
+        }
+        self.tried = None;
+        info!(
+            "{} can be written to again, wrote the {} records held back",
+            self.output.name(),
+            held.len()
+        );
+    }
+}
+
+impl Output for Recovering {
+    fn name(&self) -> String {
+        self.output.name()
+    }
+
+    fn prepare(&mut self) -> Result<(), String> {
+        self.output.prepare()
+    }
+
+    fn write(&mut self, record: &Record) -> Result<(), Box<dyn Error>> {
+        self.retry(false);
+        if self.tried.is_some() {
+            return self.hold(record);
+        }
+        match self.output.write(record) {
+            Err(error) if is_fatal(&*error) => {
+                self.lost(&*error);
+                self.hold(record)
+            }
+            result => result,
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.tried.is_some() {
+            self.retry(false);
+            return Ok(());
+        }
+        match self.output.flush() {
+            Err(error) if is_fatal(&*error) => {
+                self.lost(&*error);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn rows(&self) -> Option<usize> {
+        self.output.rows()
+    }
+
+    fn finish(&mut self) {
+        self.retry(true);
+        if self.tried.is_some() {
+            error!(
+                "Lost the {} records held back for {}",
+                self.held.len(),
+                self.output.name()
+            );
+        }
+        self.output.finish()
+    }
+}
+
+/// Whether the output can't go on after the error, rather than just losing the record
+pub fn is_fatal(error: &(dyn Error + 'static)) -> bool {
+    if let Some(FileError(error)) = error.downcast_ref() {
+        return error.is_fatal();
+    }
+    error
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|error| matches!(error.kind(), ErrorKind::NotFound | ErrorKind::BrokenPipe))
+}
+
+/// An error of a CSV or JSON file, told along with the option that gets past it
+#[derive(Debug)]
+pub struct FileError(pub sink::Error);
+
+impl FileError {
+    fn hint(error: &sink::Error) -> &'static str {
+        match error {
+            sink::Error::Open { error, .. } if error.kind() == ErrorKind::NotFound => {
+                ", pass --create to start a new file"
+            }
+            sink::Error::Delimiter { .. } => ", pass --csv-delimiter to match it",
+            sink::Error::Header { .. } => ", pass --no-header to append anyway",
+            sink::Error::Moved(_) => ", pass --create to start a new one",
+            sink::Error::Reopen(error) => FileError::hint(error),
+            _ => "",
+        }
+    }
+}
+
+impl Display for FileError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.0, FileError::hint(&self.0))
+    }
+}
+
+impl Error for FileError {}
+
+/// The message of a file's error for `Output::prepare`
+fn failed(error: sink::Error) -> String {
+    FileError(error).to_string()
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Flushes every output, returning the error of one that can't go on
+fn flush(outputs: &mut [Box<dyn Output>]) -> Option<Box<dyn Error>> {
+    PENDING.store(0, Ordering::Relaxed);
+    LAST_FLUSH.store(millis(SystemTime::now()), Ordering::Relaxed);
+    FLUSHES.fetch_add(1, Ordering::Relaxed);
+    crate::capture::flush();
+    let mut fatal = None;
+    for output in outputs {
+        match output.flush() {
+            Ok(()) => (),
+            Err(error) if is_fatal(&*error) => fatal = Some(error),
+            Err(error) => error!("Failed to flush {}: {error}", output.name()),
+        }
+    }
+    fatal
+}
+
+/// Counts a record written to the outputs, flushing them if the policy says it's time
+pub fn written(outputs: &mut [Box<dyn Output>], policy: Flush) -> Option<Box<dyn Error>> {
+    let pending = PENDING.fetch_add(1, Ordering::Relaxed) + 1;
+    LAST_WRITE.store(millis(SystemTime::now()), Ordering::Relaxed);
+    match policy {
+        Flush::EveryRecord => flush(outputs),
+        Flush::EveryN(records) if pending >= records => flush(outputs),
+        _ => None,
+    }
+}
+
+/// Flushes the outputs if records have waited for the policy's period, or for a second since
+/// the last one came in, panicking once they're let go of if one can't go on
+pub fn flush_due(policy: Flush) {
+    let fatal = {
+        let mut outputs = lock();
+        if PENDING.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let now = millis(SystemTime::now());
+        let idle =
+            now.saturating_sub(LAST_WRITE.load(Ordering::Relaxed)) >= IDLE_FLUSH.as_millis() as u64;
+        let period = match policy {
+            Flush::EveryMs(interval) => {
+                now.saturating_sub(LAST_FLUSH.load(Ordering::Relaxed)) >= interval
+            }
+            _ => false,
+        };
+        if !idle && !period {
+            return;
+        }
+        flush(&mut outputs)
+    };
+    if let Some(error) = fatal {
+        crate::exit_with(crate::EXIT_OUTPUT_FAILED);
+        panic!("{error}");
+    }
+}
+
+/// How many times the outputs were flushed, for the summary
+pub fn flushes() -> u64 {
+    FLUSHES.load(Ordering::Relaxed)
+}
+
+/// How long the panic hook waits for another thread to let go of the outputs
+const FINISH_WAIT: Duration = Duration::from_secs(1);
+
+/// Finishes and drops every output and the raw log on the way out
+pub fn finish_all() {
+    crate::capture::finish();
+    finish(lock());
+}
+
+/// Finishes the outputs from the panic hook, unless they're still held after `FINISH_WAIT`
+///
+/// Whichever thread panicked, like the one the sink runs on, may be holding them, and only lets
+/// go once it unwinds after the hook, so waiting on the lock could deadlock.
+pub fn finish_after_panic() {
+    crate::capture::finish();
+    let started = Instant::now();
+    let outputs = loop {
+        match OUTPUTS.try_lock() {
+            Ok(outputs) => break outputs,
+            Err(TryLockError::Poisoned(outputs)) => break outputs.into_inner(),
+            Err(TryLockError::WouldBlock) if started.elapsed() < FINISH_WAIT => {
+                std::thread::sleep(Duration::from_millis(10))
+            }
+            Err(TryLockError::WouldBlock) => {
+                error!("The outputs are still in use, leaving them unfinished");
+                return;
+            }
+        }
+    };
+    finish(outputs);
+}
+
+fn finish(mut outputs: MutexGuard<'static, Vec<Box<dyn Output>>>) {
+    for mut output in outputs.drain(..) {
+        output.finish();
+    }
+}
+
+/// Sets up the output, `header` is the columns if they're known and `width_varies` whether
+/// records may have more values than that
+pub fn open(
+    args: &OutputArgs,
+    format: OutputFormat,
+    path: &Path,
+    header: Option<Vec<String>>,
+    schema: Option<&Schema>,
+    width_varies: bool,
+) -> Box<dyn Output> {
+    let (path, create) = (path.to_path_buf(), args.create);
+    let overwrite = args.overwrite;
+    let compression = args.compress;
+    let mut rotation = Rotation::new(
+        &path,
+        args.rotate_every,
+        args.rotate_size,
+        args.rotate_index,
+    );
+    let file = match rotation {
+        Some(ref mut rotation) => rotation.first_path(),
+        None => path.clone(),
+    };
+    match format {
+        OutputFormat::Csv => {
+            let options = CsvOptions {
+                dialect: args.dialect(),
+                create,
+                overwrite,
+                compression,
+                durability: args.durability,
+                rotation,
+                received_at: args.received_at,
+                index: !args.no_index,
+                header: !args.no_header,
+                width_varies,
+            };
+            Recovering::wrap(Box::new(Csv::new(file, header, options)))
+        }
+        OutputFormat::Jsonl => Recovering::wrap(Box::new(JsonLines {
+            path: file,
+            create,
+            overwrite,
+            started: false,
+            compression,
+            sync: args.durability,
+            rotation,
+            appended: None,
+            writer: None,
+        })),
+        OutputFormat::Sqlite => Box::new(Sqlite::new(path, create, header, schema)),
+        OutputFormat::Parquet => Box::new(Parquet::new(
+            path,
+            create,
+            overwrite,
+            args.row_group as usize,
+            header,
+            schema,
+        )),
+    }
+}
+
+impl Output for Csv {
+    fn name(&self) -> String {
+        self.path().display().to_string()
+    }
+
+    fn prepare(&mut self) -> Result<(), String> {
+        Csv::prepare(self).map_err(failed)
+    }
+
+    fn write(&mut self, record: &Record) -> Result<(), Box<dyn Error>> {
+        Ok(Csv::write(self, record).map_err(FileError)?)
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(Csv::flush(self).map_err(FileError)?)
+    }
+
+    fn rows(&self) -> Option<usize> {
+        Csv::rows(self)
+    }
+
+    fn finish(&mut self) {
+        Csv::finish(self)
+    }
+}
+
+/// Opens the CSV or JSON lines `Stdout` output
+pub fn stdout(
+    args: &OutputArgs,
+    format: OutputFormat,
+    header: Option<Vec<String>>,
+) -> Box<dyn Output> {
+    Box::new(Stdout {
+        csv: args.dialect().writer(std::io::stdout()),
+        leading: Leading::default(),
+        format,
+        received_at: args.received_at,
+        index: !args.no_index,
+        columns: header.filter(|_| !args.no_header).map(|mut columns| {
+            args.received_at
+                .insert(&mut columns, String::from("received_at"));
+            columns
+        }),
+    })
+}
+
+/// Writes the records to stdout for a pipe, flushing after each so the reader gets them right
+/// away
+pub struct Stdout {
+    /// Flushed after each line, which goes to stdout whole
+    csv: Writer<std::io::Stdout>,
+    leading: Leading,
+    format: OutputFormat,
+    received_at: ReceivedAt,
+    index: bool,
+    /// The CSV header, if there is one to write
+    columns: Option<Vec<String>>,
+}
+
+impl Output for Stdout {
+    fn name(&self) -> String {
+        String::from("stdout")
+    }
+
+    fn prepare(&mut self) -> Result<(), String> {
+        let Some(ref columns) = self.columns else {
+            return Ok(());
+        };
+        if self.format != OutputFormat::Csv {
+            return Ok(());
+        }
+        let index = self.index.then_some("index");
+        let header = index.into_iter().chain(columns.iter().map(String::as_str));
+        self.csv
+            .write_record(header)
+            .and_then(|_| self.csv.flush().map_err(Into::into))
+            .map_err(|error| format!("Failed to write to stdout: {error}"))
+    }
+
+    fn write(&mut self, record: &Record) -> Result<(), Box<dyn Error>> {
+        if self.format == OutputFormat::Jsonl {
+            let mut line = serde_json::to_vec(&record.to_json())?;
+            line.push(b'\n');
+            return Ok(write_stdout(&line)?);
+        }
+        let index = self.index.then_some(record.index);
+        let row = self.leading.row(record, index, self.received_at);
+        self.csv.write_record(row)?;
+        Ok(self.csv.flush()?)
+    }
+
+    fn finish(&mut self) {
+        let _ = std::io::stdout().flush();
+    }
+}
+
+fn write_stdout(line: &[u8]) -> std::io::Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(line)?;
+    stdout.flush()
+}
+
+/// Keeps the file open like `Csv` does
+pub struct JsonLines {
+    path: PathBuf,
+    create: bool,
+    overwrite: bool,
+    started: bool,
+    compression: Option<Compression>,
+    sync: Durability,
+    rotation: Option<Rotation>,
+    appended: Option<usize>,
+    writer: Option<BufWriter<Sink>>,
+}
+
+impl JsonLines {
+    /// Finishes the file and starts the next one, if the rotation says so
+    fn rotate(&mut self) -> Result<(), Box<dyn Error>> {
+        let bytes = self
+            .writer
+            .as_ref()
+            .map_or(0, |writer| writer.get_ref().bytes());
+        let Some(path) = self
+            .rotation
+            .as_mut()
+            .and_then(|rotation| rotation.next_path(&self.path, bytes))
+        else {
+            return Ok(());
+        };
+        self.finish();
+        info!("Continuing in {}", path.display());
+        self.path = path;
+        Ok(self.prepare()?)
+    }
+}
+
+impl Output for JsonLines {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn prepare(&mut self) -> Result<(), String> {
+        let path = &self.path;
+        if std::mem::take(&mut self.overwrite) {
+            sink::truncate(path, self.create).map_err(failed)?;
+        }
+        let file =
+            sink::open_append(path, self.create, self.compression, self.sync).map_err(failed)?;
+        if !std::mem::replace(&mut self.started, true) {
+            match file.bytes() {
+                0 => info!("Writing to {}", path.display()),
+                _ => {
+                    let rows = sink::open_read(path, self.compression)
+                        .map(|file| BufReader::new(file).split(b'\n').count())
+                        .map_err(|error| {
+                            failed(sink::Error::Read {
+                                path: path.clone(),
+                                error: error.into(),
+                            })
+                        })?;
+                    info!("Appending to {}, which has {rows} rows", path.display());
+                    self.appended = Some(rows);
+                }
+            }
+        }
+        self.writer = Some(BufWriter::new(file));
+        Ok(())
+    }
+
+    fn write(&mut self, record: &Record) -> Result<(), Box<dyn Error>> {
+        self.rotate()?;
+        let index = match self.rotation {
+            Some(ref mut rotation) => rotation.index(record.index),
+            None => record.index,
+        };
+        let record = Record { index, ..*record };
+        let writer = self.writer.as_mut().ok_or("The JSON file isn't open")?;
+        serde_json::to_writer(&mut *writer, &record.to_json())?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(ref mut writer) = self.writer else {
+            return Ok(());
+        };
+        writer.flush()?;
+        match writer.get_ref().reopen(&self.path, self.create) {
+            Ok(false) => Ok(()),
+            Ok(true) => self.prepare().map_err(|error| {
+                self.writer = None;
+                std::io::Error::new(ErrorKind::NotFound, error).into()
+            }),
+            Err(error) => {
+                self.writer = None;
+                Err(FileError(error).into())
+            }
+        }
+    }
+
+    fn rows(&self) -> Option<usize> {
+        self.appended
+    }
+
+    fn finish(&mut self) {
+        if let Some(mut writer) = self.writer.take() {
+            let closed = writer.flush().and_then(|_| writer.get_ref().close());
+            if let Err(error) = closed {
+                error!("Failed to finish {}: {error}", self.path.display());
+            }
+        }
+    }
+}
+
+pub struct Sqlite {
+    path: PathBuf,
+    create: bool,
+    /// Columns to create the table with
+    header: Vec<String>,
+    /// Declared types of the columns, by name
+    types: HashMap<String, &'static str>,
+    connection: Option<Connection>,
+    /// Columns the table has, those of new JSON keys are added as they show up
+    columns: HashSet<String>,
+}
+
+impl Sqlite {
+    fn new(
+        path: PathBuf,
+        create: bool,
+        header: Option<Vec<String>>,
+        schema: Option<&Schema>,
+    ) -> Self {
+        let mut types = HashMap::from([
+            (String::from("index"), "INTEGER"),
+            (String::from("received_at"), "TEXT"),
+            (String::from("snr"), "INTEGER"),
+            (String::from("rssi"), "INTEGER"),
+        ]);
+        for field in schema.map(|schema| &schema.fields[..]).unwrap_or_default() {
+            let kind = match field.kind {
+                FieldType::String | FieldType::Bool | FieldType::Hex => "TEXT",
+                // Scaled integers come out as decimals, the affinity takes either
+                _ => "NUMERIC",
+            };
+            types.insert(field.name.clone(), kind);
+        }
+        Sqlite {
+            path,
+            create,
+            header: header.unwrap_or_default(),
+            types,
+            connection: None,
+            columns: HashSet::new(),
+        }
+    }
+
+    /// Adds the column to the table unless it's there already
+    fn add_column(
+        connection: &Connection,
+        columns: &mut HashSet<String>,
+        types: &HashMap<String, &'static str>,
+        name: &str,
+    ) -> rusqlite::Result<()> {
+        if columns.contains(name) {
+            return Ok(());
+        }
+        let kind = types.get(name).copied().unwrap_or("TEXT");
+        connection.execute(
+            &format!("ALTER TABLE packets ADD COLUMN {} {kind}", quote(name)),
+            [],
+        )?;
+        columns.insert(name.to_string());
+        Ok(())
+    }
+}
+
+impl Output for Sqlite {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    /// Opens the database and creates the table, or adds the columns it's missing
+    fn prepare(&mut self) -> Result<(), String> {
+        let mut flags = OpenFlags::default();
+        match self.create {
+            true => sink::create_parent(&self.path).map_err(failed)?,
+            false => flags.remove(OpenFlags::SQLITE_OPEN_CREATE),
+        }
+        let path = self.path.display().to_string();
+        let failed = |error: rusqlite::Error| format!("Failed to open {path}: {error}");
+        let connection = Connection::open_with_flags(&self.path, flags).map_err(failed)?;
+        // Readers of the live database get a snapshot rather than locking out inserts
+        connection
+            .query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))
+            .and_then(|_| connection.busy_timeout(SQLITE_BUSY_TIMEOUT))
+            .and_then(|_| {
+                connection.execute(
+                    "CREATE TABLE IF NOT EXISTS packets (\"index\" INTEGER, received_at TEXT)",
+                    [],
+                )
+            })
+            .map_err(failed)?;
+        self.columns = connection
+            .prepare("SELECT name FROM pragma_table_info('packets')")
+            .and_then(|mut statement| {
+                statement
+                    .query_map([], |row| row.get(0))?
+                    .collect::<rusqlite::Result<_>>()
+            })
+            .map_err(failed)?;
+        for name in std::mem::take(&mut self.header) {
+            Sqlite::add_column(&connection, &mut self.columns, &self.types, &name)
+                .map_err(failed)?;
+        }
+        self.connection = Some(connection);
+        Ok(())
+    }
+
+    fn write(&mut self, record: &Record) -> Result<(), Box<dyn Error>> {
+        let connection = self.connection.as_ref().ok_or("The database isn't open")?;
+        let mut names = vec![quote("index"), quote("received_at")];
+        let mut values = vec![
+            Value::Integer(record.index as i64),
+            Value::Text(crate::clock::timestamp(record.received)),
+        ];
+        for (name, value) in record.named() {
+            Sqlite::add_column(connection, &mut self.columns, &self.types, &name)?;
+            names.push(quote(&name));
+            values.push(match value {
+                "" => Value::Null,
+                value => Value::Text(value.to_string()),
+            });
+        }
+        // Without a transaction around it, every insert commits on its own
+        let placeholders = vec!["?"; values.len()].join(", ");
+        connection
+            .prepare_cached(&format!(
+                "INSERT INTO packets ({}) VALUES ({placeholders})",
+                names.join(", ")
+            ))?
+            .execute(rusqlite::params_from_iter(values))?;
+        Ok(())
+    }
+}
+
+/// Quotes an SQL identifier
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
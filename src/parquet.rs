@@ -0,0 +1,362 @@
+use crate::output::{Output, Record};
+use crate::schema::{FieldType, Schema};
+use ::parquet::basic::{Compression, LogicalType, Repetition, TimeUnit, Type as PhysicalType};
+use ::parquet::data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int64Type};
+use ::parquet::file::metadata::{FileMetaData, ParquetMetaData, ParquetMetaDataWriter};
+use ::parquet::file::properties::WriterProperties;
+use ::parquet::file::writer::{SerializedColumnWriter, SerializedFileWriter};
+use ::parquet::schema::types::{SchemaDescriptor, Type, TypePtr};
+use std::error::Error;
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tracing::info;
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Int,
+    Double,
+    Bool,
+    Text,
+}
+
+impl Kind {
+    /// The column type of a record value, going by the schema where it has the column
+    fn of(name: &str, schema: Option<&Schema>) -> Self {
+        let field = schema.and_then(|schema| schema.fields.iter().find(|field| field.name == name));
+        match field {
+            Some(field) if field.scale.is_some() || field.offset.is_some() => Kind::Double,
+            Some(field) if field.coordinate.is_some() => Kind::Double,
+            Some(field) => match field.kind {
+                FieldType::Float | FieldType::F32 | FieldType::F64 => Kind::Double,
+                FieldType::Bool => Kind::Bool,
+                FieldType::String | FieldType::Hex => Kind::Text,
+                _ => Kind::Int,
+            },
+            None if name == "snr" || name == "rssi" => Kind::Int,
+            None if name == "gps_valid" && schema.is_some_and(|schema| schema.gps_valid) => {
+                Kind::Bool
+            }
+            None => Kind::Text,
+        }
+    }
+
+    fn column(self, name: &str) -> Type {
+        let (physical, logical) = match self {
+            Kind::Int => (PhysicalType::INT64, None),
+            Kind::Double => (PhysicalType::DOUBLE, None),
+            Kind::Bool => (PhysicalType::BOOLEAN, None),
+            Kind::Text => (PhysicalType::BYTE_ARRAY, Some(LogicalType::String)),
+        };
+        Type::primitive_type_builder(name, physical)
+            .with_repetition(Repetition::OPTIONAL)
+            .with_logical_type(logical)
+            .build()
+            .unwrap()
+    }
+}
+
+/// Writes records to a Parquet file in row groups of `row_group` records
+///
+/// After each row group the file gets a footer of its own, which the next row group
+/// overwrites, so a run that dies halfway still leaves a readable file.
+pub struct Parquet {
+    path: PathBuf,
+    create: bool,
+    overwrite: bool,
+    row_group: usize,
+    columns: Vec<(String, Kind)>,
+    writer: Option<SerializedFileWriter<Provisional>>,
+    indexes: Vec<i64>,
+    received: Vec<i64>,
+    /// Buffered values by column, empty for null
+    values: Vec<Vec<String>>,
+}
+
+impl Parquet {
+    pub fn new(
+        path: PathBuf,
+        create: bool,
+        overwrite: bool,
+        row_group: usize,
+        columns: Option<Vec<String>>,
+        schema: Option<&Schema>,
+    ) -> Self {
+        let columns: Vec<(String, Kind)> = columns
+            .unwrap_or_default()
+            .into_iter()
+            .map(|name| {
+                let kind = Kind::of(&name, schema);
+                (name, kind)
+            })
+            .collect();
+        Parquet {
+            path,
+            create,
+            overwrite,
+            row_group,
+            values: vec![Vec::with_capacity(row_group); columns.len()],
+            columns,
+            writer: None,
+            indexes: Vec::with_capacity(row_group),
+            received: Vec::with_capacity(row_group),
+        }
+    }
+
+    fn schema(&self) -> TypePtr {
+        let mut fields = vec![
+            Type::primitive_type_builder("index", PhysicalType::INT64)
+                .with_repetition(Repetition::REQUIRED)
+                .build()
+                .unwrap(),
+            Type::primitive_type_builder("received_at", PhysicalType::INT64)
+                .with_repetition(Repetition::REQUIRED)
+                .with_logical_type(Some(LogicalType::timestamp(true, TimeUnit::MILLIS)))
+                .build()
+                .unwrap(),
+        ];
+        fields.extend(self.columns.iter().map(|(name, kind)| kind.column(name)));
+        Arc::new(
+            Type::group_type_builder("packets")
+                .with_fields(fields.into_iter().map(Arc::new).collect())
+                .build()
+                .unwrap(),
+        )
+    }
+
+    /// Writes the buffered records as a row group, followed by a footer covering it
+    fn flush_row_group(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(ref mut writer) = self.writer else {
+            return Err("The Parquet file isn't open".into());
+        };
+        if self.indexes.is_empty() {
+            return Ok(());
+        }
+        let mut row_group = writer.next_row_group()?;
+        write_required(row_group.next_column()?, &self.indexes)?;
+        write_required(row_group.next_column()?, &self.received)?;
+        for ((_, kind), values) in self.columns.iter().zip(&mut self.values) {
+            let column = row_group
+                .next_column()?
+                .ok_or("Parquet schema has fewer columns than the records")?;
+            write_optional(column, *kind, values)?;
+            values.clear();
+        }
+        row_group.close()?;
+        self.indexes.clear();
+        self.received.clear();
+
+        writer.flush()?;
+        let schema = Arc::new(SchemaDescriptor::new(
+            writer.schema_descr().root_schema_ptr(),
+        ));
+        let row_groups = writer.flushed_row_groups().to_vec();
+        let rows = row_groups
+            .iter()
+            .map(|row_group| row_group.num_rows())
+            .sum();
+        let metadata = ParquetMetaData::new(
+            FileMetaData::new(1, rows, Some(created_by()), None, schema, None),
+            row_groups,
+        );
+        writer.inner_mut().write_footer(&metadata)?;
+        Ok(())
+    }
+}
+
+impl Output for Parquet {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    /// Starts the file, which has to be new or overwritten as Parquet can't be appended to
+    fn prepare(&mut self) -> Result<(), String> {
+        if self.columns.is_empty() {
+            return Err(String::from(
+                "Parquet output needs the columns up front, pass --schema or --header",
+            ));
+        }
+        let path = self.path.display();
+        let file = match std::fs::metadata(&self.path) {
+            Ok(metadata) if metadata.len() > 0 && !self.overwrite => {
+                return Err(format!(
+                    "{path} already exists and Parquet files can't be appended to, pass \
+                     --overwrite to replace it"
+                ))
+            }
+            Ok(metadata) => {
+                match metadata.len() {
+                    0 => info!("Writing to {path}"),
+                    _ => info!("Overwriting {path}"),
+                }
+                File::options().write(true).truncate(true).open(&self.path)
+            }
+            Err(_) if self.create => {
+                charter::sink::create_parent(&self.path).map_err(|error| error.to_string())?;
+                info!("Writing to {path}");
+                File::create(&self.path)
+            }
+            Err(error) => return Err(format!("Failed to open {path}: {error}")),
+        }
+        .map_err(|error| format!("Failed to open {path}: {error}"))?;
+
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_created_by(created_by())
+            .build();
+        let writer =
+            SerializedFileWriter::new(Provisional::new(file), self.schema(), Arc::new(properties))
+                .map_err(|error| format!("Failed to start {path}: {error}"))?;
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    fn write(&mut self, record: &Record) -> Result<(), Box<dyn Error>> {
+        self.indexes.push(record.index as i64);
+        let received = record
+            .received
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.received.push(received.as_millis() as i64);
+        // Appended extra fields have no column to go to
+        for (index, values) in self.values.iter_mut().enumerate() {
+            values.push(record.values.get(index).unwrap_or(&"").to_string());
+        }
+        if self.indexes.len() >= self.row_group {
+            self.flush_row_group()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) {
+        let result = self
+            .flush_row_group()
+            .and_then(|_| match self.writer.take() {
+                Some(writer) => Ok(writer.close().map(drop)?),
+                None => Ok(()),
+            });
+        if let Err(error) = result {
+            tracing::error!("Failed to finish {}: {error}", self.path.display());
+        }
+    }
+}
+
+fn created_by() -> String {
+    format!("charter {}", env!("CARGO_PKG_VERSION"))
+}
+
+fn write_required(
+    column: Option<SerializedColumnWriter>,
+    values: &[i64],
+) -> Result<(), Box<dyn Error>> {
+    let mut column = column.ok_or("Parquet schema is missing a column")?;
+    column
+        .typed::<Int64Type>()
+        .write_batch(values, None, None)?;
+    column.close()?;
+    Ok(())
+}
+
+/// Writes the column, with values that are empty or don't parse as its type as nulls
+fn write_optional(
+    mut column: SerializedColumnWriter,
+    kind: Kind,
+    values: &[String],
+) -> Result<(), Box<dyn Error>> {
+    fn parsed<T: std::str::FromStr>(values: &[String]) -> (Vec<T>, Vec<i16>) {
+        let mut present = Vec::with_capacity(values.len());
+        let mut levels = Vec::with_capacity(values.len());
+        for value in values {
+            match value.parse() {
+                Ok(value) => {
+                    present.push(value);
+                    levels.push(1);
+                }
+                Err(_) => levels.push(0),
+            }
+        }
+        (present, levels)
+    }
+
+    match kind {
+        Kind::Int => {
+            let (present, levels) = parsed::<i64>(values);
+            column
+                .typed::<Int64Type>()
+                .write_batch(&present, Some(&levels), None)?;
+        }
+        Kind::Double => {
+            let (present, levels) = parsed::<f64>(values);
+            column
+                .typed::<DoubleType>()
+                .write_batch(&present, Some(&levels), None)?;
+        }
+        Kind::Bool => {
+            let (present, levels) = parsed::<bool>(values);
+            column
+                .typed::<BoolType>()
+                .write_batch(&present, Some(&levels), None)?;
+        }
+        Kind::Text => {
+            let present: Vec<ByteArray> = values
+                .iter()
+                .filter(|value| !value.is_empty())
+                .map(|value| ByteArray::from(value.as_str()))
+                .collect();
+            let levels: Vec<i16> = values
+                .iter()
+                .map(|value| !value.is_empty() as i16)
+                .collect();
+            column
+                .typed::<ByteArrayType>()
+                .write_batch(&present, Some(&levels), None)?;
+        }
+    }
+    column.close()?;
+    Ok(())
+}
+
+/// The file the Parquet writer writes to, which may end in a footer that the next write cuts off
+struct Provisional {
+    file: File,
+    /// Where the data written by the Parquet writer ends
+    end: u64,
+    footer: bool,
+}
+
+impl Provisional {
+    fn new(file: File) -> Self {
+        Provisional {
+            file,
+            end: 0,
+            footer: false,
+        }
+    }
+
+    fn write_footer(&mut self, metadata: &ParquetMetaData) -> Result<(), Box<dyn Error>> {
+        self.file.seek(SeekFrom::Start(self.end))?;
+        ParquetMetaDataWriter::new(&mut self.file, metadata).finish()?;
+        self.file.sync_data()?;
+        self.footer = true;
+        Ok(())
+    }
+}
+
+impl Write for Provisional {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.footer {
+            self.file.set_len(self.end)?;
+            self.file.seek(SeekFrom::Start(self.end))?;
+            self.footer = false;
+        }
+        let written = self.file.write(buf)?;
+        self.end += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -0,0 +1,549 @@
+use crate::checksum::Checksum;
+use crate::json::{self, InvalidJson};
+use crate::receiver::Framing;
+use crate::schema::{InvalidField, LengthMismatch, Schema};
+use base64::Engine;
+use clap::ValueEnum;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::str::Utf8Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::debug;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Encoding {
+    Hex,
+    Base64,
+    /// The payload is passed on verbatim
+    None,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Hex => "hex",
+            Encoding::Base64 => "base64",
+            Encoding::None => "none",
+        }
+    }
+
+    pub fn decode(self, payload: &str) -> Result<Vec<u8>, GetDataError> {
+        let mut decoded = Vec::new();
+        self.decode_into(payload, &mut decoded)?;
+        Ok(decoded)
+    }
+
+    /// Decodes into `out` in place of what it held, so one buffer can serve every packet
+    pub fn decode_into(self, payload: &str, out: &mut Vec<u8>) -> Result<(), GetDataError> {
+        out.clear();
+        let decoded = match self {
+            Encoding::Hex => {
+                out.resize(payload.len() / 2, 0);
+                hex::decode_to_slice(payload, out).map_err(|error| error.to_string())
+            }
+            Encoding::Base64 => base64::engine::general_purpose::STANDARD
+                .decode_vec(payload, out)
+                .map_err(|error| error.to_string()),
+            Encoding::None => {
+                out.extend_from_slice(payload.as_bytes());
+                Ok(())
+            }
+        };
+        decoded.map_err(|reason| GetDataError::Decode {
+            encoding: self,
+            reason,
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Format {
+    /// Whitespace-separated text fields
+    Text,
+    /// Fixed-width binary fields as laid out by --schema
+    Binary,
+    /// A JSON object, with a column per key in schema or first-seen order
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum ExtraFields {
+    /// Warn and leave them out of the row
+    Drop,
+    /// Add them to the row as additional columns
+    Append,
+    /// Skip the row
+    Error,
+}
+
+#[derive(Debug)]
+pub enum GetDataError {
+    IrregularMessage(&'static str),
+    ParseError(&'static str),
+    UnbalancedQuote {
+        position: usize,
+        payload: String,
+    },
+    FieldCount {
+        found: usize,
+        expected: usize,
+        payload: String,
+    },
+    Decode {
+        encoding: Encoding,
+        reason: String,
+    },
+    ChecksumMismatch {
+        checksum: Checksum,
+        received: String,
+        computed: u16,
+    },
+}
+
+impl Display for GetDataError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GetDataError::IrregularMessage(msg) => write!(f, "Irregular message: {}", msg),
+            GetDataError::ParseError(msg) => write!(f, "Error while parsing data: {}", msg),
+            GetDataError::UnbalancedQuote { position, payload } => write!(
+                f,
+                "Error while parsing data: quote at position {} isn't closed in {:?}",
+                position, payload
+            ),
+            GetDataError::FieldCount {
+                found,
+                expected,
+                payload,
+            } => write!(
+                f,
+                "Error while parsing data: expected {} fields, found {} in {:?}",
+                expected, found, payload
+            ),
+            GetDataError::Decode { encoding, reason } => write!(
+                f,
+                "Error while parsing data: payload isn't valid {} ({})",
+                encoding.name(),
+                reason
+            ),
+            GetDataError::ChecksumMismatch {
+                checksum,
+                received,
+                computed,
+            } => write!(
+                f,
+                "Error while parsing data: {} checksum is {}, but the payload says {}",
+                checksum.name(),
+                match checksum {
+                    Checksum::Crc16 => format!("{computed:04X}"),
+                    Checksum::Sum8 => format!("{computed:02X}"),
+                },
+                received
+            ),
+        }
+    }
+}
+
+impl Error for GetDataError {}
+
+impl GetDataError {
+    /// A name for the kind of error, for counting them in structured logs
+    pub fn kind(&self) -> &'static str {
+        match self {
+            GetDataError::IrregularMessage(_) => "irregular",
+            GetDataError::ParseError(_) => "parse",
+            GetDataError::UnbalancedQuote { .. } => "unbalanced_quote",
+            GetDataError::FieldCount { .. } => "field_count",
+            GetDataError::Decode { .. } => "decode",
+            GetDataError::ChecksumMismatch { .. } => "checksum",
+        }
+    }
+}
+
+/// Why a payload didn't make it into a row
+#[derive(Debug)]
+pub enum ParseError {
+    Data(GetDataError),
+    Field(InvalidField),
+    Length(LengthMismatch),
+    Json(InvalidJson),
+    Utf8(Utf8Error),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Data(error) => write!(f, "{error}"),
+            ParseError::Field(error) => write!(f, "{error}"),
+            ParseError::Length(error) => write!(f, "{error}"),
+            ParseError::Json(error) => write!(f, "{error}"),
+            ParseError::Utf8(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Data(error) => Some(error),
+            ParseError::Field(error) => Some(error),
+            ParseError::Length(error) => Some(error),
+            ParseError::Json(error) => Some(error),
+            ParseError::Utf8(error) => Some(error),
+        }
+    }
+}
+
+impl ParseError {
+    /// A name for the kind of error, for counting them in structured logs
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ParseError::Data(error) => error.kind(),
+            ParseError::Field(_) => "invalid_field",
+            ParseError::Length(_) => "length",
+            ParseError::Json(_) => "json",
+            ParseError::Utf8(_) => "utf8",
+        }
+    }
+}
+
+impl From<GetDataError> for ParseError {
+    fn from(error: GetDataError) -> Self {
+        ParseError::Data(error)
+    }
+}
+
+impl From<InvalidField> for ParseError {
+    fn from(error: InvalidField) -> Self {
+        ParseError::Field(error)
+    }
+}
+
+impl From<LengthMismatch> for ParseError {
+    fn from(error: LengthMismatch) -> Self {
+        ParseError::Length(error)
+    }
+}
+
+impl From<InvalidJson> for ParseError {
+    fn from(error: InvalidJson) -> Self {
+        ParseError::Json(error)
+    }
+}
+
+impl From<Utf8Error> for ParseError {
+    fn from(error: Utf8Error) -> Self {
+        ParseError::Utf8(error)
+    }
+}
+
+/// Extracts and decodes the payload of a packet line into `payload`, counting the corrupted
+/// lines in which a later packet was found in `resyncs`
+pub fn get_data(
+    framing: &(impl Framing + ?Sized),
+    encoding: Encoding,
+    line: &str,
+    resyncs: &AtomicU64,
+    payload: &mut Vec<u8>,
+) -> Result<(), GetDataError> {
+    if !framing.is_packet(line) {
+        debug!("{line}");
+        return Err(GetDataError::IrregularMessage(
+            "this line doesn't contain any data",
+        ));
+    }
+    let mut frame = line;
+    loop {
+        let error = match framing
+            .payload(frame)
+            .and_then(|encoded| encoding.decode_into(encoded, payload))
+        {
+            Ok(()) => return Ok(()),
+            Err(error) => error,
+        };
+        // Dropped bytes can merge a packet with the next one, which may still be intact
+        let Some(skipped) = frame[1..].find(framing.marker()).map(|pos| pos + 1) else {
+            debug!("{line}");
+            return Err(error);
+        };
+        tracing::warn!("{error}, resynchronizing {skipped} bytes later at the next packet");
+        resyncs.fetch_add(1, Ordering::Relaxed);
+        frame = &frame[skipped..];
+    }
+}
+
+/// How text payloads are split into fields and checked
+pub struct ParseOptions<'a> {
+    /// Number of fields in each payload
+    pub fields: usize,
+    /// What separates the fields, whitespace if there's nothing
+    pub delimiter: Option<char>,
+    /// Checksum in a trailing field
+    pub checksum: Option<Checksum>,
+    /// Pad payloads with missing trailing fields instead of refusing them
+    pub allow_short: bool,
+    pub extra_fields: ExtraFields,
+    pub schema: Option<&'a Schema>,
+}
+
+/// Splits a text payload into the fields of a row, which borrow from it unless a schema or
+/// quoting changed them
+pub fn parse_data<'a>(
+    line: &'a str,
+    options: &ParseOptions,
+) -> Result<Vec<Cow<'a, str>>, ParseError> {
+    let fields = options.fields;
+    let extra = options.extra_fields;
+    let mut data = split_fields(line, options.delimiter)?;
+    if let Some(checksum) = options.checksum {
+        let received = data.pop().unwrap_or_default();
+        let separator = options.delimiter.map_or(String::from(" "), String::from);
+        let computed = checksum.compute(data.join(&separator).as_bytes());
+        if u16::from_str_radix(&received, 16).ok() != Some(computed) {
+            debug!("{line}");
+            return Err(ParseError::Data(GetDataError::ChecksumMismatch {
+                checksum,
+                received: received.into_owned(),
+                computed,
+            }));
+        }
+    }
+    let too_many = data.len() > fields && extra == ExtraFields::Error;
+    if too_many || (data.len() < fields && !options.allow_short) {
+        return Err(ParseError::Data(GetDataError::FieldCount {
+            found: data.len(),
+            expected: fields,
+            payload: line.to_string(),
+        }));
+    }
+    let tail = data.split_off(fields.min(data.len()));
+    if !tail.is_empty() && extra == ExtraFields::Drop {
+        tracing::warn!("Dropped {} extra fields: {:?}", tail.len(), tail.join(" "));
+    }
+    if let Some(schema) = options.schema {
+        // Only the fields that are there, padding is left empty whatever the type
+        data = schema
+            .convert(&data)
+            .inspect_err(|_| debug!("{line}"))?
+            .iter()
+            .map(|value| Cow::Owned(value.to_string()))
+            .collect();
+    }
+    data.resize(fields, Cow::Borrowed(""));
+    if extra == ExtraFields::Append {
+        data.extend(tail);
+    }
+    Ok(data)
+}
+
+/// Splits a payload into fields, keeping double quoted text together
+///
+/// Inside quotes `\"` and `\\` stand for a quote and a backslash.
+pub fn split_fields(
+    line: &str,
+    delimiter: Option<char>,
+) -> Result<Vec<Cow<'_, str>>, GetDataError> {
+    // Without quotes every field is a slice of the line as it is
+    if !line.contains('"') {
+        return Ok(match delimiter {
+            Some(delimiter) => line.split(delimiter).map(Cow::Borrowed).collect(),
+            None => line.split_whitespace().map(Cow::Borrowed).collect(),
+        });
+    }
+    let is_separator = |c: char| delimiter.map_or(c.is_whitespace(), |delimiter| c == delimiter);
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    // Runs of whitespace separate fields just once, but "" is still an empty field
+    let mut started = delimiter.is_some();
+    let mut quote = None;
+    let mut chars = line.char_indices().peekable();
+    while let Some((position, c)) = chars.next() {
+        match (quote, c) {
+            (Some(_), '"') => quote = None,
+            (Some(_), '\\') => match chars.next_if(|&(_, next)| next == '"' || next == '\\') {
+                Some((_, escaped)) => field.push(escaped),
+                None => field.push(c),
+            },
+            (Some(_), c) => field.push(c),
+            (None, '"') => {
+                quote = Some(position);
+                started = true;
+            }
+            (None, c) if is_separator(c) => {
+                if started {
+                    fields.push(Cow::Owned(std::mem::take(&mut field)));
+                }
+                started = delimiter.is_some();
+            }
+            (None, c) => {
+                field.push(c);
+                started = true;
+            }
+        }
+    }
+    if let Some(position) = quote {
+        return Err(GetDataError::UnbalancedQuote {
+            position,
+            payload: line.to_string(),
+        });
+    }
+    if started {
+        fields.push(Cow::Owned(field));
+    }
+    Ok(fields)
+}
+
+/// Decodes a binary payload into the fields laid out by the schema
+pub fn parse_binary(payload: &[u8], schema: &Schema) -> Result<Vec<Cow<'static, str>>, ParseError> {
+    let values = schema
+        .decode(payload)
+        .inspect_err(|_| debug!("{}", hex::encode_upper(payload)))?;
+    Ok(values
+        .iter()
+        .map(|value| Cow::Owned(value.to_string()))
+        .collect())
+}
+
+/// Spreads a JSON object over the columns, leaving those of missing or null keys empty
+///
+/// With `learn`, keys that aren't one of the columns yet are added to them.
+pub fn parse_json(
+    text: &str,
+    schema: Option<&Schema>,
+    columns: &mut Vec<String>,
+    learn: bool,
+) -> Result<Vec<Cow<'static, str>>, ParseError> {
+    let members = json::parse(text)?;
+    if learn {
+        for (key, _) in &members {
+            if !columns.contains(key) {
+                columns.push(key.clone());
+            }
+        }
+    }
+    let mut data = vec![Cow::Borrowed(""); columns.len()];
+    for (key, value) in members {
+        let Some(position) = columns.iter().position(|column| *column == key) else {
+            debug!("Ignoring JSON key {key}, it isn't one of the columns");
+            continue;
+        };
+        data[position] = Cow::Owned(
+            match schema.and_then(|schema| schema.fields.get(position)) {
+                Some(field) if !value.is_empty() => field.convert(&value)?.to_string(),
+                _ => value,
+            },
+        );
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receiver::Rn2483;
+
+    fn options(fields: usize) -> ParseOptions<'static> {
+        ParseOptions {
+            fields,
+            delimiter: None,
+            checksum: None,
+            allow_short: false,
+            extra_fields: ExtraFields::Error,
+            schema: None,
+        }
+    }
+
+    fn fields(line: &str, options: &ParseOptions) -> Vec<String> {
+        let data = parse_data(line, options).unwrap();
+        data.into_iter().map(Cow::into_owned).collect()
+    }
+
+    #[test]
+    fn resyncs_at_a_later_packet() {
+        let resyncs = AtomicU64::new(0);
+        let mut payload = Vec::new();
+        let line = "radio_rx  3radio_rx  3132";
+        get_data(&Rn2483, Encoding::Hex, line, &resyncs, &mut payload).unwrap();
+        assert_eq!(payload, b"12");
+        assert_eq!(resyncs.load(Ordering::Relaxed), 1);
+
+        let broken = get_data(
+            &Rn2483,
+            Encoding::Hex,
+            "radio_rx  3",
+            &resyncs,
+            &mut payload,
+        );
+        assert!(matches!(broken, Err(GetDataError::Decode { .. })));
+        assert_eq!(resyncs.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn quotes_keep_delimiters_in_a_field() {
+        let split = split_fields(r#"1,"a,b",,"say \"hi\"""#, Some(',')).unwrap();
+        assert_eq!(split, ["1", "a,b", "", r#"say "hi""#]);
+        let split = split_fields(r#"1  "two words"   """#, None).unwrap();
+        assert_eq!(split, ["1", "two words", ""]);
+        let unbalanced = split_fields(r#"1 "open"#, None);
+        assert!(matches!(
+            unbalanced,
+            Err(GetDataError::UnbalancedQuote { position: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn field_count_is_checked() {
+        let too_many = parse_data("1 2 3", &options(2));
+        assert!(matches!(
+            too_many,
+            Err(ParseError::Data(GetDataError::FieldCount {
+                found: 3,
+                expected: 2,
+                ..
+            }))
+        ));
+        let too_few = parse_data("1", &options(2));
+        assert!(matches!(
+            too_few,
+            Err(ParseError::Data(GetDataError::FieldCount {
+                found: 1,
+                expected: 2,
+                ..
+            }))
+        ));
+
+        let short = ParseOptions {
+            allow_short: true,
+            ..options(2)
+        };
+        assert_eq!(fields("1", &short), ["1", ""]);
+        let append = ParseOptions {
+            extra_fields: ExtraFields::Append,
+            ..options(2)
+        };
+        assert_eq!(fields("1 2 3", &append), ["1", "2", "3"]);
+        let drop = ParseOptions {
+            extra_fields: ExtraFields::Drop,
+            ..options(2)
+        };
+        assert_eq!(fields("1 2 3", &drop), ["1", "2"]);
+    }
+
+    #[test]
+    fn checksum_field_is_checked_and_removed() {
+        let checksum = ParseOptions {
+            checksum: Some(Checksum::Crc16),
+            ..options(2)
+        };
+        let crc = Checksum::Crc16.compute(b"21.5 48");
+        assert_eq!(
+            fields(&format!("21.5 48 {crc:04X}"), &checksum),
+            ["21.5", "48"]
+        );
+        let corrupted = format!("21.5 49 {crc:04X}");
+        assert!(matches!(
+            parse_data(&corrupted, &checksum),
+            Err(ParseError::Data(GetDataError::ChecksumMismatch { .. }))
+        ));
+    }
+}
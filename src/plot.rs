@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+use std::io::Write;
+
+/// Bar heights of the sparkline, from lowest to highest
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// The field `--plot` follows
+#[derive(Clone)]
+pub enum PlotField {
+    /// Position among the payload's fields
+    Index(usize),
+    /// Column name, from the header or schema
+    Name(String),
+}
+
+pub fn parse_field(value: &str) -> Result<PlotField, String> {
+    match value.parse() {
+        Ok(index) => Ok(PlotField::Index(index)),
+        Err(_) if value.is_empty() => Err(String::from("the field can't be empty")),
+        Err(_) => Ok(PlotField::Name(value.to_string())),
+    }
+}
+
+/// Redraws a sparkline of the last `size` values of a field on one line of the terminal,
+/// along with the latest value and the range of those shown
+pub struct Plot {
+    field: PlotField,
+    size: usize,
+    values: VecDeque<f64>,
+    /// Values that weren't numbers, or packets without the field
+    skipped: u64,
+    drawn: bool,
+}
+
+impl Plot {
+    pub fn new(field: PlotField, size: usize) -> Self {
+        Plot {
+            field,
+            size,
+            values: VecDeque::with_capacity(size),
+            skipped: 0,
+            drawn: false,
+        }
+    }
+
+    /// Adds the field's value from the record, with `names` its columns and the payload's
+    /// fields starting at `first`, after the port if there is one
+    pub fn add(&mut self, names: &[String], record: &[&str], first: usize) {
+        let (label, value) = match &self.field {
+            PlotField::Index(index) => {
+                let label = names
+                    .get(first + index)
+                    .cloned()
+                    .unwrap_or_else(|| format!("field {index}"));
+                (label, record.get(first + index).copied())
+            }
+            PlotField::Name(name) => {
+                let value = names
+                    .iter()
+                    .position(|column| column == name)
+                    .and_then(|position| record.get(position).copied());
+                (name.clone(), value)
+            }
+        };
+        match value.and_then(|value| value.trim().parse::<f64>().ok()) {
+            Some(value) if value.is_finite() => {
+                if self.values.len() == self.size {
+                    self.values.pop_front();
+                }
+                self.values.push_back(value);
+            }
+            _ => self.skipped += 1,
+        }
+        self.draw(&label);
+    }
+
+    fn draw(&mut self, label: &str) {
+        let Some(&latest) = self.values.back() else {
+            return;
+        };
+        let min = self.values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = self
+            .values
+            .iter()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max);
+        let mut text = format!("{label} {latest}  ");
+        let mut range = format!("  min {min} max {max}");
+        if self.skipped > 0 {
+            range.push_str(&format!(" ({} skipped)", self.skipped));
+        }
+        let width = terminal_size::terminal_size().map_or(80, |(width, _)| width.0 as usize);
+        // What's left of the line after the text goes to the newest values
+        let room = width.saturating_sub(text.chars().count() + range.chars().count() + 1);
+        let shown = self.values.len().min(room);
+        text.extend(
+            self.values
+                .iter()
+                .skip(self.values.len() - shown)
+                .map(|&value| match max > min {
+                    true => BARS[((value - min) / (max - min) * 7.0).round() as usize],
+                    false => BARS[3],
+                }),
+        );
+        text.push_str(&range);
+        let mut out = std::io::stdout().lock();
+        let _ = write!(
+            out,
+            "\r{}\x1b[K",
+            text.chars()
+                .take(width.saturating_sub(1))
+                .collect::<String>()
+        )
+        .and_then(|_| out.flush());
+        self.drawn = true;
+    }
+
+    /// Moves past the plot's line, so what's logged next doesn't overwrite it
+    pub fn finish(&self) {
+        if self.drawn {
+            println!();
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use serialport::{SerialPortInfo, SerialPortType, UsbPortInfo};
+
+/// USB VID:PID pairs of the USB-serial bridges commonly found on RN2483/RN2903 breakouts
+pub const RN2483_BRIDGES: [(u16, u16); 4] = [
+    (0x04d8, 0x00df), // Microchip MCP2200
+    (0x0403, 0x6001), // FTDI FT232R
+    (0x0403, 0x6015), // FTDI FT231X
+    (0x10c4, 0xea60), // Silicon Labs CP210x
+];
+
+pub fn usb_info(port: &SerialPortInfo) -> Option<&UsbPortInfo> {
+    match port.port_type {
+        SerialPortType::UsbPort(ref info) => Some(info),
+        _ => None,
+    }
+}
+
+/// Whether the port looks like it's bridging to a Microchip LoRa module
+pub fn is_rn2483_bridge(port: &SerialPortInfo) -> bool {
+    let Some(info) = usb_info(port) else {
+        return false;
+    };
+    let names_module = [&info.manufacturer, &info.product]
+        .into_iter()
+        .flatten()
+        .any(|name| name.contains("RN2483") || name.contains("RN2903"));
+    names_module || RN2483_BRIDGES.contains(&(info.vid, info.pid))
+}
+
+/// Prints the given ports as an aligned table
+pub fn print_ports(ports: &[SerialPortInfo]) {
+    let mut rows = vec![[
+        String::from("PORT"),
+        String::from("VID:PID"),
+        String::from("MANUFACTURER"),
+        String::from("PRODUCT"),
+        String::new(),
+    ]];
+    for port in ports {
+        let (id, manufacturer, product) = match usb_info(port) {
+            Some(info) => (
+                format!("{:04x}:{:04x}", info.vid, info.pid),
+                info.manufacturer.clone().unwrap_or_default(),
+                info.product.clone().unwrap_or_default(),
+            ),
+            None => Default::default(),
+        };
+        let marker = if is_rn2483_bridge(port) {
+            String::from("RN2483/RN2903?")
+        } else {
+            String::new()
+        };
+        rows.push([port.port_name.clone(), id, manufacturer, product, marker]);
+    }
+
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in &rows {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+}
+
+/// Ports whose USB VID:PID is one of `ids`
+pub fn candidates(ids: &[(u16, u16)]) -> Result<Vec<SerialPortInfo>, serialport::Error> {
+    Ok(serialport::available_ports()?
+        .into_iter()
+        .filter(|port| usb_info(port).is_some_and(|info| ids.contains(&(info.vid, info.pid))))
+        .collect())
+}
+
+pub fn parse_usb_id(value: &str) -> Result<(u16, u16), String> {
+    let (vid, pid) = value
+        .split_once(':')
+        .ok_or_else(|| format!("`{value}` isn't in VID:PID form"))?;
+    let parse = |id: &str| {
+        u16::from_str_radix(id, 16).map_err(|_| format!("`{id}` isn't a hexadecimal USB ID"))
+    };
+    Ok((parse(vid)?, parse(pid)?))
+}
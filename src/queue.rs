@@ -0,0 +1,237 @@
+use clap::ValueEnum;
+use std::collections::VecDeque;
+use std::pin::pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use tokio::sync::Notify;
+
+/// What a reader does with a line when the output is too far behind to take it
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum WhenFull {
+    /// Drop the line, keeping the serial port drained
+    DropNewest,
+    /// Make room by dropping the line that has been waiting longest
+    DropOldest,
+    /// Wait for the output, which lets the OS serial buffer overflow instead if it takes long
+    Block,
+}
+
+pub enum Pushed<T> {
+    Queued,
+    /// The queue was full, so this one was dropped instead
+    Dropped(T),
+    /// The receiver has gone away
+    Closed,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    senders: usize,
+    receiving: bool,
+}
+
+struct Queue<T> {
+    state: Mutex<State<T>>,
+    /// Notified when there's an item to take or no senders are left
+    items: Notify,
+    /// Notified when there's room for an item or the receiver is gone
+    space: Notify,
+    capacity: usize,
+    when_full: WhenFull,
+}
+
+impl<T> Queue<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A channel like `tokio::sync::mpsc::channel` that can also drop the oldest item when it's full
+pub fn bounded<T>(capacity: usize, when_full: WhenFull) -> (Sender<T>, Receiver<T>) {
+    let queue = Arc::new(Queue {
+        state: Mutex::new(State {
+            items: VecDeque::with_capacity(capacity),
+            senders: 1,
+            receiving: true,
+        }),
+        items: Notify::new(),
+        space: Notify::new(),
+        capacity,
+        when_full,
+    });
+    (Sender(queue.clone()), Receiver(queue))
+}
+
+pub struct Sender<T>(Arc<Queue<T>>);
+
+impl<T> Sender<T> {
+    pub async fn push(&self, mut item: T) -> Pushed<T> {
+        let queue = &*self.0;
+        loop {
+            // Registered before looking, so room made in between isn't missed
+            let mut space = pin!(queue.space.notified());
+            space.as_mut().enable();
+            match self.try_push(item) {
+                Ok(pushed) => return pushed,
+                Err(full) => item = full,
+            }
+            space.await;
+        }
+    }
+
+    /// Pushes unless it has to wait for room, handing the item back if so
+    fn try_push(&self, item: T) -> Result<Pushed<T>, T> {
+        let queue = &*self.0;
+        let mut state = queue.lock();
+        if !state.receiving {
+            return Ok(Pushed::Closed);
+        }
+        let dropped = match queue.when_full {
+            _ if state.items.len() < queue.capacity => None,
+            WhenFull::Block => return Err(item),
+            WhenFull::DropNewest => return Ok(Pushed::Dropped(item)),
+            WhenFull::DropOldest => state.items.pop_front(),
+        };
+        state.items.push_back(item);
+        queue.items.notify_one();
+        Ok(match dropped {
+            Some(dropped) => Pushed::Dropped(dropped),
+            None => Pushed::Queued,
+        })
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.0.lock().senders += 1;
+        Sender(self.0.clone())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.0.lock().senders -= 1;
+        self.0.items.notify_waiters();
+    }
+}
+
+/// Yields the items in order until every sender is gone and the queue is empty
+pub struct Receiver<T>(Arc<Queue<T>>);
+
+impl<T> Receiver<T> {
+    pub async fn recv(&mut self) -> Option<T> {
+        let queue = &*self.0;
+        loop {
+            let mut items = pin!(queue.items.notified());
+            items.as_mut().enable();
+            if let Some(item) = self.pop() {
+                return item;
+            }
+            items.await;
+        }
+    }
+
+    /// The next item, or `None` once there won't be any more, unless it's yet to be pushed
+    fn pop(&self) -> Option<Option<T>> {
+        let queue = &*self.0;
+        let mut state = queue.lock();
+        if let Some(item) = state.items.pop_front() {
+            queue.space.notify_one();
+            return Some(Some(item));
+        }
+        (state.senders == 0).then_some(None)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.0.lock().receiving = false;
+        self.0.space.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn pushed(sender: &Sender<u32>, item: u32) -> Option<u32> {
+        match sender.push(item).await {
+            Pushed::Queued => None,
+            Pushed::Dropped(item) => Some(item),
+            Pushed::Closed => panic!("the receiver went away"),
+        }
+    }
+
+    async fn drain(mut receiver: Receiver<u32>) -> Vec<u32> {
+        let mut items = Vec::new();
+        while let Some(item) = receiver.recv().await {
+            items.push(item);
+        }
+        items
+    }
+
+    #[tokio::test]
+    async fn drop_newest_keeps_what_was_queued() {
+        let (sender, receiver) = bounded(2, WhenFull::DropNewest);
+        assert_eq!(pushed(&sender, 1).await, None);
+        assert_eq!(pushed(&sender, 2).await, None);
+        assert_eq!(pushed(&sender, 3).await, Some(3));
+        drop(sender);
+        assert_eq!(drain(receiver).await, [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_makes_room() {
+        let (sender, receiver) = bounded(2, WhenFull::DropOldest);
+        assert_eq!(pushed(&sender, 1).await, None);
+        assert_eq!(pushed(&sender, 2).await, None);
+        assert_eq!(pushed(&sender, 3).await, Some(1));
+        drop(sender);
+        assert_eq!(drain(receiver).await, [2, 3]);
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_room() {
+        let (sender, mut receiver) = bounded(1, WhenFull::Block);
+        assert_eq!(pushed(&sender, 1).await, None);
+        let blocked = tokio::spawn(async move { pushed(&sender, 2).await });
+        tokio::task::yield_now().await;
+        assert!(!blocked.is_finished());
+        assert_eq!(receiver.recv().await, Some(1));
+        assert_eq!(blocked.await.unwrap(), None);
+        assert_eq!(drain(receiver).await, [2]);
+    }
+
+    #[tokio::test]
+    async fn closed_once_the_receiver_is_gone() {
+        let (sender, receiver) = bounded(1, WhenFull::Block);
+        assert_eq!(pushed(&sender, 1).await, None);
+        let blocked = tokio::spawn(async move { sender.push(2).await });
+        tokio::task::yield_now().await;
+        drop(receiver);
+        assert!(matches!(blocked.await.unwrap(), Pushed::Closed));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn block_never_drops_with_racing_senders() {
+        const SENDERS: u32 = 4;
+        const EACH: u32 = 10_000;
+        let (sender, receiver) = bounded(1, WhenFull::Block);
+        let senders: Vec<_> = (0..SENDERS)
+            .map(|n| {
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    for item in n * EACH..(n + 1) * EACH {
+                        assert_eq!(pushed(&sender, item).await, None);
+                    }
+                })
+            })
+            .collect();
+        drop(sender);
+        let mut items = drain(receiver).await;
+        for sender in senders {
+            sender.await.unwrap();
+        }
+        items.sort_unstable();
+        assert_eq!(items, (0..SENDERS * EACH).collect::<Vec<_>>());
+    }
+}
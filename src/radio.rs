@@ -0,0 +1,370 @@
+use clap::ValueEnum;
+use serialport::SerialPort;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+/// Options for the module and what's done with it on opening and exit
+#[derive(clap::Args)]
+#[command(about = None, long_about = None)]
+pub struct RadioArgs {
+    #[arg(long, value_name = "SECS")]
+    /// Keep retrying to open the port until it appears, optionally giving up after SECS
+    pub wait_for_port: Option<Option<u64>>,
+    #[arg(long, value_name = "MS", num_args = 0..=1, default_missing_value = "100")]
+    /// Hold DTR asserted for MS after opening to reset the radio module
+    pub reset_on_open: Option<u64>,
+    #[arg(long, requires = "reset_on_open")]
+    /// Pulse RTS together with DTR when resetting
+    pub reset_rts: bool,
+    #[arg(long, value_name = "MS", num_args = 0..=1, default_missing_value = "4294967295", value_parser = clap::value_parser!(u32).range(100..))]
+    /// Put the module to sleep for MS on exit to save power (as long as possible if omitted)
+    pub sleep_on_exit: Option<u32>,
+    #[arg(long, value_parser = parse_freq)]
+    /// Radio frequency in Hz or MHz (e.g. 869.525)
+    pub freq: Option<u32>,
+    #[arg(long, value_parser = clap::value_parser!(u8).range(7..=12))]
+    /// LoRa spreading factor
+    pub sf: Option<u8>,
+    #[arg(long, value_name = "KHZ", value_parser = ["125", "250", "500"])]
+    /// LoRa bandwidth in kHz
+    pub bw: Option<String>,
+    #[arg(long, value_name = "4/N", value_parser = parse_coding_rate)]
+    /// LoRa coding rate, either 4/N or just N
+    pub cr: Option<u8>,
+    #[arg(long, value_name = "MS")]
+    /// Receive watchdog timeout, 0 disables it
+    pub wdt: Option<u32>,
+    #[arg(long, value_name = "HEX", value_parser = parse_ack)]
+    /// Transmit this payload after every received packet before listening again
+    pub ack: Option<String>,
+    #[arg(long, value_enum)]
+    /// Turn the radio's CRC check on or off
+    pub crc: Option<Switch>,
+    #[arg(long, value_name = "HEXBYTE", value_parser = parse_sync)]
+    /// LoRa sync word, e.g. 12 for private networks or 34 for LoRaWAN
+    pub sync: Option<u8>,
+    #[arg(long)]
+    /// Don't re-arm receive after each packet, for modules that stay in continuous RX
+    pub no_rearm: bool,
+    #[arg(long, value_name = "RETRIES", default_value_t = 0)]
+    /// Reopen the port up to RETRIES times after the device disappears
+    pub reconnect: u32,
+    #[arg(long, value_name = "MS", default_value_t = 2000)]
+    /// Delay between reconnection attempts
+    pub reconnect_delay: u64,
+    #[arg(long, value_name = "TIMEOUTS", default_value_t = 300)]
+    /// Check the module still answers after this many --timeout-ms in a row with nothing read,
+    /// then arm it again, stopping as if the device were lost if it doesn't; 0 never checks
+    pub probe_after: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Switch {
+    On,
+    Off,
+}
+
+#[derive(Debug)]
+pub enum RadioError {
+    InvalidParam(String),
+    NoReply(String),
+    UnexpectedReply { command: String, reply: String },
+    Io(std::io::Error),
+}
+
+impl Display for RadioError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RadioError::InvalidParam(command) => {
+                write!(f, "Module rejected `{}` as an invalid parameter", command)
+            }
+            RadioError::NoReply(command) => write!(f, "No reply to `{}`", command),
+            RadioError::UnexpectedReply { command, reply } => {
+                write!(f, "Unexpected reply to `{}`: {}", command, reply)
+            }
+            RadioError::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for RadioError {}
+
+impl From<std::io::Error> for RadioError {
+    fn from(error: std::io::Error) -> Self {
+        RadioError::Io(error)
+    }
+}
+
+impl From<serialport::Error> for RadioError {
+    fn from(error: serialport::Error) -> Self {
+        RadioError::Io(error.into())
+    }
+}
+
+/// Reads a single \r\n terminated reply from the module
+pub fn read_reply(serial: &mut Box<dyn SerialPort>) -> std::io::Result<String> {
+    let mut reply = Vec::new();
+    let mut byte = [0];
+    while !reply.ends_with(b"\r\n") {
+        serial.read_exact(&mut byte)?;
+        reply.push(byte[0]);
+    }
+    reply.truncate(reply.len() - 2);
+    Ok(String::from_utf8_lossy(&reply).into_owned())
+}
+
+/// Sends a command and returns the module's reply
+pub fn command(serial: &mut Box<dyn SerialPort>, command: &str) -> Result<String, RadioError> {
+    serial.write_all(format!("{command}\r\n").as_bytes())?;
+    read_reply(serial).map_err(|error| match error.kind() {
+        ErrorKind::TimedOut => RadioError::NoReply(command.to_string()),
+        _ => RadioError::Io(error),
+    })
+}
+
+/// Sends a command that the module acknowledges with `ok`
+pub fn expect_ok(serial: &mut Box<dyn SerialPort>, command: &str) -> Result<(), RadioError> {
+    match self::command(serial, command)?.as_str() {
+        "ok" => Ok(()),
+        "invalid_param" => Err(RadioError::InvalidParam(command.to_string())),
+        reply => Err(RadioError::UnexpectedReply {
+            command: command.to_string(),
+            reply: reply.to_string(),
+        }),
+    }
+}
+
+/// Takes the radio away from the LoRaWAN stack, which otherwise answers `busy` to radio commands
+pub fn pause_mac(serial: &mut Box<dyn SerialPort>) -> Result<(), RadioError> {
+    let reply = command(serial, "mac pause")?;
+    match reply.parse::<u32>() {
+        Ok(0) => tracing::warn!("The LoRaWAN MAC is joined and may interrupt receiving"),
+        Ok(duration) => info!("MAC paused for {duration} ms"),
+        Err(_) => {
+            return Err(RadioError::UnexpectedReply {
+                command: String::from("mac pause"),
+                reply,
+            })
+        }
+    }
+    Ok(())
+}
+
+/// Logs the module's firmware version, which decides how continuous RX and RSSI behave
+pub fn log_version(serial: &mut Box<dyn SerialPort>) -> Result<Option<String>, RadioError> {
+    serial.clear(serialport::ClearBuffer::Input)?;
+    match command(serial, "sys get ver") {
+        Ok(version) if is_version(&version) => {
+            info!("Module firmware: {version}");
+            Ok(Some(version))
+        }
+        Ok(reply) => {
+            tracing::warn!(
+                "Unexpected reply to `sys get ver` ({reply}), this may not be a Microchip LoRa module"
+            );
+            Ok(None)
+        }
+        Err(RadioError::NoReply(_)) => {
+            tracing::warn!("No reply to `sys get ver`, this may not be a Microchip LoRa module");
+            Ok(None)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+/// Applies the radio settings given on the command line, leaving the others untouched
+pub fn configure_radio(
+    serial: &mut Box<dyn SerialPort>,
+    radio: &RadioArgs,
+) -> Result<(), RadioError> {
+    if let Some(freq) = radio.freq {
+        info!("Setting frequency to {:.3} MHz", freq as f64 / 1_000_000.0);
+    }
+    if let Some(sf) = radio.sf {
+        info!("Setting spreading factor to SF{sf}");
+    }
+    if let Some(ref bw) = radio.bw {
+        info!("Setting bandwidth to {bw} kHz");
+    }
+    if let Some(cr) = radio.cr {
+        info!("Setting coding rate to 4/{cr}");
+    }
+    if let Some(sync) = radio.sync {
+        info!("Setting sync word to 0x{sync:02X}");
+    }
+    match radio.crc {
+        Some(Switch::On) => info!("Enabling CRC check"),
+        Some(Switch::Off) => {
+            tracing::warn!("Disabling CRC check, packets won't be checked for corruption")
+        }
+        None => (),
+    }
+    match radio.wdt {
+        Some(0) => info!("Disabling receive watchdog"),
+        Some(wdt) => info!("Setting receive watchdog to {wdt} ms"),
+        None => (),
+    }
+    for command in settings(radio) {
+        expect_ok(serial, &command)?;
+    }
+    Ok(())
+}
+
+/// The `radio set` commands for the settings given on the command line, each answered with `ok`
+pub fn settings(radio: &RadioArgs) -> Vec<String> {
+    let mut commands = Vec::new();
+    if let Some(freq) = radio.freq {
+        commands.push(format!("radio set freq {freq}"));
+    }
+    if let Some(sf) = radio.sf {
+        commands.push(format!("radio set sf sf{sf}"));
+    }
+    if let Some(ref bw) = radio.bw {
+        commands.push(format!("radio set bw {bw}"));
+    }
+    if let Some(cr) = radio.cr {
+        commands.push(format!("radio set cr 4/{cr}"));
+    }
+    if let Some(sync) = radio.sync {
+        commands.push(format!("radio set sync {sync:02X}"));
+    }
+    match radio.crc {
+        Some(Switch::On) => commands.push(String::from("radio set crc on")),
+        Some(Switch::Off) => commands.push(String::from("radio set crc off")),
+        None => (),
+    }
+    if let Some(wdt) = radio.wdt {
+        commands.push(format!("radio set wdt {wdt}"));
+    }
+    commands
+}
+
+/// Wakes the module from a previous --sleep-on-exit with a break followed by the 0x55
+/// auto-baud byte. An awake module takes the 0x55 as the start of a command instead, so the
+/// line is terminated and whatever the module replies is discarded.
+pub fn wake_module(serial: &mut Box<dyn SerialPort>) -> Result<(), serialport::Error> {
+    if let Err(error) = serial.set_break() {
+        debug!("Can't send a break to wake the module: {error}");
+        return Ok(());
+    }
+    std::thread::sleep(Duration::from_millis(10));
+    serial.clear_break()?;
+    serial.write_all(&[0x55])?;
+    std::thread::sleep(Duration::from_millis(10));
+    serial.write_all(b"\r\n")?;
+    std::thread::sleep(Duration::from_millis(100));
+    serial.clear(serialport::ClearBuffer::Input)?;
+    Ok(())
+}
+
+/// Pulses the reset line wired to DTR (and optionally RTS) and waits for the module banner
+pub fn reset_module(
+    serial: &mut Box<dyn SerialPort>,
+    duration: u64,
+    rts: bool,
+) -> Result<(), serialport::Error> {
+    info!("Resetting module...");
+    serial.write_data_terminal_ready(true)?;
+    if rts {
+        serial.write_request_to_send(true)?;
+    }
+    std::thread::sleep(Duration::from_millis(duration));
+    serial.write_data_terminal_ready(false)?;
+    if rts {
+        serial.write_request_to_send(false)?;
+    }
+    serial.clear(serialport::ClearBuffer::Input)?;
+
+    let deadline = Instant::now() + Duration::from_secs(3);
+    while Instant::now() < deadline {
+        match read_reply(serial) {
+            Ok(banner) if banner.is_empty() => (),
+            Ok(banner) => {
+                info!("Module rebooted: {banner}");
+                return Ok(());
+            }
+            Err(ref error) if error.kind() == ErrorKind::TimedOut => (),
+            Err(error) => return Err(error.into()),
+        }
+    }
+    tracing::warn!("No banner received after reset, the module may not have rebooted");
+    Ok(())
+}
+
+/// Takes the radio away from the LoRaWAN stack, applies the settings and starts receiving
+pub fn serial_begin(serial: &mut Box<dyn SerialPort>, radio: &RadioArgs) -> Result<(), RadioError> {
+    info!("Starting serial communication...");
+    serial.clear(serialport::ClearBuffer::Input)?;
+    pause_mac(serial)?;
+    configure_radio(serial, radio)?;
+    // Reading the reply here keeps it from reaching the read loop as an irregular message
+    expect_ok(serial, "radio rx 0")
+}
+
+/// Stops receiving, then puts the module to sleep for `sleep` ms if given
+pub fn serial_end(
+    serial: &mut Box<dyn SerialPort>,
+    sleep: Option<u32>,
+) -> Result<(), serialport::Error> {
+    serial.write_all("radio rxstop\r\n".as_bytes())?;
+    if let Some(duration) = sleep {
+        // The reply to rxstop may land in a read loop, so give the module time to act on it
+        std::thread::sleep(Duration::from_millis(100));
+        serial.write_all(format!("sys sleep {duration}\r\n").as_bytes())?;
+        info!("Module sleeping for {duration} ms");
+    }
+    Ok(())
+}
+
+/// Whether a `sys get ver` reply looks like `RN2483 1.0.5 Oct 31 2018 15:06:52`
+pub fn is_version(reply: &str) -> bool {
+    let mut words = reply.split_whitespace();
+    let model = words.next().unwrap_or_default();
+    let release = words.next().unwrap_or_default();
+    model.starts_with("RN")
+        && release.split('.').count() == 3
+        && release.split('.').all(|part| part.parse::<u8>().is_ok())
+}
+
+/// Parses a frequency given either in Hz (869525000) or MHz (869.525)
+pub fn parse_freq(value: &str) -> Result<u32, String> {
+    let number: f64 = value
+        .parse()
+        .map_err(|_| format!("`{value}` isn't a frequency"))?;
+    // Nothing LoRa transmits below 10 kHz, so small numbers must be MHz
+    let hz = if number < 10_000.0 {
+        (number * 1_000_000.0).round()
+    } else {
+        number
+    };
+    if hz.fract() != 0.0 || !(1.0..=u32::MAX as f64).contains(&hz) {
+        return Err(format!("`{value}` isn't a whole number of Hz"));
+    }
+    Ok(hz as u32)
+}
+
+pub fn parse_ack(value: &str) -> Result<String, String> {
+    match hex::decode(value) {
+        Ok(bytes) if bytes.is_empty() || bytes.len() > 255 => {
+            Err(String::from("the payload must be between 1 and 255 bytes"))
+        }
+        Ok(_) => Ok(value.to_uppercase()),
+        Err(error) => Err(format!("`{value}` isn't hexadecimal: {error}")),
+    }
+}
+
+pub fn parse_sync(value: &str) -> Result<u8, String> {
+    let digits = value.strip_prefix("0x").unwrap_or(value);
+    u8::from_str_radix(digits, 16).map_err(|_| format!("`{value}` isn't a single hexadecimal byte"))
+}
+
+pub fn parse_coding_rate(value: &str) -> Result<u8, String> {
+    let denominator = value.strip_prefix("4/").unwrap_or(value);
+    match denominator.parse() {
+        Ok(n @ 5..=8) => Ok(n),
+        _ => Err(format!("`{value}` isn't one of 4/5, 4/6, 4/7 or 4/8")),
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Joins payloads the sender split over several packets, each tagged `INDEX/COUNT` or
+/// `ID:INDEX/COUNT` in its first field
+pub struct Reassembly {
+    timeout: Duration,
+    /// Fragments received so far, by port and frame id
+    groups: HashMap<(String, String), Group>,
+    reassembled: u64,
+    dropped: u64,
+}
+
+struct Group {
+    count: usize,
+    parts: Vec<String>,
+    started: Instant,
+}
+
+impl Reassembly {
+    pub fn new(timeout: Duration) -> Self {
+        Reassembly {
+            timeout,
+            groups: HashMap::new(),
+            reassembled: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Adds a payload received on `port`, returning it once all fragments are in
+    ///
+    /// Payloads without a fragment tag are returned as they are.
+    pub fn add(&mut self, port: &str, payload: String, delimiter: Option<char>) -> Option<String> {
+        self.expire();
+        let split = match delimiter {
+            Some(delimiter) => payload.split_once(delimiter),
+            None => payload.trim_start().split_once(char::is_whitespace),
+        };
+        let Some(((id, index, count), part)) =
+            split.and_then(|(tag, part)| Some((parse_tag(tag)?, part)))
+        else {
+            return Some(payload);
+        };
+        let key = (port.to_string(), id.to_string());
+
+        if index == 1 {
+            if let Some(group) = self.groups.remove(&key) {
+                self.drop_group(&key, &group, "a new one started");
+            }
+            self.groups.insert(
+                key.clone(),
+                Group {
+                    count,
+                    parts: Vec::with_capacity(count),
+                    started: Instant::now(),
+                },
+            );
+        }
+        let Some(group) = self.groups.get_mut(&key) else {
+            warn!("Dropped fragment {index}/{count} of frame {id:?} from {port} without its first");
+            self.dropped += 1;
+            return None;
+        };
+        if group.count != count || group.parts.len() + 1 != index {
+            let group = self.groups.remove(&key).unwrap();
+            self.drop_group(
+                &key,
+                &group,
+                &format!("fragment {index}/{count} arrived out of order"),
+            );
+            return None;
+        }
+        group.parts.push(part.to_string());
+        if group.parts.len() < group.count {
+            return None;
+        }
+
+        let group = self.groups.remove(&key).unwrap();
+        self.reassembled += 1;
+        let separator = delimiter.map_or(String::from(" "), String::from);
+        Some(group.parts.join(&separator))
+    }
+
+    /// Drops the groups that didn't complete in time
+    fn expire(&mut self) {
+        let expired: Vec<_> = self
+            .groups
+            .iter()
+            .filter(|(_, group)| group.started.elapsed() > self.timeout)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            let group = self.groups.remove(&key).unwrap();
+            self.drop_group(&key, &group, "it timed out");
+        }
+    }
+
+    fn drop_group(&mut self, (port, id): &(String, String), group: &Group, reason: &str) {
+        warn!(
+            "Dropped frame {id:?} from {port} with {} of {} fragments as {reason}",
+            group.parts.len(),
+            group.count
+        );
+        self.dropped += 1;
+    }
+
+    pub fn log_summary(&self) {
+        // Whatever is still waiting for fragments won't get them anymore
+        let dropped = self.dropped + self.groups.len() as u64;
+        info!(
+            "Reassembled {} payloads from fragments, dropped {dropped} incomplete",
+            self.reassembled
+        );
+    }
+}
+
+/// Splits a fragment tag into frame id, 1-based index and fragment count
+fn parse_tag(tag: &str) -> Option<(&str, usize, usize)> {
+    let (id, position) = tag.rsplit_once(':').unwrap_or(("", tag));
+    let (index, count) = position.split_once('/')?;
+    let (index, count) = (index.parse().ok()?, count.parse().ok()?);
+    (1..=count).contains(&index).then_some((id, index, count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    fn add(reassembly: &mut Reassembly, port: &str, payload: &str) -> Option<String> {
+        reassembly.add(port, payload.to_string(), None)
+    }
+
+    #[test]
+    fn fragments_are_joined_in_order() {
+        let mut reassembly = Reassembly::new(TIMEOUT);
+        assert_eq!(add(&mut reassembly, "a", "1/3 21.5 48.2"), None);
+        assert_eq!(add(&mut reassembly, "a", "2/3 1013.25"), None);
+        let joined = add(&mut reassembly, "a", "3/3 -3");
+        assert_eq!(joined.as_deref(), Some("21.5 48.2 1013.25 -3"));
+        let single = add(&mut reassembly, "a", "1/1 7");
+        assert_eq!(single.as_deref(), Some("7"));
+        assert_eq!((reassembly.reassembled, reassembly.dropped), (2, 0));
+        assert!(reassembly.groups.is_empty());
+    }
+
+    #[test]
+    fn untagged_payloads_pass_through() {
+        let mut reassembly = Reassembly::new(TIMEOUT);
+        for payload in ["21.5 48.2", "0/2 21.5", "3/2 21.5", "a/b 21.5", "21.5"] {
+            assert_eq!(add(&mut reassembly, "a", payload).as_deref(), Some(payload));
+        }
+        assert_eq!((reassembly.reassembled, reassembly.dropped), (0, 0));
+    }
+
+    #[test]
+    fn delimited_fragments_are_joined_with_the_delimiter() {
+        let mut reassembly = Reassembly::new(TIMEOUT);
+        assert_eq!(reassembly.add("a", "7:1/2,21.5".into(), Some(',')), None);
+        let joined = reassembly.add("a", "7:2/2,48.2,-3".into(), Some(','));
+        assert_eq!(joined.as_deref(), Some("21.5,48.2,-3"));
+    }
+
+    #[test]
+    fn frames_are_kept_apart_by_id_and_port() {
+        let mut reassembly = Reassembly::new(TIMEOUT);
+        assert_eq!(add(&mut reassembly, "a", "x:1/2 1"), None);
+        assert_eq!(add(&mut reassembly, "a", "y:1/2 2"), None);
+        assert_eq!(add(&mut reassembly, "b", "x:1/2 3"), None);
+        assert_eq!(add(&mut reassembly, "b", "x:2/2 4").as_deref(), Some("3 4"));
+        assert_eq!(add(&mut reassembly, "a", "y:2/2 5").as_deref(), Some("2 5"));
+        assert_eq!(add(&mut reassembly, "a", "x:2/2 6").as_deref(), Some("1 6"));
+        assert_eq!((reassembly.reassembled, reassembly.dropped), (3, 0));
+    }
+
+    #[test]
+    fn missing_and_out_of_order_fragments_drop_the_frame() {
+        let mut reassembly = Reassembly::new(TIMEOUT);
+        // Without its first fragment there's nothing to add to
+        assert_eq!(add(&mut reassembly, "a", "2/3 48.2"), None);
+        assert_eq!(reassembly.dropped, 1);
+
+        // A fragment skipped, the rest of the frame is dropped with it
+        assert_eq!(add(&mut reassembly, "a", "1/3 21.5"), None);
+        assert_eq!(add(&mut reassembly, "a", "3/3 -3"), None);
+        assert_eq!(reassembly.dropped, 2);
+        assert_eq!(add(&mut reassembly, "a", "2/3 48.2"), None);
+        assert_eq!(reassembly.dropped, 3);
+
+        // So is one whose count changes halfway
+        assert_eq!(add(&mut reassembly, "a", "1/3 21.5"), None);
+        assert_eq!(add(&mut reassembly, "a", "2/2 48.2"), None);
+        assert_eq!(reassembly.dropped, 4);
+
+        // A new first fragment replaces the unfinished frame
+        assert_eq!(add(&mut reassembly, "a", "1/2 21.5"), None);
+        assert_eq!(add(&mut reassembly, "a", "1/2 22.0"), None);
+        assert_eq!(reassembly.dropped, 5);
+        assert_eq!(
+            add(&mut reassembly, "a", "2/2 48.2").as_deref(),
+            Some("22.0 48.2")
+        );
+        assert_eq!(reassembly.reassembled, 1);
+        assert!(reassembly.groups.is_empty());
+    }
+
+    #[test]
+    fn unfinished_frames_time_out() {
+        let mut reassembly = Reassembly::new(Duration::from_millis(10));
+        assert_eq!(add(&mut reassembly, "a", "1/2 21.5"), None);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(add(&mut reassembly, "a", "2/2 48.2"), None);
+        // One for the expired frame, one for the fragment left without it
+        assert_eq!(reassembly.dropped, 2);
+        assert!(reassembly.groups.is_empty());
+    }
+
+    #[test]
+    fn tags_need_an_index_within_the_count() {
+        assert_eq!(parse_tag("2/3"), Some(("", 2, 3)));
+        assert_eq!(parse_tag("a:b:1/1"), Some(("a:b", 1, 1)));
+        for tag in ["0/3", "4/3", "1/0", "1-3", "1/x", "/3", ""] {
+            assert_eq!(parse_tag(tag), None, "{tag}");
+        }
+    }
+}
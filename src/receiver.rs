@@ -0,0 +1,1027 @@
+use crate::device::Device;
+use crate::parse::{Encoding, GetDataError};
+use crate::queue::{self, Pushed};
+use crate::radio::{self, RadioArgs, RadioError};
+use crate::serial::LineEnding;
+use serialport::SerialPort;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::io::{self, ErrorKind, Read as _, Write};
+use std::str::{SplitWhitespace, Utf8Error};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info};
+
+/// How a LoRa module reports received packets
+pub trait Framing: Sync {
+    /// How lines reporting a received packet start
+    fn marker(&self) -> &'static str;
+
+    /// Whether the line reports a received packet
+    fn is_packet(&self, line: &str) -> bool {
+        line.starts_with(self.marker())
+    }
+
+    /// Extracts the still encoded payload from a packet line
+    fn payload<'l>(&self, line: &'l str) -> Result<&'l str, GetDataError>;
+
+    /// How payloads are encoded unless they're known to be otherwise
+    fn encoding(&self) -> Encoding;
+
+    /// SNR and RSSI reported as part of the packet line itself
+    fn signal(&self, _line: &str) -> (Option<i8>, Option<i16>) {
+        (None, None)
+    }
+
+    /// Whether `signal` has both for every packet, without asking for them
+    fn reports_signal(&self) -> bool {
+        false
+    }
+}
+
+/// Microchip RN2483/RN2903, reporting packets as `radio_rx <hex>`
+pub struct Rn2483;
+
+impl Rn2483 {
+    /// Splits `radio_rx <data>` into the data and any tokens an adapter appended, however many
+    /// spaces the module put between them
+    fn split(line: &str) -> Result<(&str, SplitWhitespace<'_>), GetDataError> {
+        let mut tokens = line
+            .strip_prefix("radio_rx")
+            .filter(|rest| rest.starts_with(char::is_whitespace))
+            .ok_or(GetDataError::IrregularMessage(
+                "this line doesn't contain any data",
+            ))?
+            .split_whitespace();
+        let data = tokens
+            .next()
+            .ok_or(GetDataError::ParseError("failed to retrieve data"))?;
+        Ok((data, tokens))
+    }
+}
+
+impl Framing for Rn2483 {
+    fn marker(&self) -> &'static str {
+        "radio_rx"
+    }
+
+    fn payload<'l>(&self, line: &'l str) -> Result<&'l str, GetDataError> {
+        let (data, _) = Rn2483::split(line)?;
+        Ok(data)
+    }
+
+    fn encoding(&self) -> Encoding {
+        Encoding::Hex
+    }
+
+    fn signal(&self, line: &str) -> (Option<i8>, Option<i16>) {
+        // Some adapters append the RSSI, otherwise it's left to the follow-up queries
+        let Ok((_, mut metadata)) = Rn2483::split(line) else {
+            return (None, None);
+        };
+        (None, metadata.next().and_then(|rssi| rssi.parse().ok()))
+    }
+}
+
+/// REYAX RYLR896/RYLR998, reporting packets as `+RCV=addr,len,data,rssi,snr`
+pub struct Rylr;
+
+impl Rylr {
+    /// Splits `+RCV=addr,len,data,rssi,snr` into the data and the `,rssi,snr` after it, going
+    /// by the length since the data may contain commas itself
+    fn split(line: &str) -> Result<(&str, &str), GetDataError> {
+        let fields = line
+            .strip_prefix("+RCV=")
+            .ok_or(GetDataError::IrregularMessage(
+                "this line doesn't contain any data",
+            ))?;
+        let mut fields = fields.splitn(3, ',');
+        let _address = fields.next();
+        let length: usize = fields
+            .next()
+            .and_then(|length| length.parse().ok())
+            .ok_or(GetDataError::ParseError("failed to retrieve the length"))?;
+        let rest = fields.next().unwrap_or_default();
+        if !rest.is_char_boundary(length) {
+            return Err(GetDataError::ParseError(
+                "the data is shorter than its length",
+            ));
+        }
+        Ok(rest.split_at(length))
+    }
+}
+
+impl Framing for Rylr {
+    fn marker(&self) -> &'static str {
+        "+RCV="
+    }
+
+    fn payload<'l>(&self, line: &'l str) -> Result<&'l str, GetDataError> {
+        let (data, _) = Rylr::split(line)?;
+        Ok(data)
+    }
+
+    fn encoding(&self) -> Encoding {
+        // AT+SEND takes the data as is
+        Encoding::None
+    }
+
+    fn signal(&self, line: &str) -> (Option<i8>, Option<i16>) {
+        let Ok((_, tail)) = Rylr::split(line) else {
+            return (None, None);
+        };
+        let mut values = tail.trim_start_matches(',').split(',');
+        let rssi = values.next().and_then(|rssi| rssi.parse().ok());
+        let snr = values.next().and_then(|snr| snr.parse().ok());
+        (snr, rssi)
+    }
+
+    fn reports_signal(&self) -> bool {
+        true
+    }
+}
+
+/// Follow-up commands sent from the read loop after a packet, each answered by one line
+#[derive(Clone)]
+pub enum Query<'a> {
+    Snr,
+    Rssi,
+    RxStop,
+    Tx(&'a str),
+    /// The second reply to `radio tx`, once the transmission is over
+    TxDone(&'a str),
+    Rearm,
+    /// Whether the module is still there after a long silence
+    Probe(&'static str),
+    /// A step of setting up and receiving again once the module answered the probe
+    Arm(String),
+}
+
+impl Query<'_> {
+    pub fn command(&self) -> String {
+        match self {
+            Query::Snr => String::from("radio get snr"),
+            Query::Rssi => String::from("radio get rssi"),
+            Query::RxStop => String::from("radio rxstop"),
+            Query::Tx(payload) | Query::TxDone(payload) => format!("radio tx {payload}"),
+            Query::Rearm => String::from("radio rx 0"),
+            Query::Probe(command) => command.to_string(),
+            Query::Arm(command) => command.clone(),
+        }
+    }
+
+    /// How long to wait for the reply before assuming it got lost
+    pub fn deadline(&self) -> Duration {
+        match self {
+            // Transmitting 255 bytes at SF12 takes several seconds
+            Query::TxDone(_) => Duration::from_secs(15),
+            _ => Duration::from_secs(2),
+        }
+    }
+}
+
+/// Collects what's read from a module into lines
+pub struct Lines {
+    ending: LineEnding,
+    /// The bytes as read, which are only taken for text a whole line at a time since a read
+    /// can end in the middle of a character
+    buffer: Vec<u8>,
+}
+
+impl Lines {
+    pub fn new(ending: LineEnding) -> Self {
+        Lines {
+            ending,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Adds what was read
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Takes the next complete line that isn't empty, without the line ending and trailing
+    /// whitespace, or the error of a line that isn't UTF-8, which is dropped
+    pub fn next_line(&mut self) -> Option<Result<String, Utf8Error>> {
+        while let Some((pos, len)) = self.ending.find(&self.buffer) {
+            let line =
+                std::str::from_utf8(&self.buffer[..pos]).map(|line| line.trim_end().to_string());
+            self.buffer.drain(..pos + len);
+            match line {
+                Ok(line) if line.is_empty() => continue,
+                line => return Some(line),
+            }
+        }
+        None
+    }
+
+    /// Forgets the partial line, as when the port went away
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+}
+
+/// A complete line along with the port it was received on
+pub struct Line {
+    pub port: String,
+    pub text: String,
+    /// Signal-to-noise ratio of the packet in dB, when queried
+    pub snr: Option<i8>,
+    /// Signal strength of the packet in dBm, when queried
+    pub rssi: Option<i16>,
+    /// When the line was framed
+    pub received: SystemTime,
+}
+
+impl Line {
+    pub fn new(port: &str, text: String) -> Self {
+        Line {
+            port: port.to_string(),
+            text,
+            snr: None,
+            rssi: None,
+            received: SystemTime::now(),
+        }
+    }
+}
+
+/// Replies meaning the module stopped receiving
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stopped {
+    /// `radio_err`, a receive watchdog timeout or CRC failure
+    RadioErr,
+    /// `busy` in reply to arming receive
+    Busy,
+}
+
+/// Where a [`Reader`] tells what happens to the port, as set up by whatever runs it
+pub trait Link {
+    /// The bytes as they were read, before they're framed
+    fn read(&self, _bytes: &[u8], _received: SystemTime) {}
+
+    /// The module stopped receiving, it's armed again by the reader
+    fn stopped(&self, _reply: Stopped) {}
+
+    /// A line was dropped for the queue being full
+    fn dropped(&self, _line: &Line) {}
+
+    /// The module stopped answering, for `reason`
+    fn lost(&self, _port: &str, _reason: &str) {}
+}
+
+/// Where a read task reads from, and what it tells about the port
+pub trait Source: Sync {
+    type Stream: AsyncRead + Unpin + Send;
+
+    /// The port is still working, whether or not anything came from it
+    fn alive(&self) {}
+
+    /// The port went away, for `reason`
+    fn lost(&self, _port: &str, _reason: &str) {}
+
+    /// Opens the port again after it went away, giving its name as it came back along with it
+    fn reconnect(
+        &self,
+        port: String,
+    ) -> impl Future<Output = Option<(String, Self::Stream)>> + Send;
+}
+
+/// How the ports of a run are received from
+#[derive(Clone, Copy)]
+pub struct Receive<'a> {
+    pub device: &'static dyn Device,
+    pub radio: &'a RadioArgs,
+    pub line_ending: LineEnding,
+    /// How long a read waits for a byte
+    pub timeout: Duration,
+    /// Whether each packet's SNR and RSSI are queried
+    pub signal: bool,
+}
+
+impl Receive<'_> {
+    /// Whether packets come with their signal, queried or reported by the module
+    pub fn signal_columns(&self) -> bool {
+        self.signal || self.device.reports_signal()
+    }
+}
+
+/// What a read task hands the framing task
+pub enum Read {
+    /// Bytes as they were read
+    Bytes(Vec<u8>, SystemTime),
+    /// A read timeout went by without a byte
+    TimedOut,
+    /// The port went away and was opened again under this name, so what was on its way is gone
+    Reconnected(String),
+}
+
+/// Why reading or framing a port came to an end
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Ended {
+    /// `running` was cleared, or there's no more input
+    Stopped,
+    /// Nothing takes the lines anymore
+    Closed,
+    /// The port went away or its module stopped answering, and didn't come back
+    Lost,
+}
+
+/// Turns the bytes from one port into lines for the queue, keeping track of the queries sent
+/// after each packet so their replies end up with it
+pub struct Reader<'a, L: Link> {
+    receive: Receive<'a>,
+    port: String,
+    lines: Lines,
+    exchange: Exchange<'a>,
+    /// What's asked after each packet, which is the same for every one
+    follow_ups: Vec<Query<'a>>,
+    /// Where queries are sent, none when replaying as the replies are already in the log
+    serial: Option<Arc<Mutex<Box<dyn SerialPort>>>>,
+    running: Arc<AtomicBool>,
+    /// Read timeouts in a row without a byte
+    timeouts: u64,
+    sender: queue::Sender<Line>,
+    link: L,
+}
+
+impl<'a, L: Link> Reader<'a, L> {
+    pub fn new(
+        receive: Receive<'a>,
+        port: String,
+        serial: Option<Arc<Mutex<Box<dyn SerialPort>>>>,
+        running: Arc<AtomicBool>,
+        sender: queue::Sender<Line>,
+        link: L,
+    ) -> Self {
+        Reader {
+            receive,
+            port,
+            lines: Lines::new(receive.line_ending),
+            exchange: Exchange::default(),
+            follow_ups: receive.device.follow_ups(receive.radio, receive.signal),
+            serial,
+            running,
+            timeouts: 0,
+            sender,
+            link,
+        }
+    }
+
+    /// Frames the bytes read at `received`
+    pub async fn read(&mut self, bytes: &[u8], received: SystemTime) -> Result<(), Ended> {
+        self.link.read(bytes, received);
+        self.timeouts = 0;
+        self.lines.push(bytes);
+        let Receive { device, radio, .. } = self.receive;
+        let (serial, running, port) = (self.serial.as_deref(), &*self.running, &self.port);
+        let (sender, link) = (&self.sender, &self.link);
+        let exchange = &mut self.exchange;
+        while let Some(text) = self.lines.next_line() {
+            let text = match text {
+                Ok(text) => text,
+                Err(error) => {
+                    tracing::warn!(
+                        port,
+                        kind = "utf8",
+                        "Dropped a line from {port} that isn't UTF-8: {error}"
+                    );
+                    continue;
+                }
+            };
+            let mut line = Line::new(port, text);
+            line.received = received;
+
+            if !device.is_packet(&line.text) {
+                let Some(query) = exchange.queries.pop_front() else {
+                    if let Some(reply) = radio_error(&line.text, port) {
+                        // The module stopped listening, so put it back into receive
+                        link.stopped(reply);
+                        exchange.queries.push_back(Query::Rearm);
+                        exchange.send_next(serial, running, port);
+                    } else {
+                        forward(sender, link, line).await?;
+                    }
+                    continue;
+                };
+                // Replies that don't parse leave the column empty rather than failing
+                match (query, &mut exchange.packet) {
+                    (Query::Snr, Some(packet)) => packet.snr = line.text.parse().ok(),
+                    (Query::Rssi, Some(packet)) => packet.rssi = line.text.parse().ok(),
+                    (Query::Tx(payload), _) if line.text != "ok" => {
+                        tracing::warn!(
+                            "Module on {port} refused to transmit {payload}: {}",
+                            line.text
+                        );
+                        exchange
+                            .queries
+                            .retain(|query| !matches!(query, Query::TxDone(_)));
+                    }
+                    (Query::TxDone(payload), _) => match line.text.as_str() {
+                        "radio_tx_ok" => info!("Transmitted {payload} on {port}"),
+                        reply => {
+                            tracing::warn!("Transmitting {payload} on {port} failed: {reply}")
+                        }
+                    },
+                    // It may not have been receiving, which arming it again puts right
+                    (Query::Probe(command), _) => {
+                        exchange.unanswered = 0;
+                        info!(
+                            "Module on {port} answered `{command}` after a long silence: {}",
+                            line.text
+                        );
+                        let arm = device.arm(radio).into_iter().map(Query::Arm);
+                        exchange.queries.extend(arm);
+                    }
+                    // Only the last step puts it into receive
+                    (Query::Arm(command), _)
+                        if matches!(exchange.queries.front(), Some(Query::Arm(_))) =>
+                    {
+                        debug!("Module on {port} answered `{command}`: {}", line.text)
+                    }
+                    (Query::Arm(_), _) => match line.text.as_str() {
+                        "busy" => info!("Module on {port} was still receiving"),
+                        reply => info!("Armed the module on {port} again ({reply})"),
+                    },
+                    _ => (),
+                }
+            } else {
+                // A reply went missing, so give up on the previous packet's metadata
+                if let Some(packet) = exchange.packet.take() {
+                    forward(sender, link, packet).await?;
+                }
+                exchange.queries.clear();
+                exchange.queries.extend(self.follow_ups.iter().cloned());
+                if self.receive.signal_columns() {
+                    (line.snr, line.rssi) = device.signal(&line.text);
+                }
+                exchange.packet = Some(line);
+            }
+
+            exchange.send_next(serial, running, port);
+            if let Some(packet) = exchange.ready() {
+                forward(sender, link, packet).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Follows up on a read timeout: gives up on an overdue reply, and probes a module that's
+    /// been quiet for long, taking it for lost once it stops answering
+    pub async fn timed_out(&mut self) -> Result<(), Ended> {
+        let Receive { device, radio, .. } = self.receive;
+        let (serial, running, port) = (self.serial.as_deref(), &*self.running, &self.port);
+        let exchange = &mut self.exchange;
+        self.timeouts += 1;
+        let Some(query) = exchange.stalled() else {
+            // A module that missed being armed looks just like a quiet channel
+            let (timeouts, probe_after) = (self.timeouts, radio.probe_after);
+            if probe_after > 0
+                && timeouts.is_multiple_of(probe_after)
+                && exchange.queries.is_empty()
+            {
+                debug!("Nothing from {port} in {timeouts} read timeouts, probing it");
+                exchange.queries.push_back(Query::Probe(device.probe()));
+                exchange.send_next(serial, running, port);
+            }
+            return Ok(());
+        };
+        debug!("No reply to `{}` from {port}", query.command());
+        exchange.queries.clear();
+        if let Query::Probe(command) = query {
+            exchange.unanswered += 1;
+            if exchange.unanswered < PROBES {
+                tracing::warn!(
+                    port,
+                    kind = "probe",
+                    "Module on {port} didn't answer `{command}`, asking again"
+                );
+                exchange.queries.push_back(query);
+                exchange.send_next(serial, running, port);
+                return Ok(());
+            }
+            error!(
+                port,
+                kind = "probe",
+                "Module on {port} didn't answer `{command}` {PROBES} times, giving up on it"
+            );
+            self.link.lost(port, "the module stopped answering");
+            return Err(Ended::Lost);
+        }
+        // There's no telling whether the radio is still listening, a surplus re-arm is
+        // harmless as it's only answered with `busy`
+        if !matches!(query, Query::Rearm | Query::Arm(_)) && !radio.no_rearm {
+            exchange.queries.push_back(Query::Rearm);
+            exchange.send_next(serial, running, port);
+        }
+        if let Some(packet) = exchange.ready() {
+            forward(&self.sender, &self.link, packet).await?;
+        }
+        Ok(())
+    }
+
+    /// Starts over on a port that was opened again, as `port`
+    pub fn reconnected(&mut self, port: String) {
+        self.port = port;
+        self.lines.clear();
+        self.exchange = Exchange::default();
+        self.timeouts = 0;
+    }
+
+    /// Hands on the packet still waiting on replies once there's no more input
+    pub async fn finish(&mut self) -> Result<(), Ended> {
+        match self.exchange.packet.take() {
+            Some(packet) => forward(&self.sender, &self.link, packet).await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Queues a line, telling the link if it was dropped for that
+async fn forward<L: Link>(sender: &queue::Sender<Line>, link: &L, line: Line) -> Result<(), Ended> {
+    match sender.push(line).await {
+        Pushed::Queued => Ok(()),
+        Pushed::Dropped(line) => {
+            link.dropped(&line);
+            Ok(())
+        }
+        Pushed::Closed => Err(Ended::Closed),
+    }
+}
+
+/// Probes a silent module goes without answering before it's taken for lost
+const PROBES: u32 = 3;
+
+/// Frames what a read task hands over until it's done reading or the module is lost
+pub async fn frame<L: Link>(mut reader: Reader<'_, L>, mut reads: mpsc::Receiver<Read>) -> Ended {
+    while let Some(read) = reads.recv().await {
+        let framed = match read {
+            Read::Bytes(bytes, received) => reader.read(&bytes, received).await,
+            Read::TimedOut => reader.timed_out().await,
+            Read::Reconnected(port) => {
+                reader.reconnected(port);
+                Ok(())
+            }
+        };
+        if let Err(ended) = framed {
+            return ended;
+        }
+    }
+    Ended::Stopped
+}
+
+/// What goes wrong reading a port, other than it going away, which [`Ended::Lost`] tells
+#[derive(Debug)]
+pub enum ReceiverError {
+    /// Reading failed for another reason than a timeout, a signal or a disconnect
+    Io(io::Error),
+    /// A line from a [`Receiver`] isn't UTF-8, it's dropped and the next one can be read
+    Utf8(Utf8Error),
+}
+
+impl Display for ReceiverError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReceiverError::Io(error) => write!(f, "{error}"),
+            ReceiverError::Utf8(error) => write!(f, "The line isn't UTF-8: {error}"),
+        }
+    }
+}
+
+impl Error for ReceiverError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ReceiverError::Io(error) => Some(error),
+            ReceiverError::Utf8(error) => Some(error),
+        }
+    }
+}
+
+/// A LoRa module on a serial port, for embedding without the async read loop: it sends the
+/// device's commands and frames what the module sends back into lines
+///
+/// Lines come as they are, without the SNR and RSSI queries the read loop sends after each
+/// packet, so only a module reporting them along with the packet fills them in.
+pub struct Receiver {
+    serial: Box<dyn SerialPort>,
+    device: &'static dyn Device,
+    port: String,
+    lines: Lines,
+    buffer: Vec<u8>,
+}
+
+impl Receiver {
+    /// Receives on the open port, which the lines are labelled with
+    pub fn new(
+        serial: Box<dyn SerialPort>,
+        port: &str,
+        device: &'static dyn Device,
+        ending: LineEnding,
+    ) -> Self {
+        Receiver {
+            serial,
+            device,
+            port: port.to_string(),
+            lines: Lines::new(ending),
+            buffer: vec![0; 1024],
+        }
+    }
+
+    /// Sets the module up with the settings and puts it into receive, returning its firmware
+    /// version if it reported one
+    pub fn start(&mut self, radio: &RadioArgs) -> Result<Option<String>, RadioError> {
+        self.lines.clear();
+        crate::device::start_receiver(&mut self.serial, self.device, radio)
+    }
+
+    /// Sends a command and returns the module's reply, which is only the reply while no packet
+    /// comes in, as when it isn't receiving
+    pub fn command(&mut self, command: &str) -> Result<String, RadioError> {
+        radio::command(&mut self.serial, command)
+    }
+
+    /// The next line from the module, or `None` if the port's timeout went by before one was
+    /// complete
+    pub fn next_line(&mut self) -> Result<Option<Line>, ReceiverError> {
+        loop {
+            if let Some(text) = self.lines.next_line() {
+                let mut line = Line::new(&self.port, text.map_err(ReceiverError::Utf8)?);
+                if self.device.is_packet(&line.text) && self.device.reports_signal() {
+                    (line.snr, line.rssi) = self.device.signal(&line.text);
+                }
+                return Ok(Some(line));
+            }
+            match self.serial.read(&mut self.buffer) {
+                Ok(n) => self.lines.push(&self.buffer[..n]),
+                Err(error) if error.kind() == ErrorKind::TimedOut => return Ok(None),
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(ReceiverError::Io(error)),
+            }
+        }
+    }
+
+    /// Stops receiving, then puts the module to sleep for `sleep` ms if given
+    pub fn stop(&mut self, sleep: Option<u32>) -> Result<(), RadioError> {
+        Ok(self.device.end(&mut self.serial, sleep)?)
+    }
+
+    /// The port, for anything the receiver doesn't cover
+    pub fn serial(&mut self) -> &mut Box<dyn SerialPort> {
+        &mut self.serial
+    }
+}
+
+/// Reads from one port until `running` is cleared, handing what's read to the framing task
+/// along with the read timeouts that went by without a byte
+pub async fn read_port<S: Source>(
+    source: &S,
+    mut port: String,
+    mut stream: S::Stream,
+    receive: Receive<'_>,
+    running: &AtomicBool,
+    reads: &mpsc::Sender<Read>,
+) -> Result<Ended, ReceiverError> {
+    let mut buffer: Vec<u8> = vec![0; 1024];
+    while running.load(Ordering::SeqCst) {
+        // Bounded by --timeout-ms, so `running` is looked at again at least that often
+        let read = tokio::time::timeout(receive.timeout, stream.read(&mut buffer))
+            .await
+            .unwrap_or_else(|_| Err(ErrorKind::TimedOut.into()));
+        let read = match read {
+            // A port only comes to an end once it's gone, as serialport reports a hangup
+            Ok(0) => Err(io::Error::new(ErrorKind::BrokenPipe, "Broken pipe")),
+            read => read,
+        };
+        let sent = match read {
+            Ok(n) => {
+                source.alive();
+                reads
+                    .send(Read::Bytes(buffer[..n].to_vec(), SystemTime::now()))
+                    .await
+            }
+            Err(ref error) if error.kind() == ErrorKind::TimedOut => {
+                // A quiet radio is still a working port
+                source.alive();
+                reads.send(Read::TimedOut).await
+            }
+            // A signal, in which case `running` says whether it's time to stop
+            Err(ref error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(ref error) if is_disconnect(error) => {
+                error!(
+                    port,
+                    kind = "disconnect",
+                    "Lost connection to {port}: {error}"
+                );
+                source.lost(&port, &error.to_string());
+                if receive.radio.reconnect == 0 {
+                    return Ok(Ended::Lost);
+                }
+                match source.reconnect(port.clone()).await {
+                    Some((name, new_stream)) => {
+                        (port, stream) = (name, new_stream);
+                        reads.send(Read::Reconnected(port.clone())).await
+                    }
+                    None if !running.load(Ordering::SeqCst) => return Ok(Ended::Stopped),
+                    None => {
+                        error!(
+                            "Failed to reconnect to {port} after {} attempts",
+                            receive.radio.reconnect
+                        );
+                        return Ok(Ended::Lost);
+                    }
+                }
+            }
+            Err(error) => return Err(ReceiverError::Io(error)),
+        };
+        if sent.is_err() {
+            return Ok(Ended::Closed);
+        }
+    }
+    Ok(Ended::Stopped)
+}
+
+/// Whether a read error means the device went away rather than a transient failure
+pub fn is_disconnect(error: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    if let Some(5 | 6 | 19) = error.raw_os_error() {
+        // EIO, ENXIO and ENODEV are reported once a USB serial adapter is unplugged
+        return true;
+    }
+    matches!(
+        error.kind(),
+        ErrorKind::BrokenPipe | ErrorKind::NotConnected | ErrorKind::UnexpectedEof
+    )
+}
+
+/// Recognizes replies that mean the radio stopped receiving
+fn radio_error(text: &str, port: &str) -> Option<Stopped> {
+    match text {
+        "radio_err" => {
+            tracing::warn!(
+                port,
+                kind = "radio_err",
+                "Reception on {port} failed (watchdog timeout or CRC error)"
+            );
+            Some(Stopped::RadioErr)
+        }
+        "busy" => {
+            tracing::warn!(
+                port,
+                kind = "busy",
+                "Module on {port} was busy and didn't start receiving"
+            );
+            Some(Stopped::Busy)
+        }
+        _ => None,
+    }
+}
+
+/// The queries issued after the last packet, which is held back until its metadata arrives
+#[derive(Default)]
+struct Exchange<'a> {
+    /// Queries still waiting for a reply, the front one has already been sent
+    queries: VecDeque<Query<'a>>,
+    /// When the front query was sent
+    sent: Option<Instant>,
+    packet: Option<Line>,
+    /// Probes in a row the module didn't answer
+    unanswered: u32,
+}
+
+impl<'a> Exchange<'a> {
+    /// Sends the query at the front of the queue, giving up on the rest if that fails
+    ///
+    /// Without a port the query only counts as sent, for replies that are already on their way.
+    fn send_next(
+        &mut self,
+        serial: Option<&Mutex<Box<dyn SerialPort>>>,
+        running: &AtomicBool,
+        port: &str,
+    ) {
+        let Some(query) = self.queries.front() else {
+            return;
+        };
+        self.sent = Some(Instant::now());
+        if let Query::TxDone(_) = query {
+            // Sent along with Query::Tx, only its reply is outstanding
+            return;
+        }
+        let Some(serial) = serial else {
+            return;
+        };
+        if let Query::Tx(payload) = query {
+            debug!("Transmitting {payload} on {port}");
+        }
+        match send_query(serial, running, &query.command()) {
+            Ok(true) => (),
+            Ok(false) => self.queries.clear(),
+            Err(error) => {
+                tracing::warn!("Failed to send `{}` to {port}: {error}", query.command());
+                self.queries.clear();
+            }
+        }
+    }
+
+    /// The front query if its reply is overdue
+    fn stalled(&self) -> Option<Query<'a>> {
+        let query = self.queries.front()?;
+        self.sent
+            .is_some_and(|sent| sent.elapsed() > query.deadline())
+            .then(|| query.clone())
+    }
+
+    /// Takes the held packet once no more of its metadata is outstanding
+    fn ready(&mut self) -> Option<Line> {
+        if self
+            .queries
+            .iter()
+            .any(|query| matches!(query, Query::Snr | Query::Rssi))
+        {
+            None
+        } else {
+            self.packet.take()
+        }
+    }
+}
+
+/// Writes a command from the read loop, unless the capture is stopping
+fn send_query(
+    serial: &Mutex<Box<dyn SerialPort>>,
+    running: &AtomicBool,
+    command: &str,
+) -> std::io::Result<bool> {
+    // Whoever stops the radio clears `running` before taking the lock to send `radio rxstop`,
+    // so checking it under the lock guarantees the stop is never followed by a re-arm
+    let mut serial = serial.lock().unwrap();
+    if !running.load(Ordering::SeqCst) {
+        return Ok(false);
+    }
+    serial.write_all(format!("{command}\r\n").as_bytes())?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::DeviceKind;
+    use clap::{Args, FromArgMatches};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::ReadBuf;
+
+    /// Reads out what it's given in turn, then comes to an end like an unplugged port
+    struct Scripted(VecDeque<io::Result<Vec<u8>>>);
+
+    impl AsyncRead for Scripted {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Poll::Ready(match self.0.pop_front() {
+                Some(read) => read.map(|bytes| buf.put_slice(&bytes)),
+                None => Ok(()),
+            })
+        }
+    }
+
+    struct Unplugged;
+
+    impl Source for Unplugged {
+        type Stream = Scripted;
+
+        async fn reconnect(&self, _port: String) -> Option<(String, Scripted)> {
+            None
+        }
+    }
+
+    struct Quiet;
+
+    impl Link for Quiet {}
+
+    #[test]
+    fn lines_in_one_chunk() {
+        let mut lines = Lines::new(LineEnding::Crlf);
+        lines.push(b"radio_rx 0102\r\nok\r\n\r\nradio_rx 0304\r\nradio_");
+        assert_eq!(lines.next_line().unwrap().unwrap(), "radio_rx 0102");
+        assert_eq!(lines.next_line().unwrap().unwrap(), "ok");
+        assert_eq!(lines.next_line().unwrap().unwrap(), "radio_rx 0304");
+        assert!(lines.next_line().is_none());
+    }
+
+    #[test]
+    fn line_across_chunks() {
+        let mut lines = Lines::new(LineEnding::Crlf);
+        lines.push(b"radio_rx 01");
+        assert!(lines.next_line().is_none());
+        lines.push(b"02\r");
+        assert!(lines.next_line().is_none());
+        lines.push(b"\nradio_rx 03");
+        assert_eq!(lines.next_line().unwrap().unwrap(), "radio_rx 0102");
+        assert!(lines.next_line().is_none());
+    }
+
+    #[test]
+    fn rn2483_double_space() {
+        assert_eq!(
+            Rn2483.payload("radio_rx  48656C6C6F").unwrap(),
+            "48656C6C6F"
+        );
+        assert_eq!(
+            Rn2483.signal("radio_rx  48656C6C6F  -87"),
+            (None, Some(-87))
+        );
+        assert!(Rn2483.payload("radio_rx48656C6C6F").is_err());
+    }
+
+    #[test]
+    fn character_across_chunks() {
+        let mut lines = Lines::new(LineEnding::Crlf);
+        let text = "+RCV=1,3,°C,-40,9\r\n".as_bytes();
+        // The first byte of the two of `°`
+        let split = text.iter().position(|&byte| byte == 0xC2).unwrap() + 1;
+        lines.push(&text[..split]);
+        assert!(lines.next_line().is_none());
+        lines.push(&text[split..]);
+        assert_eq!(lines.next_line().unwrap().unwrap(), "+RCV=1,3,°C,-40,9");
+    }
+
+    #[test]
+    fn invalid_line_is_dropped() {
+        let mut lines = Lines::new(LineEnding::Crlf);
+        lines.push(b"radio_rx \xff\r\nok\r\n");
+        assert!(lines.next_line().unwrap().is_err());
+        assert_eq!(lines.next_line().unwrap().unwrap(), "ok");
+    }
+
+    fn radio_args(args: &[&str]) -> RadioArgs {
+        let command = RadioArgs::augment_args(clap::Command::new("test"));
+        RadioArgs::from_arg_matches(&command.get_matches_from(args)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn answered_probe_arms_again() {
+        let radio = radio_args(&["test", "--sf", "7"]);
+        let receive = Receive {
+            device: DeviceKind::Rn2483.profile(),
+            radio: &radio,
+            line_ending: LineEnding::Crlf,
+            timeout: Duration::from_secs(1),
+            signal: false,
+        };
+        let running = Arc::new(AtomicBool::new(true));
+        let (sender, _lines) = queue::bounded(8, queue::WhenFull::Block);
+        let mut reader = Reader::new(receive, "test".into(), None, running, sender, Quiet);
+        reader
+            .exchange
+            .queries
+            .push_back(Query::Probe("sys get ver"));
+        let now = SystemTime::now();
+        reader.read(b"RN2483 1.0.5\r\n", now).await.unwrap();
+        let commands: Vec<String> = reader.exchange.queries.iter().map(Query::command).collect();
+        assert_eq!(commands, ["mac pause", "radio set sf sf7", "radio rx 0"]);
+        reader
+            .read(b"4294967245\r\nok\r\nok\r\n", now)
+            .await
+            .unwrap();
+        assert!(reader.exchange.queries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn read_goes_on_after_interrupt() {
+        let radio = radio_args(&["test"]);
+        let receive = Receive {
+            device: DeviceKind::Rn2483.profile(),
+            radio: &radio,
+            line_ending: LineEnding::Crlf,
+            timeout: Duration::from_secs(1),
+            signal: false,
+        };
+        let stream = Scripted(VecDeque::from([
+            Ok(b"radio_rx 0102\r\nradio_".to_vec()),
+            Err(ErrorKind::Interrupted.into()),
+            Ok(b"rx 0304\r\n".to_vec()),
+        ]));
+        let running = Arc::new(AtomicBool::new(true));
+        let (sender, mut lines) = queue::bounded(8, queue::WhenFull::Block);
+        let (reads, framed) = mpsc::channel(8);
+        let reader = Reader::new(receive, "test".into(), None, running.clone(), sender, Quiet);
+        let read = async {
+            let ended = read_port(&Unplugged, "test".into(), stream, receive, &running, &reads);
+            let ended = ended.await.unwrap();
+            // What was read before the port went away is still framed
+            drop(reads);
+            ended
+        };
+        let (read, framed) = tokio::join!(read, frame(reader, framed));
+        assert_eq!((read, framed), (Ended::Lost, Ended::Stopped));
+        assert_eq!(lines.recv().await.unwrap().text, "radio_rx 0102");
+        assert_eq!(lines.recv().await.unwrap().text, "radio_rx 0304");
+        assert!(lines.recv().await.is_none());
+    }
+}
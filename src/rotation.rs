@@ -0,0 +1,268 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum RotateIndex {
+    /// Keep counting across files
+    Continue,
+    /// Count from 0 in every new file
+    Reset,
+}
+
+/// Parses a period like `90s`, `30m`, `1h` or `1d`, plain numbers are seconds
+pub fn parse_period(value: &str) -> Result<Duration, String> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("`{value}` doesn't start with a number"))?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("`{unit}` isn't one of s, m, h or d")),
+    };
+    match number.checked_mul(seconds) {
+        Some(0) => Err(String::from("the period can't be 0")),
+        Some(seconds) => Ok(Duration::from_secs(seconds)),
+        None => Err(format!("`{value}` is too long")),
+    }
+}
+
+/// Parses a size like `500K`, `50MB` or `1GiB`, plain numbers are bytes
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("`{value}` doesn't start with a number"))?;
+    let bytes: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1000,
+        "M" | "MB" => 1000 * 1000,
+        "G" | "GB" => 1000 * 1000 * 1000,
+        "KIB" => 1 << 10,
+        "MIB" => 1 << 20,
+        "GIB" => 1 << 30,
+        _ => return Err(format!("`{unit}` isn't a size unit like KB, MB or GiB")),
+    };
+    match number.checked_mul(bytes) {
+        Some(0) => Err(String::from("the size can't be 0")),
+        Some(bytes) => Ok(bytes),
+        None => Err(format!("`{value}` is too big")),
+    }
+}
+
+/// Checks the output path is a pattern `Rotation` can format
+pub fn check_pattern(path: &Path) -> Result<(), String> {
+    let pattern = path.to_string_lossy();
+    match StrftimeItems::new(&pattern).any(|item| item == Item::Error) {
+        true => Err(format!("{pattern} isn't a valid strftime pattern")),
+        false => Ok(()),
+    }
+}
+
+/// Moves a file output on to a new file every period or once the current one grows too big
+///
+/// The file names come from the `--output` path as a strftime pattern in UTC, periods start at
+/// whole multiples since the epoch so `1h` rotates on the hour.
+pub struct Rotation {
+    pattern: String,
+    every: Option<Duration>,
+    size: Option<u64>,
+    index: RotateIndex,
+    /// When the current file's period ends
+    until: Option<SystemTime>,
+    /// Index of the first record in the current file
+    first: Option<usize>,
+}
+
+impl Rotation {
+    /// Rotates every period, once the file reaches the size in bytes, or whichever comes
+    /// first, and not at all without either
+    pub fn new(
+        pattern: &Path,
+        every: Option<Duration>,
+        size: Option<u64>,
+        index: RotateIndex,
+    ) -> Option<Self> {
+        if every.is_none() && size.is_none() {
+            return None;
+        }
+        Some(Rotation {
+            pattern: pattern.to_string_lossy().into_owned(),
+            every,
+            size,
+            index,
+            until: None,
+            first: None,
+        })
+    }
+
+    /// The file to start with, which is appended to if it exists
+    pub fn first_path(&mut self) -> PathBuf {
+        let now = SystemTime::now();
+        self.until = self.period_end(now);
+        self.format(now)
+    }
+
+    /// The file to move on to before writing the next record, if it's time for one
+    ///
+    /// Names the pattern gives again, as when it's only down to the hour but the size runs out
+    /// sooner, get a counter so finished files are never appended to.
+    pub fn next_path(&mut self, current: &Path, bytes: u64) -> Option<PathBuf> {
+        let now = SystemTime::now();
+        let due = self.until.is_some_and(|until| now >= until)
+            || self.size.is_some_and(|size| bytes >= size);
+        if !due {
+            return None;
+        }
+        self.until = self.period_end(now);
+        self.first = None;
+        let path = self.format(now);
+        if path != current && !path.exists() {
+            return Some(path);
+        }
+        // Before all of an extension like .csv.gz
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let (stem, extension) = match name.find('.') {
+            Some(0) | None => (&name[..], ""),
+            Some(dot) => name.split_at(dot),
+        };
+        (1..)
+            .map(|counter| path.with_file_name(format!("{stem}-{counter}{extension}")))
+            .find(|path| !path.exists())
+    }
+
+    /// The index to write for the record, counted from the current file's first when resetting
+    pub fn index(&mut self, index: usize) -> usize {
+        match self.index {
+            RotateIndex::Continue => index,
+            RotateIndex::Reset => index - *self.first.get_or_insert(index),
+        }
+    }
+
+    fn period_end(&self, now: SystemTime) -> Option<SystemTime> {
+        let every = self.every?.as_secs();
+        let since = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        Some(UNIX_EPOCH + Duration::from_secs((since / every + 1) * every))
+    }
+
+    fn format(&self, now: SystemTime) -> PathBuf {
+        PathBuf::from(DateTime::<Utc>::from(now).format(&self.pattern).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of its own, removed with everything in it at the end of the test
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(test: &str) -> Self {
+            let directory =
+                std::env::temp_dir().join(format!("charter-{test}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&directory);
+            std::fs::create_dir_all(&directory).unwrap();
+            Scratch(directory)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn by_size(pattern: &Path, index: RotateIndex) -> Rotation {
+        Rotation::new(pattern, None, Some(100), index).unwrap()
+    }
+
+    #[test]
+    fn periods_and_sizes_are_parsed() {
+        assert_eq!(parse_period("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_period("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_period("1d"), Ok(Duration::from_secs(24 * 60 * 60)));
+        for period in ["0s", "m", "1w", "-1h", "99999999999999999999d"] {
+            assert!(parse_period(period).is_err(), "{period}");
+        }
+        assert_eq!(parse_size("500K"), Ok(500_000));
+        assert_eq!(parse_size("50mb"), Ok(50_000_000));
+        assert_eq!(parse_size("1GiB"), Ok(1 << 30));
+        for size in ["0", "KB", "1TB", "99999999999999999999"] {
+            assert!(parse_size(size).is_err(), "{size}");
+        }
+    }
+
+    #[test]
+    fn periods_end_on_whole_multiples_since_the_epoch() {
+        let pattern = Path::new("data.csv");
+        assert!(Rotation::new(pattern, None, None, RotateIndex::Continue).is_none());
+        let hourly = Rotation::new(
+            pattern,
+            Some(Duration::from_secs(3600)),
+            None,
+            RotateIndex::Continue,
+        )
+        .unwrap();
+        let at = |seconds| UNIX_EPOCH + Duration::from_secs(seconds);
+        assert_eq!(hourly.period_end(at(3601)), Some(at(7200)));
+        assert_eq!(hourly.period_end(at(7200)), Some(at(10800)));
+        assert_eq!(
+            by_size(pattern, RotateIndex::Continue).period_end(at(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn a_full_file_moves_on_to_a_counted_name() {
+        let scratch = Scratch::new("rotation-counter");
+        let pattern = scratch.0.join("data.csv.gz");
+        let mut rotation = by_size(&pattern, RotateIndex::Continue);
+        let first = rotation.first_path();
+        assert_eq!(first, pattern);
+        assert_eq!(rotation.next_path(&first, 99), None);
+
+        let next = rotation.next_path(&first, 100).unwrap();
+        assert_eq!(next, scratch.0.join("data-1.csv.gz"));
+        std::fs::write(&first, "").unwrap();
+        std::fs::write(&next, "").unwrap();
+        let next = rotation.next_path(&next, 200).unwrap();
+        assert_eq!(next, scratch.0.join("data-2.csv.gz"));
+    }
+
+    #[test]
+    fn the_index_carries_over_or_restarts_in_each_file() {
+        let pattern = Path::new("charter-rotation-index/data.csv");
+        let mut continued = by_size(pattern, RotateIndex::Continue);
+        let mut reset = by_size(pattern, RotateIndex::Reset);
+        let first = continued.first_path();
+        reset.first_path();
+
+        // The first record written needn't be number 0, as when appending to a file
+        let indices = |rotation: &mut Rotation, range: std::ops::Range<usize>| {
+            range.map(|index| rotation.index(index)).collect::<Vec<_>>()
+        };
+        assert_eq!(indices(&mut continued, 5..8), [5, 6, 7]);
+        assert_eq!(indices(&mut reset, 5..8), [0, 1, 2]);
+
+        // Not due yet, so still the same file
+        assert!(reset.next_path(&first, 50).is_none());
+        assert_eq!(indices(&mut reset, 8..9), [3]);
+
+        assert!(continued.next_path(&first, 100).is_some());
+        assert!(reset.next_path(&first, 100).is_some());
+        assert_eq!(indices(&mut continued, 9..11), [9, 10]);
+        assert_eq!(indices(&mut reset, 9..11), [0, 1]);
+    }
+}
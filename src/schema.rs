@@ -0,0 +1,672 @@
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// Names and types of the payload fields, loaded from a TOML file of `[[field]]` tables:
+///
+/// ```toml
+/// [[field]]
+/// name = "altitude"
+/// type = "float"
+/// ```
+///
+/// Binary payloads are laid out with the fixed-width types, optionally big-endian. Numeric
+/// values can be turned into `raw * scale + offset` with a fixed number of decimals:
+///
+/// ```toml
+/// [[field]]
+/// name = "lat"
+/// type = "i32"
+/// endian = "big"
+/// scale = 1e-7
+/// precision = 7
+/// ```
+///
+/// The packet counter is marked with `sequence = true`.
+///
+/// Coordinates are marked with `coordinate = "lat"` or `"lon"` and converted to signed decimal
+/// degrees from `input = "nmea"` (ddmm.mmmm, optionally followed by N/S/E/W) or `"decimal"`.
+/// A timestamp field is marked with `timestamp = "ms"` or `"s"` since boot, or `"unix"` or
+/// `"unix_ms"` since the epoch, and gets a `<name>_time` column with the absolute time.
+///
+/// With `gps_valid = true` at the top of the file, a column after the fields tells whether
+/// the fix is plausible.
+///
+/// Records posted to InfluxDB go to the `measurement` named at the top of the file, with the
+/// fields marked `tag = true` as tags.
+#[derive(Clone, Deserialize)]
+pub struct Schema {
+    #[serde(rename = "field")]
+    pub fields: Vec<Field>,
+    #[serde(default)]
+    pub gps_valid: bool,
+    pub measurement: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Field {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: FieldType,
+    #[serde(default)]
+    pub endian: Endian,
+    /// Factor applied to numeric values, turning them into floats
+    pub scale: Option<f64>,
+    /// Added to numeric values after scaling
+    pub offset: Option<f64>,
+    /// Decimals printed for numeric values after the transform
+    pub precision: Option<usize>,
+    /// Whether this is the packet counter used to detect loss
+    #[serde(default)]
+    pub sequence: bool,
+    pub coordinate: Option<Coordinate>,
+    /// How the coordinate is written in the payload
+    #[serde(default)]
+    pub input: CoordinateFormat,
+    pub timestamp: Option<TimeUnit>,
+    /// Whether InfluxDB gets this as a tag rather than a field
+    #[serde(default)]
+    pub tag: bool,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeUnit {
+    /// Milliseconds since boot
+    Ms,
+    /// Seconds since boot
+    S,
+    /// Seconds since the unix epoch
+    Unix,
+    /// Milliseconds since the unix epoch
+    UnixMs,
+}
+
+impl TimeUnit {
+    pub fn is_boot(self) -> bool {
+        matches!(self, TimeUnit::Ms | TimeUnit::S)
+    }
+
+    /// Length of one unit in seconds
+    pub fn seconds(self) -> f64 {
+        match self {
+            TimeUnit::Ms | TimeUnit::UnixMs => 0.001,
+            TimeUnit::S | TimeUnit::Unix => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Coordinate {
+    Lat,
+    Lon,
+}
+
+impl Coordinate {
+    fn limit(self) -> f64 {
+        match self {
+            Coordinate::Lat => 90.0,
+            Coordinate::Lon => 180.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoordinateFormat {
+    #[default]
+    Decimal,
+    Nmea,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    Int,
+    Float,
+    String,
+    Bool,
+    Hex,
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    F64,
+}
+
+impl FieldType {
+    /// Number of bytes the type takes up in a binary payload, if it has a fixed width
+    pub fn width(self) -> Option<usize> {
+        match self {
+            FieldType::U8 | FieldType::I8 => Some(1),
+            FieldType::U16 | FieldType::I16 => Some(2),
+            FieldType::U32 | FieldType::I32 | FieldType::F32 => Some(4),
+            FieldType::F64 => Some(8),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+/// A field value converted according to its type
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Fixed(f64, usize),
+    String(String),
+    Bool(bool),
+    Hex(Vec<u8>),
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", value),
+            Value::Fixed(value, precision) => write!(f, "{:.*}", precision, value),
+            Value::String(value) => write!(f, "{}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Hex(value) => write!(f, "{}", hex::encode_upper(value)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidField {
+    pub name: String,
+    pub value: String,
+    pub kind: &'static str,
+}
+
+impl Display for InvalidField {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid value for field {}: `{}` isn't {}",
+            self.name, self.value, self.kind
+        )
+    }
+}
+
+impl Error for InvalidField {}
+
+#[derive(Debug)]
+pub struct LengthMismatch {
+    pub expected: usize,
+    pub received: usize,
+}
+
+impl Display for LengthMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Error while parsing data: expected {} bytes, received {}",
+            self.expected, self.received
+        )
+    }
+}
+
+impl Error for LengthMismatch {}
+
+impl Schema {
+    /// Drops every transform so values come out as received
+    pub fn strip_transforms(&mut self) {
+        self.gps_valid = false;
+        for field in &mut self.fields {
+            field.scale = None;
+            field.offset = None;
+            field.precision = None;
+            field.coordinate = None;
+        }
+    }
+
+    /// Position and unit of the timestamp field, if there is one
+    pub fn timestamp(&self) -> Option<(usize, TimeUnit)> {
+        self.fields
+            .iter()
+            .enumerate()
+            .find_map(|(index, field)| Some((index, field.timestamp?)))
+    }
+
+    /// Columns charter adds after the fields
+    pub fn derived_columns(&self) -> Vec<String> {
+        let mut columns = Vec::new();
+        if self.gps_valid {
+            columns.push(String::from("gps_valid"));
+        }
+        if let Some((index, _)) = self.timestamp() {
+            columns.push(format!("{}_time", self.fields[index].name));
+        }
+        columns
+    }
+
+    /// Checks the converted coordinates for a plausible fix, warning about each problem.
+    /// Returns None if the schema has no coordinates.
+    pub fn check_gps(&self, data: &[impl AsRef<str>]) -> Option<bool> {
+        let mut valid = true;
+        let mut origin = true;
+        let mut any = false;
+        for (field, value) in self.fields.iter().zip(data) {
+            let Some(coordinate) = field.coordinate else {
+                continue;
+            };
+            any = true;
+            let value = value.as_ref();
+            match value.parse::<f64>() {
+                Ok(degrees) if degrees.abs() <= coordinate.limit() => origin &= degrees == 0.0,
+                _ => {
+                    tracing::warn!("Impossible {}: {value:?}", field.name);
+                    valid = false;
+                    origin = false;
+                }
+            }
+        }
+        if any && origin {
+            tracing::warn!("GPS reports a 0,0 fix, which usually means it has none");
+            valid = false;
+        }
+        any.then_some(valid)
+    }
+
+    /// Converts each item of a payload with the field at the same position
+    pub fn convert(&self, items: &[impl AsRef<str>]) -> Result<Vec<Value>, InvalidField> {
+        self.fields
+            .iter()
+            .zip(items)
+            .map(|(field, item)| field.convert(item.as_ref()))
+            .collect()
+    }
+
+    /// Size of a binary payload, or the first field that can't be part of one
+    pub fn binary_width(&self) -> Result<usize, &Field> {
+        self.fields
+            .iter()
+            .map(|field| field.kind.width().ok_or(field))
+            .sum()
+    }
+
+    /// Reads the fields of a binary payload one after the other
+    pub fn decode(&self, bytes: &[u8]) -> Result<Vec<Value>, LengthMismatch> {
+        let expected = self.binary_width().unwrap_or_default();
+        if bytes.len() != expected {
+            return Err(LengthMismatch {
+                expected,
+                received: bytes.len(),
+            });
+        }
+        let mut rest = bytes;
+        let mut values = Vec::with_capacity(self.fields.len());
+        for field in &self.fields {
+            let (raw, next) = rest.split_at(field.kind.width().unwrap_or_default());
+            rest = next;
+            values.push(field.decode(raw));
+        }
+        Ok(values)
+    }
+}
+
+impl Field {
+    pub fn convert(&self, item: &str) -> Result<Value, InvalidField> {
+        let invalid = |kind| InvalidField {
+            name: self.name.clone(),
+            value: item.to_string(),
+            kind,
+        };
+        if self.coordinate.is_some() {
+            let degrees = parse_coordinate(item, self.input).ok_or(invalid("a coordinate"))?;
+            return Ok(self.rounded(degrees));
+        }
+        let int = |parsed: Option<i64>| parsed.map(Value::Int).ok_or(invalid("in range"));
+        let value = match self.kind {
+            FieldType::Int => Value::Int(item.parse().map_err(|_| invalid("an integer"))?),
+            FieldType::Float => Value::Float(item.parse().map_err(|_| invalid("a number"))?),
+            FieldType::String => Value::String(item.to_string()),
+            FieldType::Bool => Value::Bool(match item {
+                "true" | "1" => true,
+                "false" | "0" => false,
+                _ => return Err(invalid("a boolean")),
+            }),
+            FieldType::Hex => Value::Hex(hex::decode(item).map_err(|_| invalid("hexadecimal"))?),
+            FieldType::U8 => int(item.parse::<u8>().ok().map(i64::from))?,
+            FieldType::I8 => int(item.parse::<i8>().ok().map(i64::from))?,
+            FieldType::U16 => int(item.parse::<u16>().ok().map(i64::from))?,
+            FieldType::I16 => int(item.parse::<i16>().ok().map(i64::from))?,
+            FieldType::U32 => int(item.parse::<u32>().ok().map(i64::from))?,
+            FieldType::I32 => int(item.parse::<i32>().ok().map(i64::from))?,
+            FieldType::F32 | FieldType::F64 => {
+                Value::Float(item.parse().map_err(|_| invalid("a number"))?)
+            }
+        };
+        Ok(self.scaled(value))
+    }
+
+    /// Reads a value from exactly `width()` bytes
+    fn decode(&self, raw: &[u8]) -> Value {
+        macro_rules! read {
+            ($type:ty) => {{
+                let bytes = raw.try_into().unwrap();
+                match self.endian {
+                    Endian::Little => <$type>::from_le_bytes(bytes),
+                    Endian::Big => <$type>::from_be_bytes(bytes),
+                }
+            }};
+        }
+        let value = match self.kind {
+            FieldType::U8 => Value::Int(read!(u8).into()),
+            FieldType::I8 => Value::Int(read!(i8).into()),
+            FieldType::U16 => Value::Int(read!(u16).into()),
+            FieldType::I16 => Value::Int(read!(i16).into()),
+            FieldType::U32 => Value::Int(read!(u32).into()),
+            FieldType::I32 => Value::Int(read!(i32).into()),
+            FieldType::F32 => Value::Float(read!(f32).into()),
+            FieldType::F64 => Value::Float(read!(f64)),
+            _ => unreachable!("checked by binary_width"),
+        };
+        match (value, self.coordinate) {
+            (Value::Int(number), Some(_)) => self.degrees(self.linear(number as f64)),
+            (Value::Float(number), Some(_)) => self.degrees(self.linear(number)),
+            (value, _) => self.scaled(value),
+        }
+    }
+
+    /// Applies the linear transform and precision to numeric values
+    fn scaled(&self, value: Value) -> Value {
+        let untransformed = self.scale.is_none() && self.offset.is_none();
+        let number = match value {
+            // Integers stay integers unless there's something to do with them
+            Value::Int(_) if untransformed && self.precision.is_none() => return value,
+            Value::Int(number) => number as f64,
+            Value::Float(number) => number,
+            value => return value,
+        };
+        self.rounded(self.linear(number))
+    }
+
+    fn linear(&self, number: f64) -> f64 {
+        number * self.scale.unwrap_or(1.0) + self.offset.unwrap_or(0.0)
+    }
+
+    fn rounded(&self, number: f64) -> Value {
+        match self.precision {
+            Some(precision) => Value::Fixed(number, precision),
+            None => Value::Float(number),
+        }
+    }
+
+    /// A binary coordinate in decimal degrees
+    fn degrees(&self, number: f64) -> Value {
+        match self.input {
+            CoordinateFormat::Decimal => self.rounded(number),
+            CoordinateFormat::Nmea => self.rounded(nmea_degrees(number)),
+        }
+    }
+}
+
+/// Parses `-4807.038`, `4807.038S` or `-48.1173` into signed decimal degrees
+fn parse_coordinate(item: &str, format: CoordinateFormat) -> Option<f64> {
+    let (number, sign) = match item.strip_suffix(['N', 'E']) {
+        Some(number) => (number, 1.0),
+        None => match item.strip_suffix(['S', 'W']) {
+            Some(number) => (number, -1.0),
+            None => (item, 1.0),
+        },
+    };
+    let number: f64 = number.parse().ok()?;
+    let degrees = match format {
+        CoordinateFormat::Decimal => number,
+        CoordinateFormat::Nmea => nmea_degrees(number),
+    };
+    Some(sign * degrees)
+}
+
+/// Converts ddmm.mmmm (or dddmm.mmmm) into decimal degrees
+fn nmea_degrees(value: f64) -> f64 {
+    let degrees = (value.abs() / 100.0).trunc();
+    let minutes = value.abs() - degrees * 100.0;
+    value.signum() * (degrees + minutes / 60.0)
+}
+
+#[derive(Debug)]
+pub enum SchemaError {
+    Read {
+        path: String,
+        error: std::io::Error,
+    },
+    Invalid {
+        path: String,
+        error: toml::de::Error,
+    },
+    NoFields(String),
+    DuplicateField {
+        path: String,
+        name: String,
+    },
+}
+
+impl Display for SchemaError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaError::Read { path, error } => write!(f, "can't read `{}`: {}", path, error),
+            SchemaError::Invalid { path, error } => {
+                write!(f, "`{}` isn't a valid schema: {}", path, error)
+            }
+            SchemaError::NoFields(path) => write!(f, "`{}` doesn't list any fields", path),
+            SchemaError::DuplicateField { path, name } => {
+                write!(f, "`{}` names more than one field {}", path, name)
+            }
+        }
+    }
+}
+
+impl Error for SchemaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SchemaError::Read { error, .. } => Some(error),
+            SchemaError::Invalid { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Reads and checks a schema file given on the command line
+pub fn load(path: &str) -> Result<Schema, SchemaError> {
+    let text = std::fs::read_to_string(path).map_err(|error| SchemaError::Read {
+        path: path.to_string(),
+        error,
+    })?;
+    let schema: Schema = toml::from_str(&text).map_err(|error| SchemaError::Invalid {
+        path: path.to_string(),
+        error,
+    })?;
+    if schema.fields.is_empty() {
+        return Err(SchemaError::NoFields(path.to_string()));
+    }
+    for (index, field) in schema.fields.iter().enumerate() {
+        if schema.fields[..index]
+            .iter()
+            .any(|other| other.name == field.name)
+        {
+            return Err(SchemaError::DuplicateField {
+                path: path.to_string(),
+                name: field.name.clone(),
+            });
+        }
+    }
+    Ok(schema)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(text: &str) -> Schema {
+        toml::from_str(text).unwrap()
+    }
+
+    fn strings(values: Vec<Value>) -> Vec<String> {
+        values.iter().map(Value::to_string).collect()
+    }
+
+    #[test]
+    fn coordinates_become_signed_decimal_degrees() {
+        let cases = [
+            ("4807.038N", CoordinateFormat::Nmea, 48.1173),
+            ("4807.038S", CoordinateFormat::Nmea, -48.1173),
+            ("-4807.038", CoordinateFormat::Nmea, -48.1173),
+            ("01131.000E", CoordinateFormat::Nmea, 11.5167),
+            ("01131.000W", CoordinateFormat::Nmea, -11.5167),
+            ("48.1173", CoordinateFormat::Decimal, 48.1173),
+            ("48.1173S", CoordinateFormat::Decimal, -48.1173),
+        ];
+        for (item, format, expected) in cases {
+            let degrees = parse_coordinate(item, format).unwrap();
+            assert!((degrees - expected).abs() < 1e-4, "{item}: {degrees}");
+        }
+        assert!(parse_coordinate("north", CoordinateFormat::Nmea).is_none());
+        assert!(parse_coordinate("", CoordinateFormat::Decimal).is_none());
+    }
+
+    #[test]
+    fn coordinate_fields_round_to_their_precision() {
+        let schema = schema(
+            r#"
+            [[field]]
+            name = "lat"
+            type = "string"
+            coordinate = "lat"
+            input = "nmea"
+            precision = 4
+            "#,
+        );
+        let values = schema.convert(&["4807.038N"]).unwrap();
+        assert_eq!(strings(values), ["48.1173"]);
+        let invalid = schema.convert(&["4807,038N"]).err().unwrap();
+        assert_eq!(invalid.kind, "a coordinate");
+    }
+
+    #[test]
+    fn gps_check_rejects_impossible_and_empty_fixes() {
+        let schema = schema(
+            r#"
+            gps_valid = true
+            [[field]]
+            name = "lat"
+            type = "float"
+            coordinate = "lat"
+            [[field]]
+            name = "lon"
+            type = "float"
+            coordinate = "lon"
+            "#,
+        );
+        let cases = [
+            (["48.1173", "11.5167"], Some(true)),
+            (["-90", "180"], Some(true)),
+            (["91.5", "11.5167"], Some(false)),
+            (["48.1173", "-180.5"], Some(false)),
+            (["0", "0"], Some(false)),
+            (["0", "11.5167"], Some(true)),
+            (["nan", "11.5167"], Some(false)),
+        ];
+        for (data, expected) in cases {
+            assert_eq!(schema.check_gps(&data), expected, "{data:?}");
+        }
+        let plain = Schema {
+            fields: vec![],
+            gps_valid: false,
+            measurement: None,
+        };
+        assert_eq!(plain.check_gps(&["48.1173"]), None);
+    }
+
+    #[test]
+    fn numbers_are_scaled_offset_and_rounded() {
+        let schema = schema(
+            r#"
+            [[field]]
+            name = "count"
+            type = "u16"
+            [[field]]
+            name = "temperature"
+            type = "int"
+            scale = 0.1
+            offset = -40
+            precision = 1
+            [[field]]
+            name = "ratio"
+            type = "float"
+            precision = 3
+            [[field]]
+            name = "level"
+            type = "u8"
+            scale = 0.5
+            "#,
+        );
+        let values = schema.convert(&["65535", "652", "0.66666", "3"]).unwrap();
+        assert_eq!(strings(values), ["65535", "25.2", "0.667", "1.5"]);
+        let invalid = schema.convert(&["65536", "0", "0", "0"]).err().unwrap();
+        assert_eq!((invalid.name.as_str(), invalid.kind), ("count", "in range"));
+        let invalid = schema.convert(&["1", "1.5", "0", "0"]).err().unwrap();
+        assert_eq!(invalid.kind, "an integer");
+    }
+
+    #[test]
+    fn binary_fields_follow_their_endianness() {
+        let schema = schema(
+            r#"
+            [[field]]
+            name = "little"
+            type = "i16"
+            scale = 0.01
+            precision = 2
+            [[field]]
+            name = "big"
+            type = "i16"
+            endian = "big"
+            scale = 0.01
+            precision = 2
+            [[field]]
+            name = "counter"
+            type = "u32"
+            endian = "big"
+            "#,
+        );
+        assert_eq!(schema.binary_width().ok(), Some(8));
+        let bytes = [0x18, 0xFC, 0xFC, 0x18, 0x00, 0x00, 0x01, 0x02];
+        let values = schema.decode(&bytes).unwrap();
+        assert_eq!(strings(values), ["-10.00", "-10.00", "258"]);
+        let short = schema.decode(&bytes[..7]).err().unwrap();
+        assert_eq!((short.expected, short.received), (8, 7));
+    }
+
+    #[test]
+    fn binary_coordinates_are_converted_after_scaling() {
+        let schema = schema(
+            r#"
+            [[field]]
+            name = "lat"
+            type = "i32"
+            endian = "big"
+            coordinate = "lat"
+            input = "nmea"
+            scale = 0.001
+            precision = 4
+            "#,
+        );
+        let values = schema.decode(&4_807_038i32.to_be_bytes()).unwrap();
+        assert_eq!(strings(values), ["48.1173"]);
+        let values = schema.decode(&(-4_807_038i32).to_be_bytes()).unwrap();
+        assert_eq!(strings(values), ["-48.1173"]);
+    }
+}
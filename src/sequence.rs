@@ -0,0 +1,149 @@
+use std::collections::{HashMap, VecDeque};
+use tracing::{debug, info};
+
+/// Missing numbers remembered per port, a packet that turns up later than the last this many
+/// losses is taken for a repeat
+const MISSING: usize = 1024;
+
+/// Follows the packet counter of each port to tell how many packets never arrived
+pub struct Sequences {
+    /// The counter wraps around to 0 on reaching this
+    modulus: u64,
+    ports: HashMap<String, Sequence>,
+}
+
+#[derive(Default)]
+struct Sequence {
+    last: Option<u64>,
+    received: u64,
+    lost: u64,
+    /// The numbers counted as lost that may still turn up, oldest first
+    missing: VecDeque<u64>,
+}
+
+impl Sequences {
+    pub fn new(modulus: u64) -> Self {
+        Sequences {
+            modulus,
+            ports: HashMap::new(),
+        }
+    }
+
+    /// Accounts for a packet, warning when packets before it went missing
+    pub fn observe(&mut self, port: &str, value: u64) {
+        let modulus = self.modulus;
+        let value = value % modulus;
+        let sequence = self.ports.entry(port.to_string()).or_default();
+        let Some(last) = sequence.last else {
+            sequence.last = Some(value);
+            sequence.received += 1;
+            return;
+        };
+        let distance = (value + modulus - last) % modulus;
+        if distance == 0 || distance > modulus / 2 {
+            // Further behind than ahead, so a late packet if it was counted as lost
+            match sequence
+                .missing
+                .iter()
+                .position(|&missing| missing == value)
+            {
+                Some(position) => {
+                    debug!("Sequence number {value} on {port} arrived out of order");
+                    sequence.missing.remove(position);
+                    sequence.received += 1;
+                    sequence.lost -= 1;
+                }
+                None => debug!("Repeated sequence number {value} on {port}"),
+            }
+            return;
+        }
+        if distance > 1 {
+            tracing::warn!(
+                "Lost {} packets on {port} (sequence {last} to {value})",
+                distance - 1
+            );
+            sequence.lost += distance - 1;
+            let remembered = (distance - 1).min(MISSING as u64);
+            let missing = (1..=remembered)
+                .rev()
+                .map(|back| (value + modulus - back) % modulus);
+            sequence.missing.extend(missing);
+            let excess = sequence.missing.len().saturating_sub(MISSING);
+            sequence.missing.drain(..excess);
+        }
+        sequence.last = Some(value);
+        sequence.received += 1;
+    }
+
+    /// Packets received and lost over all ports
+    pub fn totals(&self) -> (u64, u64) {
+        self.ports
+            .values()
+            .fold((0, 0), |(received, lost), sequence| {
+                (received + sequence.received, lost + sequence.lost)
+            })
+    }
+
+    pub fn log_summary(&self) {
+        let (received, lost) = self.totals();
+        let sent = received + lost;
+        let loss = if sent == 0 {
+            0.0
+        } else {
+            lost as f64 * 100.0 / sent as f64
+        };
+        info!("Sequence numbers: {sent} sent, {received} received, {lost} lost ({loss:.1}% loss)");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The totals after the numbers arrive in turn on one port
+    fn counts(modulus: u64, values: &[u64]) -> (u64, u64) {
+        let mut sequences = Sequences::new(modulus);
+        for &value in values {
+            sequences.observe("port", value);
+        }
+        sequences.totals()
+    }
+
+    #[test]
+    fn gap_counts_the_missing_packets() {
+        assert_eq!(counts(1 << 16, &[0, 1, 2]), (3, 0));
+        assert_eq!(counts(1 << 16, &[0, 1, 5]), (3, 3));
+    }
+
+    #[test]
+    fn wraparound_is_no_gap() {
+        assert_eq!(counts(1 << 16, &[65534, 65535, 0, 1]), (4, 0));
+        assert_eq!(counts(1 << 16, &[65535, 1]), (2, 1));
+        assert_eq!(
+            counts(1 << 32, &[u32::MAX as u64 - 1, u32::MAX as u64, 0]),
+            (3, 0)
+        );
+        assert_eq!(counts(1 << 32, &[u32::MAX as u64, 2]), (2, 2));
+    }
+
+    #[test]
+    fn late_arrival_is_no_longer_lost() {
+        assert_eq!(counts(1 << 16, &[0, 1, 4, 2]), (4, 1));
+        // Across the wraparound
+        assert_eq!(counts(1 << 16, &[65534, 1, 65535]), (3, 1));
+    }
+
+    #[test]
+    fn late_duplicates_leave_the_counts_alone() {
+        assert_eq!(counts(1 << 16, &[0, 1, 4, 2, 2, 1, 4]), (4, 1));
+        // Never counted as lost in the first place
+        assert_eq!(counts(1 << 16, &[0, 1, 2, 1, 0]), (3, 0));
+    }
+
+    #[test]
+    fn losses_too_long_ago_are_forgotten() {
+        let last = MISSING as u64 + 10;
+        assert_eq!(counts(1 << 16, &[0, last, 1]), (2, last - 1));
+        assert_eq!(counts(1 << 16, &[0, last, last - 1]), (3, last - 2));
+    }
+}
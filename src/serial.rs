@@ -0,0 +1,260 @@
+use clap::ValueEnum;
+use serialport::{DataBits, FlowControl, Parity, SerialPort, StopBits};
+use std::time::Duration;
+
+/// Settings for opening a serial port
+#[derive(clap::Args)]
+#[command(about = None, long_about = None)]
+pub struct SerialArgs {
+    #[arg(short, long, default_value_t = 115200, value_parser = parse_baud)]
+    /// Baud rate of the serial port
+    pub baud: u32,
+    #[arg(long, value_enum, default_value_t = DataBitsArg::Eight)]
+    /// Number of data bits per character
+    pub data_bits: DataBitsArg,
+    #[arg(long, value_enum, default_value_t = ParityArg::None)]
+    /// Parity checking mode
+    pub parity: ParityArg,
+    #[arg(long, value_enum, default_value_t = StopBitsArg::One)]
+    /// Number of stop bits
+    pub stop_bits: StopBitsArg,
+    #[arg(long, value_enum, default_value_t = FlowControlArg::None)]
+    /// Flow control mode
+    pub flow_control: FlowControlArg,
+    #[arg(long, value_name = "MS", default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..))]
+    /// Serial read timeout; Ctrl-C takes up to this long to stop the capture
+    pub timeout_ms: u64,
+    #[arg(long, value_enum, default_value_t = LineEnding::Crlf)]
+    /// Terminator of the lines the module sends; auto accepts any of them
+    pub line_ending: LineEnding,
+}
+
+impl SerialArgs {
+    /// Character framing in the usual 8N1 notation
+    pub fn frame_format(&self) -> String {
+        let parity = match self.parity {
+            ParityArg::None => 'N',
+            ParityArg::Odd => 'O',
+            ParityArg::Even => 'E',
+        };
+        format!(
+            "{}{parity}{}",
+            u8::from(DataBits::from(self.data_bits)),
+            u8::from(StopBits::from(self.stop_bits))
+        )
+    }
+
+    pub fn open(&self, port: &str) -> Result<Box<dyn SerialPort>, serialport::Error> {
+        self.builder(port).open()
+    }
+
+    /// Opens the port as the platform's own type, which [`stream`] makes a [`Stream`] of
+    pub fn open_native(&self, port: &str) -> Result<NativePort, serialport::Error> {
+        self.builder(port).open_native()
+    }
+
+    fn builder(&self, port: &str) -> serialport::SerialPortBuilder {
+        serialport::new(port, self.baud)
+            .data_bits(self.data_bits.into())
+            .parity(self.parity.into())
+            .stop_bits(self.stop_bits.into())
+            .flow_control(self.flow_control.into())
+            .timeout(Duration::from_millis(self.timeout_ms))
+    }
+}
+
+#[cfg(unix)]
+pub type NativePort = serialport::TTYPort;
+#[cfg(windows)]
+pub type NativePort = serialport::COMPort;
+
+/// A port that's read asynchronously
+#[cfg(unix)]
+pub type Stream = tokio_serial::SerialStream;
+#[cfg(windows)]
+pub type Stream = Blocking;
+
+/// Makes a stream for reading the port, which belongs to the tokio runtime the caller is in
+///
+/// The module is set up through a clone of the port first, which the commands are still sent
+/// through afterwards. Reading starts right away where a port can't be read asynchronously.
+pub fn stream(port: NativePort) -> Result<Stream, serialport::Error> {
+    #[cfg(unix)]
+    let stream = Stream::try_from(port)?;
+    #[cfg(windows)]
+    let stream = Blocking::new(Box::new(port));
+    Ok(stream)
+}
+
+/// Reads a port on a thread of its own, as tokio-serial can't read a COM port that's also
+/// written to through a handle of its own
+#[cfg(windows)]
+pub struct Blocking {
+    chunks: tokio::sync::mpsc::Receiver<std::io::Result<Vec<u8>>>,
+    /// What's left of the last chunk
+    rest: Vec<u8>,
+}
+
+#[cfg(windows)]
+impl Blocking {
+    fn new(mut serial: Box<dyn SerialPort>) -> Self {
+        let (sender, chunks) = tokio::sync::mpsc::channel(16);
+        std::thread::spawn(move || {
+            use std::io::Read;
+            let mut buffer = vec![0; 1024];
+            loop {
+                let chunk = serial.read(&mut buffer).map(|n| buffer[..n].to_vec());
+                if sender.blocking_send(chunk).is_err() {
+                    return;
+                }
+            }
+        });
+        Blocking {
+            chunks,
+            rest: Vec::new(),
+        }
+    }
+}
+
+#[cfg(windows)]
+impl tokio::io::AsyncRead for Blocking {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        if self.rest.is_empty() {
+            match std::task::ready!(self.chunks.poll_recv(cx)) {
+                Some(Ok(chunk)) => self.rest = chunk,
+                Some(Err(error)) => return std::task::Poll::Ready(Err(error)),
+                None => return std::task::Poll::Ready(Ok(())),
+            }
+        }
+        let n = self.rest.len().min(buf.remaining());
+        buf.put_slice(&self.rest[..n]);
+        self.rest.drain(..n);
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+const BAUD_RATES: [u32; 13] = [
+    300, 600, 1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400, 460800, 921600,
+];
+
+pub fn parse_baud(value: &str) -> Result<u32, String> {
+    let baud: u32 = value
+        .parse()
+        .map_err(|_| format!("`{value}` isn't a number"))?;
+    if BAUD_RATES.contains(&baud) {
+        Ok(baud)
+    } else {
+        Err(format!(
+            "{baud} isn't a standard baud rate (expected one of {})",
+            BAUD_RATES.map(|rate| rate.to_string()).join(", ")
+        ))
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum DataBitsArg {
+    #[value(name = "5")]
+    Five,
+    #[value(name = "6")]
+    Six,
+    #[value(name = "7")]
+    Seven,
+    #[value(name = "8")]
+    Eight,
+}
+
+impl From<DataBitsArg> for DataBits {
+    fn from(value: DataBitsArg) -> Self {
+        match value {
+            DataBitsArg::Five => DataBits::Five,
+            DataBitsArg::Six => DataBits::Six,
+            DataBitsArg::Seven => DataBits::Seven,
+            DataBitsArg::Eight => DataBits::Eight,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ParityArg {
+    None,
+    Odd,
+    Even,
+}
+
+impl From<ParityArg> for Parity {
+    fn from(value: ParityArg) -> Self {
+        match value {
+            ParityArg::None => Parity::None,
+            ParityArg::Odd => Parity::Odd,
+            ParityArg::Even => Parity::Even,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum StopBitsArg {
+    #[value(name = "1")]
+    One,
+    #[value(name = "2")]
+    Two,
+}
+
+impl From<StopBitsArg> for StopBits {
+    fn from(value: StopBitsArg) -> Self {
+        match value {
+            StopBitsArg::One => StopBits::One,
+            StopBitsArg::Two => StopBits::Two,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum FlowControlArg {
+    None,
+    Hardware,
+    Software,
+}
+
+impl From<FlowControlArg> for FlowControl {
+    fn from(value: FlowControlArg) -> Self {
+        match value {
+            FlowControlArg::None => FlowControl::None,
+            FlowControlArg::Hardware => FlowControl::Hardware,
+            FlowControlArg::Software => FlowControl::Software,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum LineEnding {
+    Crlf,
+    Lf,
+    Cr,
+    Auto,
+}
+
+impl LineEnding {
+    /// Position and length of the first terminator in `buf`
+    ///
+    /// In auto mode a `\r\n` is two terminators around an empty line, so callers skip those.
+    pub fn find(self, buf: &[u8]) -> Option<(usize, usize)> {
+        let ends: &[u8] = match self {
+            LineEnding::Crlf => {
+                return buf
+                    .windows(2)
+                    .position(|pair| pair == b"\r\n")
+                    .map(|pos| (pos, 2))
+            }
+            LineEnding::Lf => b"\n",
+            LineEnding::Cr => b"\r",
+            LineEnding::Auto => b"\r\n",
+        };
+        buf.iter()
+            .position(|byte| ends.contains(byte))
+            .map(|pos| (pos, 1))
+    }
+}
@@ -0,0 +1,87 @@
+use crate::serial::{LineEnding, SerialArgs};
+use charter::device::Device;
+use rustyline::error::ReadlineError;
+use rustyline::{DefaultEditor, ExternalPrinter};
+use serialport::SerialPort;
+use std::io::{ErrorKind, Read, Write};
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use tracing::{error, info};
+
+/// Forwards lines typed on stdin to the module and prints its replies until Ctrl-D
+pub fn run(port: &str, device: &dyn Device, serial: &SerialArgs) -> ! {
+    let mut writer = serial
+        .open(port)
+        .unwrap_or_else(|error| crate::open_failed(port, serial, error));
+    let reader = writer
+        .try_clone()
+        .unwrap_or_else(|error| panic!("Failed to clone {port}: {error}"));
+    let mut editor =
+        DefaultEditor::new().unwrap_or_else(|error| panic!("Failed to open the terminal: {error}"));
+    let printer = editor
+        .create_external_printer()
+        .unwrap_or_else(|error| panic!("Failed to open the terminal: {error}"));
+    info!("Connected to {port}, Ctrl-D to exit");
+
+    let running = AtomicBool::new(true);
+    thread::scope(|scope| {
+        scope.spawn(|| print_replies(reader, printer, serial.line_ending, &running));
+
+        loop {
+            match editor.readline("> ") {
+                Ok(line) => {
+                    let line = line.trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    let _ = editor.add_history_entry(line);
+                    if let Err(error) = writer.write_all(format!("{line}\r\n").as_bytes()) {
+                        error!("Failed to write to {port}: {error}");
+                        break;
+                    }
+                }
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(error) => panic!("Failed to read from the terminal: {error}"),
+            }
+        }
+
+        running.store(false, Ordering::SeqCst);
+        // Don't leave the module receiving after we're gone
+        if let Err(error) = device.end(&mut writer, None) {
+            error!("Failed to stop the radio: {error}");
+        }
+    });
+    exit(0);
+}
+
+/// Prints every \r\n terminated line the module sends above the prompt
+fn print_replies(
+    mut serial: Box<dyn SerialPort>,
+    mut printer: impl ExternalPrinter,
+    line_ending: LineEnding,
+    running: &AtomicBool,
+) {
+    let mut buf = [0; 1024];
+    let mut line = Vec::new();
+    while running.load(Ordering::SeqCst) {
+        match serial.read(&mut buf) {
+            Ok(count) => {
+                line.extend_from_slice(&buf[..count]);
+                while let Some((end, len)) = line_ending.find(&line) {
+                    let reply = String::from_utf8_lossy(&line[..end]).trim_end().to_string();
+                    line.drain(..end + len);
+                    if !reply.is_empty() {
+                        let _ = printer.print(reply);
+                    }
+                }
+            }
+            Err(error) if error.kind() == ErrorKind::TimedOut => continue,
+            Err(error) => {
+                let _ = printer.print(format!("Failed to read from the module: {error}"));
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,788 @@
+//! Writing the rows to CSV files, which are appended to, rotated and compressed as asked
+//!
+//! [`Csv`] is what the `charter` binary writes its CSV output with, the helpers around it are
+//! shared with its other file outputs.
+
+use crate::rotation::Rotation;
+use clap::ValueEnum;
+use csv::{StringRecord, Writer, WriterBuilder};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::borrow::Cow;
+use std::fmt::{self, Display, Formatter, Write as _};
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum QuoteStyle {
+    /// Only values that need it, like those holding the delimiter
+    Necessary,
+    Always,
+    /// Not even values that need it, which may leave the rows unreadable
+    Never,
+}
+
+/// How CSV rows are written, for spreadsheets that expect something other than plain commas
+#[derive(Clone, Copy)]
+pub struct Dialect {
+    pub delimiter: u8,
+    pub quote_style: QuoteStyle,
+    /// Whether new files start with a UTF-8 byte order mark
+    pub bom: bool,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Dialect {
+            delimiter: b',',
+            quote_style: QuoteStyle::Necessary,
+            bom: false,
+        }
+    }
+}
+
+/// Parses a single ASCII character, or `tab`
+pub fn parse_delimiter(value: &str) -> Result<u8, String> {
+    match value {
+        "tab" | "\\t" | "\t" => Ok(b'\t'),
+        _ if value.len() != 1 || !value.is_ascii() => {
+            Err(format!("`{value}` isn't a single ASCII character"))
+        }
+        "\"" | "\n" | "\r" => Err(format!("{value:?} can't separate the values")),
+        _ => Ok(value.as_bytes()[0]),
+    }
+}
+
+/// A CSV file that seems to be separated by another delimiter than the dialect's
+#[derive(Debug)]
+pub struct DelimiterMismatch {
+    pub found: u8,
+    pub expected: u8,
+}
+
+impl Display for DelimiterMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "separated by {:?} rather than {:?}",
+            self.found as char, self.expected as char
+        )
+    }
+}
+
+impl std::error::Error for DelimiterMismatch {}
+
+impl Dialect {
+    pub fn writer<W: Write>(&self, writer: W) -> Writer<W> {
+        let quote_style = match self.quote_style {
+            QuoteStyle::Necessary => csv::QuoteStyle::Necessary,
+            QuoteStyle::Always => csv::QuoteStyle::Always,
+            QuoteStyle::Never => csv::QuoteStyle::Never,
+        };
+        WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .quote_style(quote_style)
+            .from_writer(writer)
+    }
+
+    /// Refuses a file whose first row seems to be separated by another delimiter, going by it
+    /// coming out as a single value holding a common one
+    pub fn check(&self, first: &StringRecord) -> Result<(), DelimiterMismatch> {
+        let Some(value) = first.get(0).filter(|_| first.len() == 1) else {
+            return Ok(());
+        };
+        let other = [b',', b';', b'\t', b'|'].into_iter().find(|&delimiter| {
+            delimiter != self.delimiter && value.as_bytes().contains(&delimiter)
+        });
+        match other {
+            Some(found) => Err(DelimiterMismatch {
+                found,
+                expected: self.delimiter,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+/// How far the CSV and JSON files are pushed towards the disk, past the page cache
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Durability {
+    /// Leave it to the OS, which may lose the last half minute or so on a power cut
+    None,
+    /// Sync each file once it's finished, on rotation and on the way out
+    Flush,
+    /// Sync after every flush as well, which costs a lot of throughput on SD cards unless
+    /// --flush lets the records pile up
+    Fsync,
+}
+
+/// Where CSV rows have the time the packet was received
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum ReceivedAt {
+    /// Before the other columns
+    First,
+    /// After the other columns
+    Last,
+    /// Not at all
+    Off,
+}
+
+impl ReceivedAt {
+    /// Puts the time into the row, or its name into the header
+    pub fn insert<T>(self, row: &mut Vec<T>, time: T) {
+        match self {
+            ReceivedAt::First => row.insert(0, time),
+            ReceivedAt::Last => row.push(time),
+            ReceivedAt::Off => (),
+        }
+    }
+}
+
+/// A row as it goes to the output
+pub struct Record<'a> {
+    pub index: usize,
+    pub received: SystemTime,
+    /// The payload as decoded from the packet, before it was parsed
+    pub payload: &'a [u8],
+    /// Names of the values, which may run out before them when extra fields are appended
+    pub columns: &'a [String],
+    pub values: &'a [&'a str],
+}
+
+impl Record<'_> {
+    /// The values along with their names, `field_N` for those past the last column
+    pub fn named(&self) -> impl Iterator<Item = (Cow<'_, str>, &str)> {
+        self.values.iter().enumerate().map(|(index, value)| {
+            let name = match self.columns.get(index) {
+                Some(column) => Cow::Borrowed(column.as_str()),
+                None => Cow::Owned(format!("field_{index}")),
+            };
+            (name, *value)
+        })
+    }
+
+    /// The record as a JSON object, with empty values as null
+    pub fn to_json(&self) -> serde_json::Map<String, serde_json::Value> {
+        let mut object = serde_json::Map::new();
+        object.insert(String::from("index"), self.index.into());
+        object.insert(
+            String::from("received_at"),
+            crate::clock::timestamp(self.received).into(),
+        );
+        for (name, value) in self.named() {
+            let value = match value {
+                "" => serde_json::Value::Null,
+                value => value.into(),
+            };
+            object.insert(name.into_owned(), value);
+        }
+        object
+    }
+}
+
+/// The index and receive time of the record being written as CSV, kept to reuse their text
+#[derive(Default)]
+pub struct Leading {
+    index: String,
+    received: String,
+}
+
+impl Leading {
+    /// The values of the CSV row of the record, led by the index if there's one
+    pub fn row<'r>(
+        &'r mut self,
+        record: &'r Record,
+        index: Option<usize>,
+        received_at: ReceivedAt,
+    ) -> impl Iterator<Item = &'r str> {
+        self.index.clear();
+        if let Some(index) = index {
+            let _ = write!(self.index, "{index}");
+        }
+        self.received.clear();
+        if received_at != ReceivedAt::Off {
+            crate::clock::push_timestamp(&mut self.received, record.received);
+        }
+        let (first, last) = match received_at {
+            ReceivedAt::First => (Some(self.received.as_str()), None),
+            ReceivedAt::Last => (None, Some(self.received.as_str())),
+            ReceivedAt::Off => (None, None),
+        };
+        index
+            .map(|_| self.index.as_str())
+            .into_iter()
+            .chain(first)
+            .chain(record.values.iter().copied())
+            .chain(last)
+    }
+}
+
+/// What goes wrong with an output file
+#[derive(Debug)]
+pub enum Error {
+    Open {
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// Making the directories the file is to be created in failed
+    Create {
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// Reading back the file to append to failed
+    Read {
+        path: PathBuf,
+        error: csv::Error,
+    },
+    Delimiter {
+        path: PathBuf,
+        mismatch: DelimiterMismatch,
+    },
+    /// The file to append to has another header than the columns
+    Header {
+        path: PathBuf,
+        found: Vec<String>,
+        expected: Vec<String>,
+    },
+    /// The file to append to has rows of another width than the columns, going without a
+    /// header
+    Width {
+        path: PathBuf,
+        found: usize,
+        columns: Vec<String>,
+    },
+    /// Starting a new file failed
+    Write {
+        path: PathBuf,
+        error: csv::Error,
+    },
+    /// Writing a record failed
+    Record(csv::Error),
+    /// Flushing failed
+    Io(io::Error),
+    /// The file was moved or removed while it was written to and can't be created again
+    Moved(PathBuf),
+    /// Opening the file again after it was moved or removed failed
+    Reopen(Box<Error>),
+    NotOpen,
+}
+
+impl Error {
+    /// Whether the file can't be written to any more, rather than just the record being lost
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            Error::Moved(_) | Error::Reopen(_) => true,
+            Error::Io(error) => matches!(error.kind(), ErrorKind::NotFound | ErrorKind::BrokenPipe),
+            _ => false,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Open { path, error } => write!(f, "Failed to open {}: {error}", path.display()),
+            Error::Create { path, error } => {
+                write!(f, "Failed to create {}: {error}", path.display())
+            }
+            Error::Read { path, error } => write!(f, "Failed to read {}: {error}", path.display()),
+            Error::Delimiter { path, mismatch } => write!(f, "{} is {mismatch}", path.display()),
+            Error::Header {
+                path,
+                found,
+                expected,
+            } => write!(
+                f,
+                "{} starts with {found:?} rather than the header {expected:?}",
+                path.display()
+            ),
+            Error::Width {
+                path,
+                found,
+                columns,
+            } => write!(
+                f,
+                "{} has rows of {found} columns rather than the {} of this run ({})",
+                path.display(),
+                columns.len(),
+                columns.join(", ")
+            ),
+            Error::Write { path, error } => {
+                write!(f, "Failed to write to {}: {error}", path.display())
+            }
+            Error::Record(error) => write!(f, "{error}"),
+            Error::Io(error) => write!(f, "{error}"),
+            Error::Moved(path) => write!(f, "{} was moved or removed", path.display()),
+            Error::Reopen(error) => write!(f, "{error}"),
+            Error::NotOpen => write!(f, "The CSV file isn't open"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Open { error, .. } | Error::Create { error, .. } | Error::Io(error) => {
+                Some(error)
+            }
+            Error::Read { error, .. } | Error::Write { error, .. } | Error::Record(error) => {
+                Some(error)
+            }
+            Error::Delimiter { mismatch, .. } => Some(mismatch),
+            Error::Reopen(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// The file a text output appends to, through the compression if there is one, counting the
+/// bytes in it for `--rotate-size`
+///
+/// Compressed data is held until the next flush, which ends the gzip member or zstd frame, so
+/// everything up to it decompresses even if the run dies before the next one.
+pub struct Sink {
+    file: File,
+    bytes: u64,
+    compression: Option<Compression>,
+    encoder: Option<Encoder>,
+    sync: Durability,
+}
+
+impl Sink {
+    /// The size of the file, up to the last flush when it's compressed
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Syncs the file once it's no longer written to, unless it's left to the OS
+    pub fn close(&self) -> io::Result<()> {
+        match self.sync {
+            Durability::None => Ok(()),
+            Durability::Flush | Durability::Fsync => self.file.sync_data(),
+        }
+    }
+
+    /// Whether the file has to be opened again after a flush, because something like log
+    /// rotation moved or removed it from `path` and records would go nowhere otherwise
+    ///
+    /// It's only opened again if it may be created, otherwise that's an [`Error::Moved`].
+    pub fn reopen(&self, path: &Path, create: bool) -> Result<bool, Error> {
+        let moved = match (std::fs::metadata(path), self.file.metadata()) {
+            #[cfg(unix)]
+            (Ok(current), Ok(open)) => {
+                use std::os::unix::fs::MetadataExt;
+                (current.dev(), current.ino()) != (open.dev(), open.ino())
+            }
+            (current, _) => current.is_err(),
+        };
+        match moved {
+            false => Ok(false),
+            true if create => {
+                warn!(
+                    "{} was moved or removed, starting a new one",
+                    path.display()
+                );
+                Ok(true)
+            }
+            true => Err(Error::Moved(path.to_path_buf())),
+        }
+    }
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Zstd(zstd::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(compression: Compression) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), Default::default())),
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(Vec::new(), 0)?),
+        })
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(compression) = self.compression else {
+            let written = self.file.write(buf)?;
+            self.bytes += written as u64;
+            return Ok(written);
+        };
+        let encoder = match self.encoder {
+            Some(ref mut encoder) => encoder,
+            None => self.encoder.insert(Encoder::new(compression)?),
+        };
+        match encoder {
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(encoder) = self.encoder.take() {
+            let compressed = encoder.finish()?;
+            self.file.write_all(&compressed)?;
+            self.bytes += compressed.len() as u64;
+        }
+        self.file.flush()?;
+        match self.sync {
+            Durability::Fsync => self.file.sync_data(),
+            Durability::None | Durability::Flush => Ok(()),
+        }
+    }
+}
+
+/// Reads the file back as it was written
+pub fn open_read(path: &Path, compression: Option<Compression>) -> io::Result<Box<dyn Read>> {
+    let file = File::open(path)?;
+    Ok(match compression {
+        None => Box::new(file),
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(file)),
+        Some(Compression::Zstd) => Box::new(zstd::Decoder::new(file)?),
+    })
+}
+
+/// Makes the directories a file is to be created in
+pub fn create_parent(path: &Path) -> Result<(), Error> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => {
+            std::fs::create_dir_all(parent).map_err(|error| Error::Create {
+                path: parent.to_path_buf(),
+                error,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+fn open_failed(path: &Path) -> impl FnOnce(io::Error) -> Error + '_ {
+    |error| Error::Open {
+        path: path.to_path_buf(),
+        error,
+    }
+}
+
+/// Empties the file for `--overwrite`, creating it only if allowed
+pub fn truncate(path: &Path, create: bool) -> Result<(), Error> {
+    if create {
+        create_parent(path)?;
+    }
+    std::fs::OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(create)
+        .open(path)
+        .map(drop)
+        .map_err(open_failed(path))?;
+    info!("Overwriting {}", path.display());
+    Ok(())
+}
+
+/// Opens the file for appending, creating it only if allowed
+pub fn open_append(
+    path: &Path,
+    create: bool,
+    compression: Option<Compression>,
+    sync: Durability,
+) -> Result<Sink, Error> {
+    if create {
+        create_parent(path)?;
+    }
+    let file = std::fs::OpenOptions::new()
+        .append(true)
+        .create(create)
+        .open(path)
+        .map_err(open_failed(path))?;
+    let bytes = file.metadata().map_err(open_failed(path))?.len();
+    Ok(Sink {
+        file,
+        bytes,
+        compression,
+        encoder: None,
+        sync,
+    })
+}
+
+/// The first row of the file and how many complete rows there are
+fn scan(
+    path: &Path,
+    compression: Option<Compression>,
+    delimiter: u8,
+) -> Result<(StringRecord, usize), csv::Error> {
+    // Which also skips a byte order mark
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(open_read(path, compression)?);
+    let mut records = reader.records();
+    let Some(first) = records.next().transpose()? else {
+        return Ok(Default::default());
+    };
+    Ok((first, 1 + records.map_while(Result::ok).count()))
+}
+
+/// How a [`Csv`] file is opened and what its rows hold
+pub struct CsvOptions {
+    pub dialect: Dialect,
+    /// Whether the file, and the directories it's in, may be created
+    pub create: bool,
+    /// Whether the first file is emptied rather than appended to
+    pub overwrite: bool,
+    pub compression: Option<Compression>,
+    pub durability: Durability,
+    pub rotation: Option<Rotation>,
+    pub received_at: ReceivedAt,
+    /// Whether new files start with an index column
+    pub index: bool,
+    /// Whether the file starts with the columns as a header row
+    pub header: bool,
+    /// Whether records may have more values than there are columns
+    pub width_varies: bool,
+}
+
+/// Keeps the file open for the whole run, flushed when the caller says so
+pub struct Csv {
+    dialect: Dialect,
+    path: PathBuf,
+    create: bool,
+    /// Whether the file is still to be emptied, which only the first one is
+    overwrite: bool,
+    /// Whether the first file was opened, after which only rotation says where the records go
+    started: bool,
+    compression: Option<Compression>,
+    sync: Durability,
+    rotation: Option<Rotation>,
+    received_at: ReceivedAt,
+    /// Whether new files start with an index column
+    index: bool,
+    /// Whether the current file has one
+    indexed: bool,
+    /// Rows of the first file, if it was appended to
+    appended: Option<usize>,
+    header: bool,
+    width_varies: bool,
+    columns: Option<Vec<String>>,
+    leading: Leading,
+    writer: Option<Writer<Sink>>,
+}
+
+impl Csv {
+    /// Writes to the file at `path`, the first one when rotating, with `columns` as the header
+    /// if they're known
+    ///
+    /// Nothing is opened until [`Csv::prepare`].
+    pub fn new(path: PathBuf, columns: Option<Vec<String>>, options: CsvOptions) -> Self {
+        Csv {
+            dialect: options.dialect,
+            path,
+            create: options.create,
+            overwrite: options.overwrite,
+            started: false,
+            compression: options.compression,
+            sync: options.durability,
+            rotation: options.rotation,
+            received_at: options.received_at,
+            index: options.index,
+            indexed: false,
+            appended: None,
+            header: options.header,
+            width_varies: options.width_varies,
+            columns: columns.map(|mut columns| {
+                options
+                    .received_at
+                    .insert(&mut columns, String::from("received_at"));
+                columns
+            }),
+            leading: Leading::default(),
+            writer: None,
+        }
+    }
+
+    /// The file being written to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Starts a new or empty file with the header, or checks an existing file was written with
+    /// the same columns, or at least as many of them, so incompatible runs don't end up in one
+    /// file
+    pub fn prepare(&mut self) -> Result<(), Error> {
+        let path = &self.path;
+        if std::mem::take(&mut self.overwrite) {
+            truncate(path, self.create)?;
+        }
+        let mut file = open_append(path, self.create, self.compression, self.sync)?;
+        let started = std::mem::replace(&mut self.started, true);
+        if file.bytes > 0 {
+            let (first, rows) =
+                scan(path, self.compression, self.dialect.delimiter).map_err(|error| {
+                    Error::Read {
+                        path: path.clone(),
+                        error,
+                    }
+                })?;
+            self.dialect
+                .check(&first)
+                .map_err(|mismatch| Error::Delimiter {
+                    path: path.clone(),
+                    mismatch,
+                })?;
+            self.indexed = self.check_columns(&first)?;
+            if !started {
+                // The check found the header if there's meant to be one
+                let header = self.header && self.columns.is_some();
+                let rows = rows - header as usize;
+                info!("Appending to {}, which has {rows} rows", path.display());
+                self.appended = Some(rows);
+            }
+            self.writer = Some(self.dialect.writer(file));
+            return Ok(());
+        }
+        if !started {
+            info!("Writing to {}", path.display());
+        }
+        let failed = |error: csv::Error| Error::Write {
+            path: path.clone(),
+            error,
+        };
+        if self.dialect.bom {
+            file.write_all("\u{feff}".as_bytes())
+                .map_err(|error| failed(error.into()))?;
+        }
+        let mut writer = self.dialect.writer(file);
+        self.indexed = self.index;
+        if let (true, Some(columns)) = (self.header, &self.columns) {
+            let mut header: Vec<&str> = columns.iter().map(String::as_str).collect();
+            if self.indexed {
+                header.insert(0, "index");
+            }
+            writer
+                .write_record(&header)
+                .and_then(|_| writer.flush().map_err(Into::into))
+                .map_err(failed)?;
+        }
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    /// Writes the record, with the index it's given unless the rotation restarts it
+    pub fn write(&mut self, record: &Record) -> Result<(), Error> {
+        self.rotate()?;
+        let index = match self.rotation {
+            Some(ref mut rotation) => rotation.index(record.index),
+            None => record.index,
+        };
+        let index = self.indexed.then_some(index);
+        let writer = self.writer.as_mut().ok_or(Error::NotOpen)?;
+        writer
+            .write_record(self.leading.row(record, index, self.received_at))
+            .map_err(Error::Record)
+    }
+
+    /// Writes out the records buffered since the last flush, then opens the file again if it
+    /// was moved or removed
+    pub fn flush(&mut self) -> Result<(), Error> {
+        let Some(ref mut writer) = self.writer else {
+            return Ok(());
+        };
+        writer.flush().map_err(Error::Io)?;
+        match writer.get_ref().reopen(&self.path, self.create) {
+            Ok(false) => Ok(()),
+            // A new file gets the header again
+            Ok(true) => self.prepare().map_err(|error| {
+                self.writer = None;
+                Error::Reopen(Box::new(error))
+            }),
+            Err(error) => {
+                self.writer = None;
+                Err(error)
+            }
+        }
+    }
+
+    /// Records the file already had when `prepare` found it to append to
+    pub fn rows(&self) -> Option<usize> {
+        self.appended
+    }
+
+    /// Writes out whatever is still buffered and closes the file
+    pub fn finish(&mut self) {
+        if let Some(mut writer) = self.writer.take() {
+            let closed = writer.flush().and_then(|_| writer.get_ref().close());
+            if let Err(error) = closed {
+                tracing::error!("Failed to finish {}: {error}", self.path.display());
+            }
+        }
+    }
+
+    /// Finishes the file and starts the next one with its header, if the rotation says so
+    fn rotate(&mut self) -> Result<(), Error> {
+        let bytes = self
+            .writer
+            .as_ref()
+            .map_or(0, |writer| writer.get_ref().bytes);
+        let Some(path) = self
+            .rotation
+            .as_mut()
+            .and_then(|rotation| rotation.next_path(&self.path, bytes))
+        else {
+            return Ok(());
+        };
+        self.finish();
+        info!("Continuing in {}", path.display());
+        self.path = path;
+        self.prepare()
+    }
+
+    /// Checks an existing file has the columns, with or without the index in front, and
+    /// returns whether it's there
+    fn check_columns(&self, first: &StringRecord) -> Result<bool, Error> {
+        let Some(ref columns) = self.columns else {
+            return Ok(false);
+        };
+        let indexed: Vec<&str> = std::iter::once("index")
+            .chain(columns.iter().map(String::as_str))
+            .collect();
+        let path = self.path.clone();
+        match self.header {
+            true if first.iter().eq(indexed.iter().copied()) => Ok(true),
+            true if first.iter().eq(columns.iter()) => Ok(false),
+            true => Err(Error::Header {
+                path,
+                found: first.iter().map(String::from).collect(),
+                expected: match self.index {
+                    true => indexed.into_iter().map(String::from).collect(),
+                    false => columns.clone(),
+                },
+            }),
+            // There's no telling where the extra fields end
+            false if self.width_varies => Ok(self.index),
+            false if first.len() == indexed.len() => Ok(true),
+            false if first.len() == columns.len() => Ok(false),
+            false => Err(Error::Width {
+                path,
+                found: first.len(),
+                columns: columns.clone(),
+            }),
+        }
+    }
+}
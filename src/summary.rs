@@ -0,0 +1,258 @@
+use crate::{output, webhook, Args, Shared};
+use serde_json::{json, Map, Value};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError};
+use std::time::{Duration, SystemTime};
+use tracing::info;
+
+/// Time between consecutive packets, from any port
+#[derive(Default)]
+pub struct Intervals {
+    last: Option<SystemTime>,
+    count: u32,
+    total: Duration,
+    min: Option<Duration>,
+    max: Duration,
+}
+
+impl Intervals {
+    pub fn observe(&mut self, received: SystemTime) {
+        if let Some(last) = self.last.replace(received) {
+            let interval = received.duration_since(last).unwrap_or_default();
+            self.count += 1;
+            self.total += interval;
+            self.min = Some(self.min.map_or(interval, |min| min.min(interval)));
+            self.max = self.max.max(interval);
+        }
+    }
+
+    /// The average, shortest and longest interval, once there are two packets
+    fn stats(&self) -> Option<(Duration, Duration, Duration)> {
+        Some((self.total / self.count.max(1), self.min?, self.max))
+    }
+}
+
+/// What happened during the run, logged on the way out
+pub struct Summary {
+    duration: Duration,
+    packets: u64,
+    /// Records passed on to the outputs, after reassembly and --dedup
+    records: u64,
+    /// Of them, those no output failed to write
+    written: u64,
+    irregular: u64,
+    decode_failures: u64,
+    truncated: u64,
+    resyncs: u64,
+    dropped: u64,
+    radio_errors: u64,
+    busy: u64,
+    bytes_read: u64,
+    /// Failures of --checksum, by its name
+    checksum_failures: Option<(&'static str, u64)>,
+    duplicates: Option<u64>,
+    /// Failed writes and flushes, with outputs
+    writes: Option<(u64, u64)>,
+    /// Records posted and failed, with --post-url
+    posts: Option<(u64, u64)>,
+    intervals: Option<(Duration, Duration, Duration)>,
+    /// The longest time without a parsed packet, with --idle-warn
+    longest_gap: Option<Duration>,
+}
+
+impl Summary {
+    pub fn new(args: &Args, shared: &Shared, duration: Duration) -> Self {
+        let load = |counter: &std::sync::atomic::AtomicU64| counter.load(Ordering::Relaxed);
+        Summary {
+            duration,
+            packets: load(&shared.packets),
+            records: load(&shared.records),
+            written: load(&shared.written),
+            irregular: load(&shared.irregular),
+            decode_failures: load(&shared.decode_failures),
+            truncated: load(&shared.truncated),
+            resyncs: load(&shared.resyncs),
+            dropped: load(&shared.dropped),
+            radio_errors: load(&shared.radio_errors),
+            busy: load(&shared.busy),
+            bytes_read: load(&shared.bytes_read),
+            checksum_failures: args
+                .checksum
+                .map(|checksum| (checksum.name(), load(&shared.checksum_failures))),
+            duplicates: args.dedup.then(|| load(&shared.duplicates)),
+            writes: (!args.output.is_empty())
+                .then(|| (load(&shared.write_errors), output::flushes())),
+            posts: args.webhook.url.is_some().then(webhook::counts),
+            intervals: shared
+                .intervals
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .stats(),
+            longest_gap: shared.idle.as_ref().map(|idle| idle.longest()),
+        }
+    }
+
+    pub fn log(&self) {
+        info!(
+            "Ran for {}: {} packets received, {} parsed, {} rows written",
+            seconds(self.duration),
+            self.packets,
+            self.records,
+            self.written
+        );
+        let mut skipped = format!(
+            "{} irregular, {} failed to decode, {} truncated, {} resynced, {} dropped",
+            self.irregular, self.decode_failures, self.truncated, self.resyncs, self.dropped
+        );
+        if let Some((name, failures)) = self.checksum_failures {
+            skipped.push_str(&format!(", {failures} failed {name}"));
+        }
+        if let Some(duplicates) = self.duplicates {
+            skipped.push_str(&format!(", {duplicates} duplicates"));
+        }
+        info!("Payloads: {skipped}");
+        info!(
+            "Radio: {} radio_err, {} busy, {} bytes read",
+            self.radio_errors, self.busy, self.bytes_read
+        );
+        if let Some((failed, flushes)) = self.writes {
+            info!("Outputs: {failed} failed writes, {flushes} flushes");
+        }
+        if let Some((delivered, failed)) = self.posts {
+            info!("Webhook: {delivered} posted, {failed} failed");
+        }
+        if let Some((average, min, max)) = self.intervals {
+            info!(
+                "Packet interval: {} average, {} min, {} max",
+                seconds(average),
+                seconds(min),
+                seconds(max)
+            );
+        }
+        if let Some(gap) = self.longest_gap {
+            info!("Longest gap without telemetry: {}", seconds(gap));
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let mut object = Map::new();
+        object.insert(
+            String::from("duration_s"),
+            json!(self.duration.as_secs_f64()),
+        );
+        object.insert(String::from("packets"), json!(self.packets));
+        object.insert(String::from("parsed"), json!(self.records));
+        object.insert(String::from("rows_written"), json!(self.written));
+        object.insert(String::from("irregular"), json!(self.irregular));
+        object.insert(String::from("decode_failures"), json!(self.decode_failures));
+        object.insert(String::from("truncated"), json!(self.truncated));
+        object.insert(String::from("resynced"), json!(self.resyncs));
+        object.insert(String::from("dropped"), json!(self.dropped));
+        object.insert(String::from("radio_err"), json!(self.radio_errors));
+        object.insert(String::from("busy"), json!(self.busy));
+        object.insert(String::from("bytes_read"), json!(self.bytes_read));
+        if let Some((_, failures)) = self.checksum_failures {
+            object.insert(String::from("checksum_failures"), json!(failures));
+        }
+        if let Some(duplicates) = self.duplicates {
+            object.insert(String::from("duplicates"), json!(duplicates));
+        }
+        if let Some((failed, flushes)) = self.writes {
+            object.insert(String::from("failed_writes"), json!(failed));
+            object.insert(String::from("flushes"), json!(flushes));
+        }
+        if let Some((delivered, failed)) = self.posts {
+            object.insert(String::from("posted"), json!(delivered));
+            object.insert(String::from("post_failures"), json!(failed));
+        }
+        if let Some((average, min, max)) = self.intervals {
+            object.insert(
+                String::from("interval_s"),
+                json!({
+                    "average": average.as_secs_f64(),
+                    "min": min.as_secs_f64(),
+                    "max": max.as_secs_f64(),
+                }),
+            );
+        }
+        if let Some(gap) = self.longest_gap {
+            object.insert(String::from("longest_gap_s"), json!(gap.as_secs_f64()));
+        }
+        Value::Object(object)
+    }
+
+    /// Writes the summary as JSON next to `output`, replacing the one from an earlier run
+    pub fn write_next_to(&self, output: &Path) -> std::io::Result<PathBuf> {
+        let mut path = output.as_os_str().to_owned();
+        path.push(".summary.json");
+        let path = PathBuf::from(path);
+        let mut text = serde_json::to_string_pretty(&self.to_json())?;
+        text.push('\n');
+        std::fs::write(&path, text)?;
+        Ok(path)
+    }
+}
+
+/// The counters a heartbeat line compares with those of the previous one
+#[derive(Clone, Copy)]
+struct Window {
+    packets: u64,
+    parse_errors: u64,
+    radio_errors: u64,
+    /// Receive time of the last packet in ms since the epoch, 0 before the first
+    last_packet: u64,
+}
+
+impl Window {
+    fn sample(shared: &Shared) -> Self {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        Window {
+            packets: load(&shared.packets),
+            parse_errors: load(&shared.parse_errors),
+            radio_errors: load(&shared.radio_errors),
+            last_packet: load(&shared.last_packet),
+        }
+    }
+}
+
+/// Logs what came in over each period from a thread of its own, staying quiet while nothing
+/// does as --idle-warn is there for that
+pub fn heartbeat(shared: Arc<Shared>, every: Duration) {
+    std::thread::spawn(move || {
+        let mut start = Window::sample(&shared);
+        loop {
+            std::thread::sleep(every);
+            if !shared.running.load(Ordering::SeqCst) {
+                return;
+            }
+            let end = Window::sample(&shared);
+            let packets = end.packets - start.packets;
+            if packets == 0 {
+                start = end;
+                continue;
+            }
+            let mut line = format!(
+                "last {}s: {packets} packets, {} parse errors, {} radio_err",
+                every.as_secs(),
+                end.parse_errors - start.parse_errors,
+                end.radio_errors - start.radio_errors
+            );
+            // Counted from the last packet before the period, which the first one lacks
+            if start.last_packet > 0 {
+                let average = end.last_packet.saturating_sub(start.last_packet) / packets;
+                line.push_str(&format!(", avg interval {average}ms"));
+            }
+            let rssi = shared.last_rssi.load(Ordering::Relaxed);
+            if rssi != i64::MIN {
+                line.push_str(&format!(", last RSSI {rssi}"));
+            }
+            info!("{line}");
+            start = end;
+        }
+    });
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.3} s", duration.as_secs_f64())
+}
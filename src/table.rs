@@ -0,0 +1,107 @@
+use clap::ValueEnum;
+use std::io::Write;
+
+/// Longest a cell gets before it's cut short
+const MAX_CELL: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Display {
+    /// A log line per record
+    Log,
+    /// Aligned columns under a header, on a terminal
+    Table,
+}
+
+/// Prints the records as aligned columns under a header repeated every `every` rows, each line
+/// cut to the terminal's width
+pub struct Table {
+    every: usize,
+    /// Column names the header was last printed with
+    names: Vec<String>,
+    widths: Vec<usize>,
+    /// Rows printed since the header
+    rows: usize,
+}
+
+impl Table {
+    pub fn new(every: usize) -> Self {
+        Table {
+            every,
+            names: Vec::new(),
+            widths: Vec::new(),
+            rows: 0,
+        }
+    }
+
+    pub fn print(&mut self, names: &[String], values: &[&str]) {
+        let cells: Vec<String> = values.iter().map(|value| cut(value)).collect();
+        // Values past the named columns get numbered ones
+        let names: Vec<String> = (0..cells.len().max(names.len()))
+            .map(|index| match names.get(index) {
+                Some(name) => name.clone(),
+                None => format!("field_{index}"),
+            })
+            .collect();
+        let mut changed = names != self.names;
+        self.widths.resize(names.len(), 0);
+        for (index, width) in self.widths.iter_mut().enumerate() {
+            let name = names.get(index).map_or(0, |name| cut(name).chars().count());
+            let cell = cells.get(index).map_or(0, |cell| cell.chars().count());
+            let wanted = name.max(cell);
+            if wanted > *width {
+                *width = wanted;
+                changed = true;
+            }
+        }
+
+        let terminal = terminal_size::terminal_size().map(|(width, _)| width.0 as usize);
+        let mut out = std::io::stdout().lock();
+        if changed || self.rows >= self.every {
+            self.names = names;
+            let header: Vec<String> = self.names.iter().map(|name| cut(name)).collect();
+            let line = self.line(&header, |_| false);
+            let _ = writeln!(out, "{}", fit(&line, terminal));
+            self.rows = 0;
+        }
+        let line = self.line(&cells, |cell| cell.parse::<f64>().is_ok());
+        let _ = writeln!(out, "{}", fit(&line, terminal));
+        self.rows += 1;
+    }
+
+    /// Pads the cells to the column widths, numbers to the right
+    fn line(&self, cells: &[String], right: impl Fn(&str) -> bool) -> String {
+        let padded: Vec<String> = self
+            .widths
+            .iter()
+            .zip(
+                cells
+                    .iter()
+                    .map(String::as_str)
+                    .chain(std::iter::repeat("")),
+            )
+            .map(|(&width, cell)| match right(cell) {
+                true => format!("{cell:>width$}"),
+                false => format!("{cell:<width$}"),
+            })
+            .collect();
+        padded.join("  ").trim_end().to_string()
+    }
+}
+
+/// Cuts the text short with an ellipsis if it's longer than a cell
+fn cut(text: &str) -> String {
+    match text.chars().count() > MAX_CELL {
+        true => text.chars().take(MAX_CELL - 1).chain(['…']).collect(),
+        false => text.to_string(),
+    }
+}
+
+/// Cuts the line to the terminal's width, if it's known
+fn fit(line: &str, terminal: Option<usize>) -> String {
+    match terminal {
+        Some(width) if width > 0 && line.chars().count() > width => {
+            line.chars().take(width - 1).chain(['…']).collect()
+        }
+        _ => line.to_string(),
+    }
+}
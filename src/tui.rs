@@ -0,0 +1,309 @@
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table};
+use ratatui::{Frame, Terminal};
+use std::collections::VecDeque;
+use std::io::{Stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Lines kept for the warnings pane
+const WARNINGS: usize = 8;
+
+/// How far back the packet rate looks
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// How often the screen is redrawn, so the time since the last packet keeps counting, which is
+/// also how long a key or a resize waits to be seen
+const REDRAW: Duration = Duration::from_millis(250);
+
+/// Whether the dashboard has the terminal, and log lines go to its pane rather than stderr
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// The terminal while the dashboard has it, held while drawing so the screen isn't drawn on
+/// once it's been given back
+static SCREEN: Mutex<Option<Terminal<CrosstermBackend<Stdout>>>> = Mutex::new(None);
+
+fn screen() -> MutexGuard<'static, Option<Terminal<CrosstermBackend<Stdout>>>> {
+    SCREEN.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The latest warnings and errors, for the pane
+static PANE: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+fn pane() -> MutexGuard<'static, VecDeque<String>> {
+    PANE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Where the log lines go: stderr, or the warnings pane while the dashboard is up
+pub struct Logs;
+
+pub enum LogWriter {
+    Stderr(std::io::Stderr),
+    /// A log line for the pane, added to it once it's complete
+    Pane(Vec<u8>),
+    /// Below a warning, which would only clutter the pane
+    Dropped,
+}
+
+impl<'a> MakeWriter<'a> for Logs {
+    type Writer = LogWriter;
+
+    fn make_writer(&'a self) -> LogWriter {
+        match ACTIVE.load(Ordering::Relaxed) {
+            true => LogWriter::Pane(Vec::new()),
+            false => LogWriter::Stderr(std::io::stderr()),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> LogWriter {
+        match ACTIVE.load(Ordering::Relaxed) {
+            true if *meta.level() > Level::WARN => LogWriter::Dropped,
+            _ => self.make_writer(),
+        }
+    }
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            LogWriter::Stderr(stderr) => stderr.write(buf),
+            LogWriter::Pane(line) => {
+                line.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            LogWriter::Dropped => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            LogWriter::Stderr(stderr) => stderr.flush(),
+            LogWriter::Pane(_) | LogWriter::Dropped => Ok(()),
+        }
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        let LogWriter::Pane(line) = self else {
+            return;
+        };
+        let text = strip_escapes(&String::from_utf8_lossy(line));
+        let mut pane = pane();
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            if pane.len() == WARNINGS {
+                pane.pop_front();
+            }
+            pane.push_back(line.to_string());
+        }
+    }
+}
+
+/// Removes the colours the log lines come with
+fn strip_escapes(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                // Up to and including the letter ending the sequence
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+            c => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+#[derive(Default)]
+struct State {
+    names: Vec<String>,
+    values: Vec<String>,
+    packets: u64,
+    /// When the packets within the rate window came in
+    recent: VecDeque<Instant>,
+    last: Option<Instant>,
+    snr: Option<i8>,
+    rssi: Option<i16>,
+}
+
+/// Shows the latest value of each field, how fast packets come in and the recent warnings on
+/// the terminal's alternate screen, redrawn as records arrive
+pub struct Dashboard {
+    state: Arc<Mutex<State>>,
+    started: Instant,
+}
+
+impl Dashboard {
+    /// Takes over the terminal, calling `quit` once q or Ctrl-C is pressed
+    pub fn start(quit: impl Fn() + Send + 'static) -> Self {
+        let state = Arc::new(Mutex::new(State::default()));
+        let started = Instant::now();
+        let terminal = terminal::enable_raw_mode()
+            .and_then(|_| crossterm::execute!(std::io::stdout(), EnterAlternateScreen))
+            .and_then(|_| Terminal::new(CrosstermBackend::new(std::io::stdout())));
+        match terminal {
+            Ok(mut terminal) => {
+                let _ = terminal.hide_cursor();
+                *screen() = Some(terminal);
+                ACTIVE.store(true, Ordering::SeqCst);
+            }
+            Err(error) => {
+                let _ = terminal::disable_raw_mode();
+                tracing::error!("Failed to take over the terminal for the dashboard: {error}");
+            }
+        }
+        let redraws = state.clone();
+        std::thread::spawn(move || {
+            let mut quitting = false;
+            while ACTIVE.load(Ordering::SeqCst) {
+                // A resize only needs the redraw, which fits the layout to the new size
+                match event::poll(REDRAW).and_then(|ready| ready.then(event::read).transpose()) {
+                    Ok(Some(Event::Key(key))) if !quitting && quits(&key) => {
+                        quitting = true;
+                        quit();
+                    }
+                    Ok(_) => (),
+                    Err(_) => std::thread::sleep(REDRAW),
+                }
+                draw(
+                    &redraws.lock().unwrap_or_else(PoisonError::into_inner),
+                    started,
+                );
+            }
+        });
+        Dashboard { state, started }
+    }
+
+    /// Shows the fields of the latest record, named by `columns` and then the `derived` ones
+    /// the schema adds after them
+    pub fn update(
+        &self,
+        columns: &[String],
+        derived: &[String],
+        values: &[String],
+        snr: Option<i8>,
+        rssi: Option<i16>,
+    ) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        state.names.clear();
+        state.names.extend(columns.iter().chain(derived).cloned());
+        state.values.clear();
+        state.values.extend_from_slice(values);
+        state.packets += 1;
+        state.recent.push_back(now);
+        while state
+            .recent
+            .front()
+            .is_some_and(|time| now.duration_since(*time) > RATE_WINDOW)
+        {
+            state.recent.pop_front();
+        }
+        state.last = Some(now);
+        state.snr = snr.or(state.snr);
+        state.rssi = rssi.or(state.rssi);
+        draw(&state, self.started);
+    }
+}
+
+/// Whether the key quits, Ctrl-C included as raw mode keeps it from raising SIGINT
+fn quits(key: &KeyEvent) -> bool {
+    match key.code {
+        _ if key.kind != KeyEventKind::Press => false,
+        KeyCode::Char('q' | 'Q') => true,
+        KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+        _ => false,
+    }
+}
+
+/// Gives the terminal back, on the way out or from the panic hook
+pub fn restore() {
+    let mut screen = screen();
+    if !ACTIVE.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    if let Some(mut terminal) = screen.take() {
+        let _ = terminal.show_cursor();
+    }
+    let _ = terminal::disable_raw_mode();
+    let _ = crossterm::execute!(std::io::stdout(), LeaveAlternateScreen);
+    // What was only in the pane would be lost with the alternate screen
+    for line in pane().drain(..) {
+        eprintln!("{line}");
+    }
+}
+
+fn draw(state: &State, started: Instant) {
+    let mut screen = screen();
+    let Some(terminal) = screen.as_mut().filter(|_| ACTIVE.load(Ordering::SeqCst)) else {
+        return;
+    };
+    let pane = pane();
+    let _ = terminal.draw(|frame| render(frame, state, started, &pane));
+}
+
+/// Lays the screen out as the status line, the fields and the warnings pane at the bottom
+fn render(frame: &mut Frame, state: &State, started: Instant, pane: &VecDeque<String>) {
+    let now = Instant::now();
+    // Less than a second in, the rate would only be noise
+    let window = now
+        .duration_since(started)
+        .clamp(Duration::from_secs(1), RATE_WINDOW);
+    let rate = state.recent.len() as f64 / window.as_secs_f64();
+    let mut status = format!("charter  {} packets  {rate:.1}/s  last ", state.packets);
+    match state.last {
+        Some(last) => status.push_str(&format!("{:.1} s ago", (now - last).as_secs_f64())),
+        None => status.push_str("never"),
+    }
+    if let Some(snr) = state.snr {
+        status.push_str(&format!("  SNR {snr} dB"));
+    }
+    if let Some(rssi) = state.rssi {
+        status.push_str(&format!("  RSSI {rssi} dBm"));
+    }
+
+    let [top, fields, warnings] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(1),
+        Constraint::Length(WARNINGS as u16 + 1),
+    ])
+    .areas(frame.area());
+    frame.render_widget(Paragraph::new(status), top);
+
+    let name_width = state
+        .names
+        .iter()
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let rows = state.values.iter().enumerate().map(|(index, value)| {
+        let name = match state.names.get(index) {
+            Some(name) => name.clone(),
+            None => format!("field_{index}"),
+        };
+        Row::new([name, value.clone()])
+    });
+    let widths = [Constraint::Length(name_width as u16), Constraint::Fill(1)];
+    let table = Table::new(rows, widths)
+        .column_spacing(2)
+        .block(Block::new().borders(Borders::TOP));
+    frame.render_widget(table, fields);
+
+    let lines: Vec<Line> = pane.iter().map(|line| Line::raw(line.as_str())).collect();
+    let block = Block::new()
+        .borders(Borders::TOP)
+        .title("Warnings (q quits)");
+    frame.render_widget(Paragraph::new(lines).block(block), warnings);
+}
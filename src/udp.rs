@@ -0,0 +1,119 @@
+use crate::output::{Output, Record};
+use clap::ValueEnum;
+use std::error::Error;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use tracing::debug;
+
+/// Settings for sending records as UDP datagrams
+#[derive(clap::Args)]
+#[command(about = None, long_about = None)]
+pub struct UdpArgs {
+    #[arg(
+        long = "udp-forward",
+        id = "udp_forward",
+        value_name = "HOST:PORT",
+        value_delimiter = ',',
+        value_parser = parse_destination
+    )]
+    /// Send each record as a datagram to every one of these receivers
+    pub destinations: Vec<SocketAddr>,
+    #[arg(
+        long = "udp-format",
+        id = "udp_format",
+        value_enum,
+        default_value_t = DatagramFormat::Json,
+        requires = "udp_forward"
+    )]
+    /// What each datagram holds
+    pub format: DatagramFormat,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum DatagramFormat {
+    /// The record as a JSON object
+    Json,
+    /// The payload as decoded from the packet, before it's parsed
+    Payload,
+}
+
+/// Looks the host up once at startup, so a slow resolver can't hold up sending later
+fn parse_destination(value: &str) -> Result<SocketAddr, String> {
+    value
+        .to_socket_addrs()
+        .map_err(|error| format!("`{value}` isn't a reachable HOST:PORT: {error}"))?
+        .next()
+        .ok_or_else(|| format!("`{value}` doesn't resolve to an address"))
+}
+
+/// Sends datagrams without ever waiting, a receiver that isn't there just misses them
+pub struct Udp {
+    destinations: Vec<SocketAddr>,
+    format: DatagramFormat,
+    /// Sockets for IPv4 and IPv6 receivers, bound when there are any of the kind
+    v4: Option<UdpSocket>,
+    v6: Option<UdpSocket>,
+}
+
+impl Udp {
+    pub fn new(args: &UdpArgs) -> Option<Self> {
+        if args.destinations.is_empty() {
+            return None;
+        }
+        Some(Udp {
+            destinations: args.destinations.clone(),
+            format: args.format,
+            v4: None,
+            v6: None,
+        })
+    }
+}
+
+impl Output for Udp {
+    fn name(&self) -> String {
+        let destinations: Vec<String> = self
+            .destinations
+            .iter()
+            .map(|destination| format!("udp://{destination}"))
+            .collect();
+        destinations.join(", ")
+    }
+
+    fn prepare(&mut self) -> Result<(), String> {
+        let bind = |address: &str| {
+            UdpSocket::bind(address)
+                .and_then(|socket| socket.set_nonblocking(true).map(|_| socket))
+                .map_err(|error| format!("Failed to open a UDP socket: {error}"))
+        };
+        if self.destinations.iter().any(SocketAddr::is_ipv4) {
+            self.v4 = Some(bind("0.0.0.0:0")?);
+        }
+        if self.destinations.iter().any(SocketAddr::is_ipv6) {
+            self.v6 = Some(bind("[::]:0")?);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, record: &Record) -> Result<(), Box<dyn Error>> {
+        let json;
+        let datagram = match self.format {
+            DatagramFormat::Json => {
+                json = serde_json::to_vec(&record.to_json())?;
+                &json[..]
+            }
+            DatagramFormat::Payload => record.payload,
+        };
+        for destination in &self.destinations {
+            let socket = match destination {
+                SocketAddr::V4(_) => self.v4.as_ref(),
+                SocketAddr::V6(_) => self.v6.as_ref(),
+            };
+            let Some(socket) = socket else {
+                continue;
+            };
+            if let Err(error) = socket.send_to(datagram, destination) {
+                debug!("Failed to send the record to {destination}: {error}");
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+use crate::output::{Output, Record};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tracing::warn;
+use ureq::Agent;
+
+/// Longest wait between attempts to post to a failing server
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Longest a post may take before it's retried
+const POST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest the queued records are given to go out on the way out
+const DRAIN: Duration = Duration::from_secs(5);
+
+/// Settings for posting each record to a webhook
+#[derive(clap::Args)]
+#[command(about = None, long_about = None)]
+pub struct WebhookArgs {
+    #[arg(long = "post-url", id = "post_url", value_name = "URL")]
+    /// Endpoint to POST each record to as JSON
+    pub url: Option<String>,
+    #[arg(long = "post-header", id = "post_header", value_name = "NAME:VALUE", requires = "post_url", value_parser = parse_header)]
+    /// Header to send along, e.g. for an auth token, can be given more than once
+    pub headers: Vec<(String, String)>,
+    #[arg(long = "post-queue", id = "post_queue", value_name = "RECORDS", default_value_t = 1000, requires = "post_url", value_parser = clap::value_parser!(u64).range(1..))]
+    /// Records kept for retrying while the server fails, the oldest are dropped first
+    pub queue: u64,
+}
+
+fn parse_header(value: &str) -> Result<(String, String), String> {
+    let (name, header) = value
+        .split_once(':')
+        .ok_or_else(|| format!("`{value}` isn't NAME:VALUE"))?;
+    if name.trim().is_empty() {
+        return Err(format!("`{value}` doesn't name a header"));
+    }
+    Ok((name.trim().to_string(), header.trim().to_string()))
+}
+
+/// Records given to the output and those of them posted, for the summary
+static RECORDS: AtomicU64 = AtomicU64::new(0);
+static DELIVERED: AtomicU64 = AtomicU64::new(0);
+
+/// How many records were posted and how many weren't, once the output is finished
+pub fn counts() -> (u64, u64) {
+    let delivered = DELIVERED.load(Ordering::Relaxed);
+    (delivered, RECORDS.load(Ordering::Relaxed) - delivered)
+}
+
+/// Posts records from a background thread, retrying those the server fails on with 5xx
+pub struct Webhook {
+    /// The endpoint, without the credentials, for the logs
+    url: String,
+    sender: Option<SyncSender<Vec<u8>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Webhook {
+    pub fn new(args: &WebhookArgs) -> Option<Self> {
+        let url = args.url.clone()?;
+        let request = Request {
+            url: url.clone(),
+            headers: args.headers.clone(),
+        };
+        let queue = args.queue as usize;
+        let (sender, receiver) = mpsc::sync_channel(queue);
+        let thread = std::thread::spawn(move || post_records(request, receiver, queue));
+        Some(Webhook {
+            url: crate::config::redact(&url),
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Output for Webhook {
+    fn name(&self) -> String {
+        self.url.clone()
+    }
+
+    fn prepare(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn write(&mut self, record: &Record) -> Result<(), Box<dyn Error>> {
+        RECORDS.fetch_add(1, Ordering::Relaxed);
+        let body = serde_json::to_vec(&record.to_json())?;
+        let sender = self
+            .sender
+            .as_ref()
+            .ok_or("Webhook output already finished")?;
+        match sender.try_send(body) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err("Webhook queue is full, dropped the record".into()),
+            Err(TrySendError::Disconnected(_)) => Err("Webhook output stopped".into()),
+        }
+    }
+
+    /// Gives the queued records one last chance to go out, for no longer than `DRAIN` even
+    /// with a post under way, after which those left count as failed
+    fn finish(&mut self) {
+        drop(self.sender.take());
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        let deadline = Instant::now() + DRAIN;
+        while !thread.is_finished() {
+            if Instant::now() >= deadline {
+                warn!("Gave up waiting on {} to take the last records", self.url);
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let _ = thread.join();
+    }
+}
+
+struct Request {
+    url: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    fn post(&self, agent: &Agent, body: &[u8], timeout: Duration) -> Result<(), ureq::Error> {
+        let mut request = agent
+            .post(&self.url)
+            .config()
+            .timeout_global(Some(timeout))
+            .build()
+            .header("Content-Type", "application/json");
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        request.send(body)?;
+        Ok(())
+    }
+}
+
+/// Whether posting the record again might work
+fn retryable(error: &ureq::Error) -> bool {
+    !matches!(error, ureq::Error::StatusCode(status) if *status < 500)
+}
+
+/// Posts records as they come in, holding on to up to `capacity` of them while the server fails
+fn post_records(request: Request, receiver: Receiver<Vec<u8>>, capacity: usize) {
+    let agent = Agent::new_with_defaults();
+    let mut queue: VecDeque<Vec<u8>> = VecDeque::new();
+    let mut backoff = Duration::from_secs(1);
+    let mut retry = Instant::now();
+    let mut open = true;
+    // When the records still queued are given up on, once the output is finished
+    let mut drain = None;
+    while open || !queue.is_empty() {
+        let next = if queue.is_empty() {
+            receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else if open {
+            receiver.recv_timeout(retry.saturating_duration_since(Instant::now()))
+        } else {
+            Err(RecvTimeoutError::Timeout)
+        };
+        match next {
+            Ok(body) => {
+                queue.push_back(body);
+                queue.extend(receiver.try_iter());
+                let excess = queue.len().saturating_sub(capacity);
+                if excess > 0 {
+                    queue.drain(..excess);
+                    warn!("Webhook queue is full, dropped the {excess} oldest records");
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                open = false;
+                drain.get_or_insert_with(|| Instant::now() + DRAIN);
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+        }
+        if open && Instant::now() < retry {
+            continue;
+        }
+
+        while let Some(body) = queue.front() {
+            // Cut short by the drain, nothing posts past it
+            let timeout = match drain {
+                Some(drain) => drain.saturating_duration_since(Instant::now()),
+                None => POST_TIMEOUT,
+            };
+            if timeout.is_zero() {
+                warn!(
+                    "Ran out of time posting to the webhook, dropped {} records",
+                    queue.len()
+                );
+                return;
+            }
+            match request.post(&agent, body, timeout.min(POST_TIMEOUT)) {
+                Ok(()) => {
+                    DELIVERED.fetch_add(1, Ordering::Relaxed);
+                    backoff = Duration::from_secs(1);
+                }
+                Err(error) if !retryable(&error) => {
+                    warn!("Webhook rejected a record: {error}");
+                }
+                Err(error) if open => {
+                    warn!(
+                        "Failed to post to the webhook, retrying {} records in {} s: {error}",
+                        queue.len(),
+                        backoff.as_secs()
+                    );
+                    retry = Instant::now() + backoff;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    break;
+                }
+                Err(error) => {
+                    warn!(
+                        "Failed to post to the webhook, dropped {} records: {error}",
+                        queue.len()
+                    );
+                    return;
+                }
+            }
+            queue.pop_front();
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Packets on their way from the lines a module sends to the rows of a CSV file
+
+use charter::device::DeviceKind;
+use charter::parse::{self, Encoding, ExtraFields, GetDataError, ParseOptions};
+use charter::sink::{self, Csv, CsvOptions, Dialect, Durability, ReceivedAt, Record};
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::time::SystemTime;
+
+/// A file in a directory of its own, removed along with it at the end of the test
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(test: &str) -> Self {
+        let directory = std::env::temp_dir().join(format!("charter-{test}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        Scratch(directory)
+    }
+
+    fn file(&self) -> PathBuf {
+        self.0.join("data.csv")
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn options() -> CsvOptions {
+    CsvOptions {
+        dialect: Dialect::default(),
+        create: true,
+        overwrite: false,
+        compression: None,
+        durability: Durability::None,
+        rotation: None,
+        received_at: ReceivedAt::Off,
+        index: true,
+        header: true,
+        width_varies: false,
+    }
+}
+
+fn columns(names: &[&str]) -> Option<Vec<String>> {
+    Some(names.iter().map(|name| name.to_string()).collect())
+}
+
+/// Writes the packets among the lines as they'd come from an RN2483, skipping the rest
+fn write(csv: &mut Csv, lines: &[&str]) {
+    let device = DeviceKind::Rn2483.profile();
+    let options = ParseOptions {
+        fields: 2,
+        delimiter: None,
+        checksum: None,
+        allow_short: false,
+        extra_fields: ExtraFields::Error,
+        schema: None,
+    };
+    let columns = columns(&["temperature", "humidity"]).unwrap();
+    let resyncs = AtomicU64::new(0);
+    let mut payload = Vec::new();
+    let mut index = 0;
+    for line in lines {
+        payload.clear();
+        match parse::get_data(device, Encoding::Hex, line, &resyncs, &mut payload) {
+            Ok(()) => (),
+            Err(GetDataError::IrregularMessage(_)) => continue,
+            Err(error) => panic!("{error}"),
+        }
+        let text = std::str::from_utf8(&payload).unwrap();
+        let fields = parse::parse_data(text, &options).unwrap();
+        let values: Vec<&str> = fields.iter().map(Cow::as_ref).collect();
+        let record = Record {
+            index,
+            received: SystemTime::now(),
+            payload: &payload,
+            columns: &columns,
+            values: &values,
+        };
+        csv.write(&record).unwrap();
+        index += 1;
+    }
+    csv.flush().unwrap();
+}
+
+fn packet(payload: &str) -> String {
+    format!("radio_rx  {}", hex::encode(payload))
+}
+
+#[test]
+fn packets_become_rows_under_the_header() {
+    let scratch = Scratch::new("rows");
+    let mut csv = Csv::new(
+        scratch.file(),
+        columns(&["temperature", "humidity"]),
+        options(),
+    );
+    csv.prepare().unwrap();
+    let (first, second) = (packet("21.5 48.2"), packet("21.4 \"48,3\""));
+    write(&mut csv, &[&first, "ok", &second]);
+    csv.finish();
+
+    let written = std::fs::read_to_string(scratch.file()).unwrap();
+    assert_eq!(
+        written,
+        "index,temperature,humidity\n0,21.5,48.2\n1,21.4,\"48,3\"\n"
+    );
+}
+
+#[test]
+fn appending_checks_the_header() {
+    let scratch = Scratch::new("append");
+    let mut csv = Csv::new(
+        scratch.file(),
+        columns(&["temperature", "humidity"]),
+        options(),
+    );
+    csv.prepare().unwrap();
+    write(&mut csv, &[&packet("21.5 48.2")]);
+    csv.finish();
+
+    let mut again = Csv::new(
+        scratch.file(),
+        columns(&["temperature", "humidity"]),
+        options(),
+    );
+    again.prepare().unwrap();
+    assert_eq!(again.rows(), Some(1));
+
+    let mut other = Csv::new(scratch.file(), columns(&["pressure"]), options());
+    match other.prepare() {
+        Err(sink::Error::Header { found, .. }) => {
+            assert_eq!(found, ["index", "temperature", "humidity"])
+        }
+        result => panic!("appended to a file of other columns: {:?}", result.err()),
+    }
+}
+
+#[test]
+fn missing_file_needs_create() {
+    let scratch = Scratch::new("create");
+    let options = CsvOptions {
+        create: false,
+        ..options()
+    };
+    let mut csv = Csv::new(scratch.file(), columns(&["temperature"]), options);
+    match csv.prepare() {
+        Err(sink::Error::Open { error, .. }) => {
+            assert_eq!(error.kind(), std::io::ErrorKind::NotFound)
+        }
+        result => panic!("opened a file that isn't there: {:?}", result.err()),
+    }
+}